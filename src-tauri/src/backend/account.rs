@@ -1,28 +1,30 @@
-use crate::backend::db::get_connection;
+use crate::backend::db::Database;
+use crate::backend::error::{BackendError, BackendResult, Context};
+use crate::backend::money::Money;
+use crate::backend::{attachment, balance, currency, statement};
 use rusqlite::{params, Connection, Result};
+use tauri::State;
 
 #[derive(serde::Serialize)]
 pub struct Account {
     pub id: i64,
     pub name: String,
     pub account_type: String,
-    pub balance: f64,
+    // Opening balance plus every transaction, maintained by the balance module
+    pub balance: Money,
+    pub opening_balance: Money,
     pub currency: String,
     pub note: Option<String>,
     pub count_in_asset: bool,
-    pub credit_limit: Option<f64>,
-    pub owed: Option<f64>,
+    pub credit_limit: Option<Money>,
+    // What a credit account owes, the negated balance
+    pub owed: Option<Money>,
     pub billing_date: Option<String>,
     pub due_date: Option<String>,
-    pub avg_cost: Option<f64>,
-    pub quantity: Option<f64>,
-    pub total_cap: Option<f64>,
 }
 
 // Function to create the accounts table
-pub fn create_accounts_table() -> Result<()> {
-    let conn = get_connection();
-
+pub fn create_accounts_table(conn: &Connection) -> Result<()> {
     let create_table_sql = "
     CREATE TABLE IF NOT EXISTS accounts (
         id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -63,129 +65,143 @@ pub fn create_accounts_table() -> Result<()> {
 }
 
 #[tauri::command]
-pub fn read_accounts() -> Result<Vec<Account>, String> {
-    let conn = get_connection();
-
-    let mut stmt = match conn.prepare(
-        "SELECT a.id, a.name, a.type, a.balance, a.currency, a.note, a.count_in_asset,
-            c.credit_limit, c.owed, c.billing_date, c.due_date, 
-            i.avg_cost, i.quantity, i.total_cap
+pub fn read_accounts(db: State<'_, Database>) -> BackendResult<Vec<Account>> {
+    let conn = db.reader().context("Failed to open database connection")?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT a.id, a.name, a.type, a.balance, a.currency, a.note, a.count_in_asset,
+            c.credit_limit, CASE WHEN c.account_id IS NOT NULL THEN -a.balance END,
+            c.billing_date, c.due_date, cur.minor_unit, a.opening_balance
         FROM accounts a
-        LEFT JOIN credit_accounts c ON a.id = c.account_id
-        LEFT JOIN invest_accounts i ON a.id = i.account_id",
-    ) {
-        Ok(statement) => statement,
-        Err(e) => return Err(format!("Failed to prepare statement: {}", e)),
-    };
-
-    let account_iter = match stmt.query_map([], |row| {
-        Ok(Account {
-            id: row.get(0)?,
-            name: row.get(1)?,
-            account_type: row.get(2)?,
-            balance: row.get(3)?,
-            currency: row.get(4)?,
-            note: row.get(5)?,
-            count_in_asset: row.get(6)?,
-            credit_limit: row.get(7).ok(),
-            owed: row.get(8).ok(),
-            billing_date: row.get(9).ok(),
-            due_date: row.get(10).ok(),
-            avg_cost: row.get(11).ok(),
-            quantity: row.get(12).ok(),
-            total_cap: row.get(13).ok(),
+        JOIN currencies cur ON cur.code = a.currency
+        LEFT JOIN credit_accounts c ON a.id = c.account_id",
+        )
+        .context("Failed to prepare statement")?;
+
+    let account_iter = stmt
+        .query_map([], |row| {
+            let scale: u32 = row.get(11)?;
+            let money = |idx: usize| -> Result<Option<Money>> {
+                Ok(row
+                    .get::<_, Option<i64>>(idx)?
+                    .map(|minor| Money::from_minor(minor, scale)))
+            };
+
+            Ok(Account {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                account_type: row.get(2)?,
+                balance: Money::from_minor(row.get(3)?, scale),
+                opening_balance: Money::from_minor(row.get(12)?, scale),
+                currency: row.get(4)?,
+                note: row.get(5)?,
+                count_in_asset: row.get(6)?,
+                credit_limit: money(7)?,
+                owed: money(8)?,
+                billing_date: row.get(9)?,
+                due_date: row.get(10)?,
+            })
         })
-    }) {
-        Ok(iterator) => iterator,
-        Err(e) => return Err(format!("Failed to query accounts: {}", e)),
-    };
+        .context("Failed to query accounts")?;
 
     let mut accounts = Vec::new();
     for account in account_iter {
-        match account {
-            Ok(acc) => accounts.push(acc),
-            Err(e) => return Err(format!("Failed to parse account row: {}", e)),
-        }
+        accounts.push(account.context("Failed to parse account row")?);
     }
 
     Ok(accounts)
 }
 
 #[tauri::command(rename_all = "snake_case")]
+#[allow(clippy::too_many_arguments)]
 pub fn create_account(
+    db: State<'_, Database>,
     name: &str,
     account_type: &str,
-    balance: f64,
+    opening_balance: Money,
     currency: &str,
     note: Option<&str>,
-    credit_limit: Option<f64>,
-    owed: Option<f64>,
+    credit_limit: Option<Money>,
     billing_date: Option<&str>,
     due_date: Option<&str>,
-    avg_cost: Option<f64>,
-    quantity: Option<f64>,
-    total_cap: Option<f64>,
-) -> Result<(), String> {
-    let conn = get_connection();
+) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    let scale = currency_scale(&tx, currency)?;
+    let opening_balance =
+        to_currency_scale(Some(opening_balance), scale, "opening_balance")?.unwrap_or_default();
+    let credit_limit = to_currency_scale(credit_limit, scale, "credit_limit")?;
 
     if account_type == "credit" {
-        if let (Some(credit_limit), Some(owed), Some(billing_date), Some(due_date)) =
-            (credit_limit, owed, billing_date, due_date)
+        if let (Some(credit_limit), Some(billing_date), Some(due_date)) =
+            (credit_limit, billing_date, due_date)
         {
-            create_general_account(&conn, name, account_type, balance, currency, note)
-                .map_err(|err| format!("Failed to insert account: {}", err))?;
-
-            let account_id = conn.last_insert_rowid();
-
-            create_credit_account(
-                &conn,
-                account_id,
-                credit_limit,
-                owed,
-                billing_date,
-                due_date,
-            )
-            .map_err(|err| format!("Failed to insert credit account details: {}", err))?;
+            statement::statement_day(billing_date, "billing_date")?;
+            statement::statement_day(due_date, "due_date")?;
+            create_general_account(&tx, name, account_type, opening_balance, currency, note)
+                .context("Failed to insert account")?;
+
+            let account_id = tx.last_insert_rowid();
+
+            create_credit_account(&tx, account_id, credit_limit, billing_date, due_date)
+                .context("Failed to insert credit account details")?;
         } else {
-            return Err(
-                "Missing credit account details: credit_limit, owed, billing_date, or due_date"
-                    .to_string(),
-            );
+            return Err(BackendError::validation(
+                "credit_limit",
+                "Missing credit account details: credit_limit, billing_date, or due_date",
+            ));
         }
     } else if account_type == "invest" {
-        if let (Some(avg_cost), Some(quantity), Some(total_cap)) = (avg_cost, quantity, total_cap) {
-            create_general_account(&conn, name, account_type, balance, currency, note)
-                .map_err(|err| format!("Failed to insert account: {}", err))?;
+        // Positions are held in `holdings`, added once the account exists
+        create_general_account(&tx, name, account_type, opening_balance, currency, note)
+            .context("Failed to insert account")?;
 
-            let account_id = conn.last_insert_rowid();
+        let account_id = tx.last_insert_rowid();
 
-            insert_invest_account(&conn, account_id, avg_cost, quantity, total_cap)
-                .map_err(|err| format!("Failed to insert invest account: {}", err))?;
-        } else {
-            return Err(
-                "Missing invest account details: avg_cost, quantity, or total_cap".to_string(),
-            );
-        }
+        insert_invest_account(&tx, account_id).context("Failed to insert invest account")?;
     } else {
-        create_general_account(&conn, name, account_type, balance, currency, note)
-            .map_err(|err| format!("Failed to insert account: {}", err))?;
+        create_general_account(&tx, name, account_type, opening_balance, currency, note)
+            .context("Failed to insert account")?;
     }
 
+    tx.commit().context("Failed to commit account")?;
     Ok(())
 }
 
+fn currency_scale(conn: &Connection, currency: &str) -> BackendResult<u32> {
+    currency::minor_unit(conn, currency)
+        .context(&format!("Failed to look up currency {}", currency))
+        .map_err(|err| err.with_field("currency"))
+}
+
+// Amounts arrive with whatever precision the user typed; store them in the currency's minor units
+fn to_currency_scale(
+    amount: Option<Money>,
+    scale: u32,
+    field: &str,
+) -> BackendResult<Option<Money>> {
+    amount
+        .map(|amount| amount.to_scale(scale))
+        .transpose()
+        .map_err(|err| BackendError::from(err).with_field(field))
+}
+
 // Insert the account into the general accounts table
 fn create_general_account(
     conn: &Connection,
     name: &str,
     acc_type: &str,
-    balance: f64,
+    opening_balance: Money,
     currency: &str,
     note: Option<&str>,
 ) -> Result<()> {
+    // A new account has no transactions, so it starts at its opening balance
     conn.execute(
-        "INSERT INTO accounts (name, type, balance, currency, note) VALUES (?1, ?2, ?3, ?4, ?5)",
-        params![name, acc_type, balance, currency, note],
+        "INSERT INTO accounts (name, type, opening_balance, balance, currency, note)
+         VALUES (?1, ?2, ?3, ?3, ?4, ?5)",
+        params![name, acc_type, opening_balance.minor(), currency, note],
     )?;
     Ok(())
 }
@@ -194,115 +210,170 @@ fn create_general_account(
 fn create_credit_account(
     conn: &Connection,
     account_id: i64,
-    credit_limit: f64,
-    owed: f64,
+    credit_limit: Money,
     billing_date: &str,
     due_date: &str,
 ) -> Result<()> {
     conn.execute(
-        "INSERT INTO credit_accounts (account_id, credit_limit, owed, billing_date, due_date) VALUES (?1, ?2, ?3, ?4, ?5)",
-        params![account_id, credit_limit, owed, billing_date, due_date],
+        "INSERT INTO credit_accounts (account_id, credit_limit, billing_date, due_date) VALUES (?1, ?2, ?3, ?4)",
+        params![account_id, credit_limit.minor(), billing_date, due_date],
     )?;
     Ok(())
 }
 
-fn insert_invest_account(
-    conn: &Connection,
-    account_id: i64,
-    avg_cost: f64,
-    quantity: f64,
-    total_cap: f64,
-) -> Result<()> {
+fn insert_invest_account(conn: &Connection, account_id: i64) -> Result<()> {
     conn.execute(
-        "INSERT INTO invest_accounts (account_id, avg_cost, quantity, total_cap) VALUES (?1, ?2, ?3, ?4)",
-        params![account_id, avg_cost, quantity, total_cap],
+        "INSERT INTO invest_accounts (account_id) VALUES (?1)",
+        params![account_id],
     )?;
     Ok(())
 }
 
 #[tauri::command(rename_all = "snake_case")]
-pub fn delete_account(account_id: i64) -> Result<(), String> {
-    let conn = get_connection();
+pub fn delete_account(db: State<'_, Database>, account_id: i64) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    // Transfers to or from this account disappear with it, so the accounts on their other side
+    // need their balances rebuilt afterwards
+    let counterparts = {
+        let mut stmt = tx
+            .prepare(
+                "SELECT to_account_id FROM transactions
+                 WHERE account_id = ?1 AND to_account_id IS NOT NULL
+                 UNION
+                 SELECT account_id FROM transactions WHERE to_account_id = ?1",
+            )
+            .context("Failed to prepare statement")?;
+        let rows = stmt
+            .query_map(params![account_id], |row| row.get::<_, i64>(0))
+            .context("Failed to query transfers")?
+            .collect::<Result<Vec<_>>>()
+            .context("Failed to parse transfer row")?;
+        rows
+    };
 
-    if let Err(e) = conn.execute(
-        "DELETE FROM credit_accounts WHERE account_id = ?1",
-        params![account_id],
-    ) {
-        return Err(format!("Failed to delete from credit accounts: {}", e));
+    // credit_accounts, invest_accounts and transactions rows cascade with the account
+    let deleted = tx
+        .execute("DELETE FROM accounts WHERE id = ?1", params![account_id])
+        .context("Failed to delete account")?;
+    if deleted == 0 {
+        return Err(BackendError::not_found(format!(
+            "Account {} not found",
+            account_id
+        )));
     }
 
-    match conn.execute("DELETE FROM accounts WHERE id = ?1", params![account_id]) {
-        Ok(_) => Ok(()),
-        Err(e) => Err(format!("Failed to delete account: {}", e)),
+    for counterpart in counterparts {
+        balance::recompute_balance(&tx, counterpart)?;
     }
+    let orphans = attachment::take_orphans(&tx)?;
+
+    tx.commit().context("Failed to commit account deletion")?;
+    attachment::remove_files(&db, &orphans)?;
+    Ok(())
 }
 
 #[tauri::command(rename_all = "snake_case")]
+#[allow(clippy::too_many_arguments)]
 pub fn update_account(
+    db: State<'_, Database>,
     account_id: i64,
     name: &str,
     account_type: &str,
-    balance: f64,
+    opening_balance: Money,
     currency: &str,
     note: Option<&str>,
-    credit_limit: Option<f64>, // Optional fields for credit accounts
-    owed: Option<f64>,
+    credit_limit: Option<Money>, // Optional fields for credit accounts
     billing_date: Option<&str>,
     due_date: Option<&str>,
-    avg_cost: Option<f64>, // Optional fields for invest accounts
-    quantity: Option<f64>,
-    total_cap: Option<f64>,
-) -> Result<(), String> {
-    let conn = get_connection();
+) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    let scale = currency_scale(&tx, currency)?;
+    let opening_balance =
+        to_currency_scale(Some(opening_balance), scale, "opening_balance")?.unwrap_or_default();
+    let credit_limit = to_currency_scale(credit_limit, scale, "credit_limit")?;
+
+    ensure_currency_change_allowed(&tx, account_id, currency)?;
 
     // First, update the general accounts table for all types of accounts
-    update_general_account(
-        &conn,
+    let updated = update_general_account(
+        &tx,
         account_id,
         name,
         account_type,
-        balance,
+        opening_balance,
         currency,
         note,
     )
-    .map_err(|err| format!("Failed to update account: {}", err))?;
+    .context("Failed to update account")?;
+    if updated == 0 {
+        return Err(BackendError::not_found(format!(
+            "Account {} not found",
+            account_id
+        )));
+    }
+    balance::recompute_balance(&tx, account_id)?;
 
     // Update credit-specific fields if it's a credit account
     if account_type == "credit" {
-        if let (Some(credit_limit), Some(owed), Some(billing_date), Some(due_date)) =
-            (credit_limit, owed, billing_date, due_date)
+        if let (Some(credit_limit), Some(billing_date), Some(due_date)) =
+            (credit_limit, billing_date, due_date)
         {
-            update_credit_account(
-                &conn,
-                account_id,
-                credit_limit,
-                owed,
-                billing_date,
-                due_date,
-            )
-            .map_err(|err| format!("Failed to update credit account details: {}", err))?;
+            statement::statement_day(billing_date, "billing_date")?;
+            statement::statement_day(due_date, "due_date")?;
+            update_credit_account(&tx, account_id, credit_limit, billing_date, due_date)
+                .context("Failed to update credit account details")?;
         } else {
-            return Err(
-                "Missing credit account details: credit_limit, owed, billing_date, or due_date"
-                    .to_string(),
-            );
+            return Err(BackendError::validation(
+                "credit_limit",
+                "Missing credit account details: credit_limit, billing_date, or due_date",
+            ));
         }
     }
 
-    // Update invest-specific fields if it's an invest account
+    // An account becoming an invest account gets its invest_accounts row; holdings are managed
+    // separately
     if account_type == "invest" {
-        if let (Some(avg_cost), Some(quantity), Some(total_cap)) = (avg_cost, quantity, total_cap) {
-            update_invest_account(&conn, account_id, avg_cost, quantity, total_cap)
-                .map_err(|err| format!("Failed to update invest account details: {}", err))?;
-        } else {
-            return Err(
-                "Missing invest account details: avg_cost, quantity, or total_cap".to_string(),
-            );
-        }
+        tx.execute(
+            "INSERT OR IGNORE INTO invest_accounts (account_id) VALUES (?1)",
+            params![account_id],
+        )
+        .context("Failed to update invest account details")?;
     }
 
     // For debit and member accounts, no additional table updates are needed
 
+    tx.commit().context("Failed to commit account")?;
+    Ok(())
+}
+
+// Transactions are stored in the account's currency, so it can only change while there are none,
+// including transfers into it and investment trades
+fn ensure_currency_change_allowed(
+    conn: &Connection,
+    account_id: i64,
+    currency: &str,
+) -> BackendResult<()> {
+    let in_use: bool = conn
+        .query_row(
+            "SELECT EXISTS (
+                SELECT 1 FROM accounts a
+                JOIN balance_effects e ON e.account_id = a.id
+                WHERE a.id = ?1 AND a.currency != ?2
+            )",
+            params![account_id, currency],
+            |row| row.get(0),
+        )
+        .context("Failed to check account transactions")?;
+    if in_use {
+        return Err(BackendError::validation(
+            "currency",
+            "Cannot change the currency of an account that has transactions",
+        ));
+    }
     Ok(())
 }
 
@@ -312,44 +383,27 @@ fn update_general_account(
     account_id: i64,
     name: &str,
     acc_type: &str,
-    balance: f64,
+    opening_balance: Money,
     currency: &str,
     note: Option<&str>,
-) -> Result<()> {
+) -> Result<usize> {
     conn.execute(
-        "UPDATE accounts SET name = ?1, type = ?2, balance = ?3, currency = ?4, note = ?5 WHERE id = ?6",
-        params![name, acc_type, balance, currency, note, account_id],
-    )?;
-    Ok(())
+        "UPDATE accounts SET name = ?1, type = ?2, opening_balance = ?3, currency = ?4, note = ?5 WHERE id = ?6",
+        params![name, acc_type, opening_balance.minor(), currency, note, account_id],
+    )
 }
 
 // Update the credit-specific fields in the credit_accounts table
 fn update_credit_account(
     conn: &Connection,
     account_id: i64,
-    credit_limit: f64,
-    owed: f64,
+    credit_limit: Money,
     billing_date: &str,
     due_date: &str,
 ) -> Result<()> {
     conn.execute(
-        "UPDATE credit_accounts SET credit_limit = ?1, owed = ?2, billing_date = ?3, due_date = ?4 WHERE account_id = ?5",
-        params![credit_limit, owed, billing_date, due_date, account_id],
-    )?;
-    Ok(())
-}
-
-// Update the invest-specific fields in the invest_accounts table
-fn update_invest_account(
-    conn: &Connection,
-    account_id: i64,
-    avg_cost: f64,
-    quantity: f64,
-    total_cap: f64,
-) -> Result<()> {
-    conn.execute(
-        "UPDATE invest_accounts SET avg_cost = ?1, quantity = ?2, total_cap = ?3 WHERE account_id = ?4",
-        params![avg_cost, quantity, total_cap, account_id],
+        "UPDATE credit_accounts SET credit_limit = ?1, billing_date = ?2, due_date = ?3 WHERE account_id = ?4",
+        params![credit_limit.minor(), billing_date, due_date, account_id],
     )?;
     Ok(())
 }
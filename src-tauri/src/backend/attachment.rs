@@ -0,0 +1,474 @@
+use crate::backend::db::Database;
+use crate::backend::error::{BackendError, BackendResult, Context};
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use tauri::State;
+
+// Kept next to the database file, so a database moved elsewhere keeps its attachments
+const ATTACHMENTS_DIR: &str = "attachments";
+// Where a file is copied while it is hashed, before it moves to its content address
+const INCOMING_FILE: &str = "incoming";
+
+// Attachment files are named by the SHA-256 of their content, under a directory named after the
+// hash's first two characters, so the same receipt attached twice is stored once. `attachments`
+// holds one row per stored file and `transaction_attachments` links them to transactions under
+// the name they were added with. A file no transaction links to is deleted along with its row.
+
+#[derive(serde::Serialize)]
+pub struct Attachment {
+    pub id: i64,
+    pub transaction_id: i64,
+    // Name of the file as it was added
+    pub file_name: String,
+    pub mime_type: String,
+    pub size: i64,
+    pub hash: String,
+    // Absolute path of the stored copy, for opening it
+    pub path: String,
+}
+
+pub fn create_attachments_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS attachments (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            hash TEXT NOT NULL UNIQUE,
+            mime_type TEXT NOT NULL,
+            size INTEGER NOT NULL CHECK( size >= 0 ),
+            created_at TEXT DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE TABLE IF NOT EXISTS transaction_attachments (
+            transaction_id INTEGER NOT NULL,
+            attachment_id INTEGER NOT NULL,
+            file_name TEXT NOT NULL,
+            added_at TEXT DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (transaction_id, attachment_id),
+            FOREIGN KEY (transaction_id) REFERENCES transactions(id) ON DELETE CASCADE,
+            FOREIGN KEY (attachment_id) REFERENCES attachments(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS idx_transaction_attachments_attachment
+            ON transaction_attachments (attachment_id);
+        ",
+    )
+}
+
+// Copy the file at `path` into the attachments directory and attach it to the transaction
+#[tauri::command(rename_all = "snake_case")]
+pub fn add_attachment(
+    db: State<'_, Database>,
+    transaction_id: i64,
+    path: &str,
+) -> BackendResult<Attachment> {
+    let source = Path::new(path);
+    let file_name = source
+        .file_name()
+        .and_then(|name| name.to_str())
+        .filter(|_| source.is_file())
+        .ok_or_else(|| BackendError::validation("path", format!("'{}' is not a file", path)))?
+        .to_string();
+
+    // Held until the file is in place, so a collection cannot delete it in between
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    let exists: bool = tx
+        .query_row(
+            "SELECT EXISTS(SELECT 1 FROM transactions WHERE id = ?1)",
+            params![transaction_id],
+            |row| row.get(0),
+        )
+        .context("Failed to look up transaction")?;
+    if !exists {
+        return Err(
+            BackendError::not_found(format!("Transaction {} not found", transaction_id))
+                .with_field("transaction_id"),
+        );
+    }
+
+    let dir = attachments_dir(&db);
+    let (hash, size) = store_file(&dir, source)?;
+    let mime_type = mime_type(&file_name);
+
+    let attachment_id = match tx
+        .query_row(
+            "SELECT id FROM attachments WHERE hash = ?1",
+            params![hash],
+            |row| row.get::<_, i64>(0),
+        )
+        .optional()
+        .context("Failed to look up attachment")?
+    {
+        Some(id) => id,
+        None => {
+            tx.execute(
+                "INSERT INTO attachments (hash, mime_type, size) VALUES (?1, ?2, ?3)",
+                params![hash, mime_type, size],
+            )
+            .context("Failed to insert attachment")?;
+            tx.last_insert_rowid()
+        }
+    };
+
+    tx.execute(
+        "INSERT INTO transaction_attachments (transaction_id, attachment_id, file_name)
+         VALUES (?1, ?2, ?3)",
+        params![transaction_id, attachment_id, file_name],
+    )
+    .context("Failed to attach file")?;
+
+    let attachment = load_attachment(&tx, &dir, transaction_id, attachment_id)?;
+    tx.commit().context("Failed to commit attachment")?;
+    Ok(attachment)
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn get_attachments(
+    db: State<'_, Database>,
+    transaction_id: i64,
+) -> BackendResult<Vec<Attachment>> {
+    let conn = db.reader().context("Failed to open database connection")?;
+    let dir = attachments_dir(&db);
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT ta.transaction_id, a.id, ta.file_name, a.mime_type, a.size, a.hash
+             FROM transaction_attachments ta
+             JOIN attachments a ON a.id = ta.attachment_id
+             WHERE ta.transaction_id = ?1
+             ORDER BY ta.added_at, a.id",
+        )
+        .context("Failed to prepare statement")?;
+    let attachments = stmt
+        .query_map(params![transaction_id], |row| {
+            attachment_from_row(row, &dir)
+        })
+        .context("Failed to query attachments")?
+        .collect::<Result<Vec<_>>>()
+        .context("Failed to parse attachment row")?;
+
+    Ok(attachments)
+}
+
+// Detach the file from the transaction, deleting it once nothing else links to it
+#[tauri::command(rename_all = "snake_case")]
+pub fn remove_attachment(
+    db: State<'_, Database>,
+    transaction_id: i64,
+    attachment_id: i64,
+) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    let removed = tx
+        .execute(
+            "DELETE FROM transaction_attachments
+             WHERE transaction_id = ?1 AND attachment_id = ?2",
+            params![transaction_id, attachment_id],
+        )
+        .context("Failed to remove attachment")?;
+    if removed == 0 {
+        return Err(BackendError::not_found(format!(
+            "Attachment {} not found on transaction {}",
+            attachment_id, transaction_id
+        )));
+    }
+    let orphans = take_orphans(&tx)?;
+
+    tx.commit().context("Failed to commit attachment removal")?;
+    remove_files(&db, &orphans)?;
+    Ok(())
+}
+
+// Delete the rows of attachments no transaction links to any more, after the transactions that
+// linked them were deleted, and return their hashes. Call on the writer connection and pass the
+// result to `remove_files` once committed, while still holding the writer.
+pub(crate) fn take_orphans(conn: &Connection) -> BackendResult<Vec<String>> {
+    let orphans = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT hash FROM attachments a
+                 WHERE NOT EXISTS (
+                     SELECT 1 FROM transaction_attachments ta WHERE ta.attachment_id = a.id
+                 )",
+            )
+            .context("Failed to prepare statement")?;
+        let rows = stmt
+            .query_map([], |row| row.get::<_, String>(0))
+            .context("Failed to query orphaned attachments")?
+            .collect::<Result<Vec<_>>>()
+            .context("Failed to parse attachment row")?;
+        rows
+    };
+    if !orphans.is_empty() {
+        conn.execute(
+            "DELETE FROM attachments WHERE NOT EXISTS (
+                 SELECT 1 FROM transaction_attachments ta WHERE ta.attachment_id = attachments.id
+             )",
+            [],
+        )
+        .context("Failed to delete orphaned attachments")?;
+    }
+
+    Ok(orphans)
+}
+
+// The rows are already gone, so every file is tried; one that cannot be deleted is reported and
+// left to `collect_garbage`
+pub(crate) fn remove_files(db: &Database, hashes: &[String]) -> BackendResult<()> {
+    let dir = attachments_dir(db);
+    let mut result = Ok(());
+    for hash in hashes {
+        match fs::remove_file(stored_path(&dir, hash)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound && result.is_ok() => {
+                result = Err(err).context("Failed to delete attachment");
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+// Run at startup: delete attachments orphaned by transactions that went with their account or
+// ledger, along with any file that has no row, such as a copy interrupted by a crash. Returns the
+// number of files deleted.
+pub fn collect_garbage(db: &Database) -> BackendResult<usize> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+    let orphans = take_orphans(&tx)?;
+    tx.commit().context("Failed to commit attachment cleanup")?;
+    remove_files(db, &orphans)?;
+
+    let dir = attachments_dir(db);
+    if !dir.is_dir() {
+        return Ok(orphans.len());
+    }
+    let known = {
+        let mut stmt = conn
+            .prepare("SELECT hash FROM attachments")
+            .context("Failed to prepare statement")?;
+        let rows = stmt
+            .query_map([], |row| row.get::<_, String>(0))
+            .context("Failed to query attachments")?
+            .collect::<Result<HashSet<_>>>()
+            .context("Failed to parse attachment row")?;
+        rows
+    };
+
+    let mut removed = orphans.len();
+    for entry in fs::read_dir(&dir).context("Failed to read attachments directory")? {
+        let path = entry
+            .context("Failed to read attachments directory")?
+            .path();
+        if !path.is_dir() {
+            // Only the incoming copy lives at the top level
+            fs::remove_file(&path).context("Failed to delete attachment")?;
+            removed += 1;
+            continue;
+        }
+        for file in fs::read_dir(&path).context("Failed to read attachments directory")? {
+            let file = file.context("Failed to read attachments directory")?.path();
+            let name = file.file_name().and_then(|name| name.to_str());
+            if name.is_some_and(|hash| !known.contains(hash)) {
+                fs::remove_file(&file).context("Failed to delete attachment")?;
+                removed += 1;
+            }
+        }
+    }
+
+    Ok(removed)
+}
+
+fn attachments_dir(db: &Database) -> PathBuf {
+    db.path()
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(ATTACHMENTS_DIR)
+}
+
+fn stored_path(dir: &Path, hash: &str) -> PathBuf {
+    dir.join(&hash[..2]).join(hash)
+}
+
+// Copy `source` into `dir` under its content address, hashing it on the way. Returns the hash
+// and size; a file already stored is left as it is.
+fn store_file(dir: &Path, source: &Path) -> BackendResult<(String, i64)> {
+    fs::create_dir_all(dir).context("Failed to create attachments directory")?;
+    let incoming = dir.join(INCOMING_FILE);
+
+    let mut hasher = Sha256::new();
+    let size = {
+        let mut reader = File::open(source).context("Failed to open file")?;
+        let mut writer = Tee {
+            file: File::create(&incoming).context("Failed to copy file")?,
+            hasher: &mut hasher,
+        };
+        let size = io::copy(&mut reader, &mut writer).context("Failed to copy file")?;
+        writer.file.sync_all().context("Failed to copy file")?;
+        size
+    };
+    let hash: String = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    let path = stored_path(dir, &hash);
+    if path.exists() {
+        fs::remove_file(&incoming).context("Failed to clean up copied file")?;
+    } else {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Failed to create attachments directory")?;
+        }
+        fs::rename(&incoming, &path).context("Failed to store file")?;
+    }
+
+    Ok((hash, size as i64))
+}
+
+// Writes to the copy and the hash at once
+struct Tee<'a> {
+    file: File,
+    hasher: &'a mut Sha256,
+}
+
+impl io::Write for Tee<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// By extension; anything unrecognised is stored as a generic binary
+fn mime_type(file_name: &str) -> &'static str {
+    let extension = Path::new(file_name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("pdf") => "application/pdf",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("heic") => "image/heic",
+        Some("tif" | "tiff") => "image/tiff",
+        Some("txt") => "text/plain",
+        Some("csv") => "text/csv",
+        Some("html" | "htm") => "text/html",
+        Some("eml") => "message/rfc822",
+        _ => "application/octet-stream",
+    }
+}
+
+fn load_attachment(
+    conn: &Connection,
+    dir: &Path,
+    transaction_id: i64,
+    attachment_id: i64,
+) -> BackendResult<Attachment> {
+    conn.query_row(
+        "SELECT ta.transaction_id, a.id, ta.file_name, a.mime_type, a.size, a.hash
+         FROM transaction_attachments ta
+         JOIN attachments a ON a.id = ta.attachment_id
+         WHERE ta.transaction_id = ?1 AND ta.attachment_id = ?2",
+        params![transaction_id, attachment_id],
+        |row| attachment_from_row(row, dir),
+    )
+    .context("Failed to look up attachment")
+}
+
+fn attachment_from_row(row: &rusqlite::Row, dir: &Path) -> Result<Attachment> {
+    let hash: String = row.get(5)?;
+    Ok(Attachment {
+        transaction_id: row.get(0)?,
+        id: row.get(1)?,
+        file_name: row.get(2)?,
+        mime_type: row.get(3)?,
+        size: row.get(4)?,
+        path: stored_path(dir, &hash).display().to_string(),
+        hash,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::account::delete_account;
+    use crate::backend::testing::{money, Fixture};
+    use crate::backend::transaction::create_transaction;
+
+    fn expense(fixture: &Fixture, ledger: i64, account: i64) -> i64 {
+        create_transaction(
+            fixture.db(),
+            ledger,
+            account,
+            "expense",
+            None,
+            money("1"),
+            "USD",
+            "2024-05-01",
+            vec![],
+            None,
+        )
+        .unwrap()
+    }
+
+    // A file with `contents` outside the attachments directory
+    fn source(fixture: &Fixture, name: &str, contents: &str) -> String {
+        let path = fixture.dir().join(name);
+        fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn a_shared_file_is_kept_until_its_last_transaction_goes() {
+        let fixture = Fixture::new();
+        let ledger = fixture.ledger("USD");
+        let bank = fixture.account("bank", "debit", "USD", "0");
+        let wallet = fixture.account("wallet", "debit", "USD", "0");
+        let first = expense(&fixture, ledger, bank);
+        let second = expense(&fixture, ledger, wallet);
+
+        let receipt =
+            add_attachment(fixture.db(), first, &source(&fixture, "a.pdf", "same")).unwrap();
+        let copy =
+            add_attachment(fixture.db(), second, &source(&fixture, "b.png", "same")).unwrap();
+        assert_eq!((receipt.id, copy.file_name.as_str()), (copy.id, "b.png"));
+        assert_eq!(fs::read_to_string(&receipt.path).unwrap(), "same");
+
+        remove_attachment(fixture.db(), first, receipt.id).unwrap();
+        assert!(Path::new(&receipt.path).exists());
+        // Deleting the account takes its transactions, and with them the last link
+        delete_account(fixture.db(), wallet).unwrap();
+        assert!(!Path::new(&receipt.path).exists());
+        let rows: i64 = fixture.query("SELECT COUNT(*) FROM attachments", []);
+        assert_eq!(rows, 0);
+    }
+
+    #[test]
+    fn every_file_is_tried_before_a_failed_removal_is_reported() {
+        let fixture = Fixture::new();
+        let dir = attachments_dir(&fixture.db());
+        let hashes = ["aa11".to_string(), "bb22".to_string(), "cc33".to_string()];
+        for hash in &hashes[1..] {
+            fs::create_dir_all(stored_path(&dir, hash).parent().unwrap()).unwrap();
+        }
+        // A directory where a file should be cannot be removed as one; the first file is gone already
+        fs::create_dir_all(stored_path(&dir, &hashes[1])).unwrap();
+        fs::write(stored_path(&dir, &hashes[2]), "x").unwrap();
+
+        assert!(remove_files(&fixture.db(), &hashes).is_err());
+        assert!(!stored_path(&dir, &hashes[2]).exists());
+        assert!(remove_files(&fixture.db(), &hashes[2..]).is_ok());
+    }
+}
@@ -0,0 +1,144 @@
+use crate::backend::db::Database;
+use crate::backend::error::{BackendError, BackendResult, Context};
+use crate::backend::money::Money;
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use tauri::State;
+
+// An account's balance is its opening balance plus the `delta` of every row in the
+// `balance_effects` view, which turns each transaction and investment trade into signed changes
+// per account on its `day`. Rows carry either a `transaction_id` or a `trade_id`. Writes
+// keep `accounts.balance` in step incrementally; `recompute_account_balance` rebuilds it from
+// scratch, so both paths share the one definition below. The view holds no data, so it is
+// recreated from this definition after every migration run.
+//
+// A transfer takes its amount plus fee out of the source account in the source currency and puts
+// `to_amount` into the destination account in the destination currency. A reinvested dividend
+// is income spent on units at once, so it leaves cash where it was (see `invest_event`). A buy
+// takes its `amount` out of the invest account and a sale puts it in; trades without an amount
+// leave cash alone (see `invest`).
+pub fn create_balance_effects_view(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        DROP VIEW IF EXISTS balance_effects;
+        CREATE VIEW balance_effects AS
+            SELECT id AS transaction_id,
+                   NULL AS trade_id,
+                   account_id,
+                   substr(date, 1, 10) AS day,
+                   CASE
+                       WHEN id IN (SELECT transaction_id FROM invest_events
+                                   WHERE kind = 'reinvestment') THEN 0
+                       WHEN type = 'income' THEN amount
+                       WHEN type = 'transfer' THEN -(amount + fee)
+                       ELSE -amount
+                   END AS delta
+            FROM transactions
+            UNION ALL
+            SELECT id, NULL, to_account_id, substr(date, 1, 10), to_amount
+            FROM transactions
+            WHERE type = 'transfer' AND to_account_id IS NOT NULL
+            UNION ALL
+            SELECT NULL, id, account_id, date,
+                   CASE WHEN side = 'buy' THEN -amount ELSE amount END
+            FROM invest_trades
+            WHERE amount IS NOT NULL;
+        ",
+    )
+}
+
+#[derive(serde::Serialize)]
+pub struct BalanceRecomputation {
+    pub account_id: i64,
+    pub stored: Money,
+    pub computed: Money,
+    // computed - stored; zero when the stored balance was already correct
+    pub drift: Money,
+}
+
+// Add (sign = 1) or remove (sign = -1) a transaction's effects on every account it touches.
+// Call with -1 before changing or deleting a transaction and with 1 after inserting or changing it.
+pub(crate) fn apply_transaction_effects(
+    conn: &Connection,
+    transaction_id: i64,
+    sign: i64,
+) -> Result<()> {
+    apply_effects(conn, "transaction_id", transaction_id, sign)
+}
+
+// The same for an investment trade
+pub(crate) fn apply_trade_effects(conn: &Connection, trade_id: i64, sign: i64) -> Result<()> {
+    apply_effects(conn, "trade_id", trade_id, sign)
+}
+
+// `key` is the `balance_effects` column `id` is matched against
+fn apply_effects(conn: &Connection, key: &str, id: i64, sign: i64) -> Result<()> {
+    conn.execute(
+        &format!(
+            "UPDATE accounts
+             SET balance = balance + ?2 * (
+                 SELECT SUM(e.delta) FROM balance_effects e
+                 WHERE e.{key} = ?1 AND e.account_id = accounts.id
+             )
+             WHERE id IN (SELECT account_id FROM balance_effects WHERE {key} = ?1)"
+        ),
+        params![id, sign],
+    )?;
+    Ok(())
+}
+
+// Opening balance plus the full transaction history, in minor units
+pub(crate) fn computed_balance(conn: &Connection, account_id: i64) -> Result<i64> {
+    conn.query_row(
+        "SELECT a.opening_balance
+             + COALESCE((SELECT SUM(e.delta) FROM balance_effects e WHERE e.account_id = a.id), 0)
+         FROM accounts a WHERE a.id = ?1",
+        params![account_id],
+        |row| row.get(0),
+    )
+}
+
+// Rebuild the stored balance from history and write it back
+pub(crate) fn recompute_balance(
+    conn: &Connection,
+    account_id: i64,
+) -> BackendResult<BalanceRecomputation> {
+    let (stored, scale): (i64, u32) = conn
+        .query_row(
+            "SELECT a.balance, cur.minor_unit FROM accounts a
+             JOIN currencies cur ON cur.code = a.currency
+             WHERE a.id = ?1",
+            params![account_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .context("Failed to read account balance")?
+        .ok_or_else(|| BackendError::not_found(format!("Account {} not found", account_id)))?;
+
+    let computed = computed_balance(conn, account_id).context("Failed to compute balance")?;
+    if computed != stored {
+        conn.execute(
+            "UPDATE accounts SET balance = ?1 WHERE id = ?2",
+            params![computed, account_id],
+        )
+        .context("Failed to store recomputed balance")?;
+    }
+
+    Ok(BalanceRecomputation {
+        account_id,
+        stored: Money::from_minor(stored, scale),
+        computed: Money::from_minor(computed, scale),
+        drift: Money::from_minor(computed - stored, scale),
+    })
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn recompute_account_balance(
+    db: State<'_, Database>,
+    account_id: i64,
+) -> BackendResult<BalanceRecomputation> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+    let result = recompute_balance(&tx, account_id)?;
+    tx.commit().context("Failed to commit balance")?;
+    Ok(result)
+}
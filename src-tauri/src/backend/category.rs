@@ -1,20 +1,46 @@
-use crate::backend::db::get_connection;
-use rusqlite::{params, Result};
+use crate::backend::date::is_iso_date;
+use crate::backend::db::Database;
+use crate::backend::error::{BackendError, BackendResult, Context};
+use crate::backend::money::Money;
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use std::collections::HashMap;
+use tauri::State;
 
+// Categories form a tree per ledger and type through `parent_id`. A node's children are
+// listed in `sort_order`; every node in a tree has the type of its root.
 #[derive(serde::Serialize)]
 pub struct Category {
     pub id: i64,
     pub ledger_id: i64,
+    pub parent_id: Option<i64>,
     pub name: String,
     pub icon: Option<String>,
     pub color: Option<String>,
     pub category_type: String,
-    pub subcategories: Vec<String>,
+    pub sort_order: i64,
+    pub children: Vec<Category>,
 }
 
-pub fn create_categories_table() -> Result<()> {
-    let conn = get_connection();
+// Income or expenses in one category and currency. Split transactions count once per line.
+#[derive(serde::Serialize)]
+pub struct CategoryTotal {
+    pub category_id: Option<i64>,
+    pub category_name: Option<String>,
+    pub transaction_type: String,
+    pub currency: String,
+    pub amount: Money,
+    // Split lines plus transactions that are not split
+    pub count: i64,
+}
+
+// The columns of a category row that decide where it may sit in the tree
+struct Node {
+    ledger_id: i64,
+    parent_id: Option<i64>,
+    category_type: String,
+}
 
+pub fn create_categories_table(conn: &Connection) -> Result<()> {
     let create_table_sql = "
     CREATE TABLE IF NOT EXISTS categories (
         id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -34,108 +60,612 @@ pub fn create_categories_table() -> Result<()> {
 
 #[tauri::command(rename_all = "snake_case")]
 pub fn insert_category(
+    db: State<'_, Database>,
     ledger_id: i64,
+    parent_id: Option<i64>,
     name: &str,
     icon: Option<&str>,
     color: Option<&str>,
-    subcategories: Vec<String>,
     category_type: &str, // 'expense', 'income', 'transfer'
-) -> Result<i64, String> {
-    let conn = get_connection();
-
-    let subcategories_json = serde_json::to_string(&subcategories).map_err(|e| e.to_string())?;
-
-    conn.execute(
-        "INSERT INTO categories (ledger_id, name, icon, color, subcategories, type) 
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-        params![
-            ledger_id,
-            name,
-            icon,
-            color,
-            subcategories_json,
-            category_type
-        ],
+) -> BackendResult<i64> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    if let Some(parent_id) = parent_id {
+        let parent = load_node(&tx, parent_id, "parent_id")?;
+        check_same_tree(&parent, ledger_id, category_type, "parent_id")?;
+    }
+
+    tx.execute(
+        "INSERT INTO categories (ledger_id, parent_id, name, icon, color, type, sort_order)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6,
+             (SELECT COALESCE(MAX(sort_order) + 1, 0) FROM categories
+              WHERE ledger_id = ?1 AND parent_id IS ?2))",
+        params![ledger_id, parent_id, name, icon, color, category_type],
     )
-    .map_err(|err| format!("Failed to insert category: {}", err))?;
+    .context("Failed to insert category")?;
+    let category_id = tx.last_insert_rowid();
 
-    Ok(conn.last_insert_rowid())
+    tx.commit().context("Failed to commit category")?;
+    Ok(category_id)
 }
 
+// Returns the roots of the ledger's tree for `category_type`, each with its descendants
 #[tauri::command(rename_all = "snake_case")]
 pub fn get_categories_for_ledger(
+    db: State<'_, Database>,
     ledger_id: i64,
     category_type: &str,
-) -> Result<Vec<Category>, String> {
-    let conn = get_connection();
+) -> BackendResult<Vec<Category>> {
+    let conn = db.reader().context("Failed to open database connection")?;
 
     let mut stmt = conn
         .prepare(
-            "SELECT id, name, icon, color, subcategories 
-         FROM categories WHERE ledger_id = ?1 AND type = ?2",
+            "SELECT id, parent_id, name, icon, color, sort_order
+             FROM categories WHERE ledger_id = ?1 AND type = ?2
+             ORDER BY sort_order, id",
         )
-        .map_err(|e| format!("Failed to prepare statement: {}", e))?;
+        .context("Failed to prepare statement")?;
 
     let category_iter = stmt
         .query_map(params![ledger_id, category_type], |row| {
-            let subcategories_json: String = row.get(4)?;
-
             Ok(Category {
                 id: row.get(0)?,
                 ledger_id,
-                name: row.get(1)?,
-                icon: row.get(2)?,
-                color: row.get(3)?,
-                subcategories: serde_json::from_str(&subcategories_json).unwrap_or_else(|_| vec![]),
+                parent_id: row.get(1)?,
+                name: row.get(2)?,
+                icon: row.get(3)?,
+                color: row.get(4)?,
                 category_type: category_type.to_string(),
+                sort_order: row.get(5)?,
+                children: vec![],
             })
         })
-        .map_err(|err| format!("Failed to get category: {}", err))?;
+        .context("Failed to get category")?;
 
-    let mut categories = Vec::new();
+    let mut by_parent: HashMap<Option<i64>, Vec<Category>> = HashMap::new();
     for category in category_iter {
-        categories.push(category.map_err(|e| format!("Failed to parse category row: {}", e))?);
+        let category = category.context("Failed to parse category row")?;
+        by_parent
+            .entry(category.parent_id)
+            .or_default()
+            .push(category);
     }
 
-    Ok(categories)
+    Ok(build_tree(&mut by_parent, None))
+}
+
+fn build_tree(
+    by_parent: &mut HashMap<Option<i64>, Vec<Category>>,
+    parent_id: Option<i64>,
+) -> Vec<Category> {
+    let mut nodes = by_parent.remove(&parent_id).unwrap_or_default();
+    for node in &mut nodes {
+        node.children = build_tree(by_parent, Some(node.id));
+    }
+    nodes
 }
 
+// Changing the type is only allowed on a root and carries the whole tree with it
 #[tauri::command(rename_all = "snake_case")]
 pub fn update_category(
+    db: State<'_, Database>,
     category_id: i64,
     name: &str,
     icon: Option<&str>,
     color: Option<&str>,
-    subcategories: Vec<String>,
     category_type: &str, // 'expense', 'income', 'transfer'
-) -> Result<(), String> {
-    let conn = get_connection();
-
-    let subcategories_json = serde_json::to_string(&subcategories).map_err(|e| e.to_string())?;
-
-    conn.execute(
-        "UPDATE categories SET name = ?1, icon = ?2, color = ?3, subcategories = ?4, type = ?5 
-         WHERE id = ?6",
-        params![
-            name,
-            icon,
-            color,
-            subcategories_json,
-            category_type,
-            category_id
-        ],
+) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    let node = load_node(&tx, category_id, "category_id")?;
+    if node.category_type != category_type {
+        if node.parent_id.is_some() {
+            return Err(BackendError::validation(
+                "category_type",
+                "A subcategory takes its type from its parent; change the parent instead",
+            ));
+        }
+
+        // Transactions, their split lines and recurring rules must keep matching their
+        // category's type
+        let mismatched: i64 = tx
+            .query_row(
+                &format!(
+                    "{} SELECT
+                         (SELECT COUNT(*) FROM transactions
+                          WHERE category_id IN (SELECT id FROM subtree) AND type != ?2)
+                         + (SELECT COUNT(*) FROM transaction_splits s
+                            JOIN transactions t ON t.id = s.transaction_id
+                            WHERE s.category_id IN (SELECT id FROM subtree) AND t.type != ?2)
+                         + (SELECT COUNT(*) FROM recurring_rules
+                            WHERE category_id IN (SELECT id FROM subtree) AND type != ?2)",
+                    SUBTREE_CTE
+                ),
+                params![category_id, category_type],
+                |row| row.get(0),
+            )
+            .context("Failed to check category usage")?;
+        if mismatched > 0 {
+            return Err(BackendError::validation(
+                "category_type",
+                format!(
+                    "Category is used by {} transactions, split lines or recurring rules of \
+                     another type",
+                    mismatched
+                ),
+            ));
+        }
+
+        tx.execute(
+            &format!(
+                "{} UPDATE categories SET type = ?2 WHERE id IN (SELECT id FROM subtree)",
+                SUBTREE_CTE
+            ),
+            params![category_id, category_type],
+        )
+        .context("Failed to update category type")?;
+    }
+
+    tx.execute(
+        "UPDATE categories SET name = ?1, icon = ?2, color = ?3 WHERE id = ?4",
+        params![name, icon, color, category_id],
+    )
+    .context("Failed to update category")?;
+
+    tx.commit().context("Failed to commit category")?;
+    Ok(())
+}
+
+// Deletes the category and everything below it; their transactions become uncategorised
+#[tauri::command(rename_all = "snake_case")]
+pub fn delete_category(db: State<'_, Database>, category_id: i64) -> BackendResult<()> {
+    let conn = db.writer();
+
+    let deleted = conn
+        .execute("DELETE FROM categories WHERE id = ?1", params![category_id])
+        .context("Failed to delete category")?;
+    if deleted == 0 {
+        return Err(category_not_found(category_id));
+    }
+
+    Ok(())
+}
+
+// Re-parent a category, or make it a root with `new_parent_id = None`. It lands at `position`
+// among its new siblings, or last when no position is given.
+#[tauri::command(rename_all = "snake_case")]
+pub fn move_category(
+    db: State<'_, Database>,
+    category_id: i64,
+    new_parent_id: Option<i64>,
+    position: Option<usize>,
+) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    let node = load_node(&tx, category_id, "category_id")?;
+    if let Some(new_parent_id) = new_parent_id {
+        let parent = load_node(&tx, new_parent_id, "new_parent_id")?;
+        check_same_tree(
+            &parent,
+            node.ledger_id,
+            &node.category_type,
+            "new_parent_id",
+        )?;
+        if subtree_ids(&tx, category_id)?.contains(&new_parent_id) {
+            return Err(BackendError::validation(
+                "new_parent_id",
+                "A category cannot be moved below itself",
+            ));
+        }
+    }
+
+    let mut siblings = sibling_ids(&tx, node.ledger_id, &node.category_type, new_parent_id)?;
+    siblings.retain(|&id| id != category_id);
+    let position = position.unwrap_or(siblings.len()).min(siblings.len());
+    siblings.insert(position, category_id);
+
+    tx.execute(
+        "UPDATE categories SET parent_id = ?1 WHERE id = ?2",
+        params![new_parent_id, category_id],
     )
-    .map_err(|err| format!("Failed to update category: {}", err))?;
+    .context("Failed to move category")?;
+    write_sort_order(&tx, &siblings)?;
 
+    tx.commit().context("Failed to commit category move")?;
     Ok(())
 }
 
+// Fold `source_id` into `target_id`: its transactions and children move to the target and the
+// source is deleted
 #[tauri::command(rename_all = "snake_case")]
-pub fn delete_category(category_id: i64) -> Result<(), String> {
-    let conn = get_connection();
+pub fn merge_category(
+    db: State<'_, Database>,
+    source_id: i64,
+    target_id: i64,
+) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    let source = load_node(&tx, source_id, "source_id")?;
+    let target = load_node(&tx, target_id, "target_id")?;
+    check_same_tree(
+        &target,
+        source.ledger_id,
+        &source.category_type,
+        "target_id",
+    )?;
+    if subtree_ids(&tx, source_id)?.contains(&target_id) {
+        return Err(BackendError::validation(
+            "target_id",
+            "A category cannot be merged into itself or one of its subcategories",
+        ));
+    }
+
+    tx.execute(
+        "UPDATE transactions SET category_id = ?1 WHERE category_id = ?2",
+        params![target_id, source_id],
+    )
+    .context("Failed to move transactions")?;
+    tx.execute(
+        "UPDATE transaction_splits SET category_id = ?1 WHERE category_id = ?2",
+        params![target_id, source_id],
+    )
+    .context("Failed to move split lines")?;
+    tx.execute(
+        "UPDATE recurring_rules SET category_id = ?1 WHERE category_id = ?2",
+        params![target_id, source_id],
+    )
+    .context("Failed to move recurring rules")?;
+
+    // Children keep their relative order, after the target's own children
+    let mut children = sibling_ids(
+        &tx,
+        target.ledger_id,
+        &target.category_type,
+        Some(target_id),
+    )?;
+    children.extend(sibling_ids(
+        &tx,
+        source.ledger_id,
+        &source.category_type,
+        Some(source_id),
+    )?);
+    tx.execute(
+        "UPDATE categories SET parent_id = ?1 WHERE parent_id = ?2",
+        params![target_id, source_id],
+    )
+    .context("Failed to move subcategories")?;
+    write_sort_order(&tx, &children)?;
 
-    conn.execute("DELETE FROM categories WHERE id = ?1", params![category_id])
-        .map_err(|err| format!("Failed to delete category: {}", err))?;
+    tx.execute("DELETE FROM categories WHERE id = ?1", params![source_id])
+        .context("Failed to delete merged category")?;
 
+    tx.commit().context("Failed to commit category merge")?;
     Ok(())
 }
+
+// Totals per category for the ledger's income and expenses dated within `from` and `to`
+// (inclusive, either open), read from `transaction_lines`. Uncategorised amounts come last with
+// no category.
+#[tauri::command(rename_all = "snake_case")]
+pub fn get_category_totals(
+    db: State<'_, Database>,
+    ledger_id: i64,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> BackendResult<Vec<CategoryTotal>> {
+    for (field, date) in [("from", from), ("to", to)] {
+        if let Some(date) = date.filter(|date| !is_iso_date(date)) {
+            return Err(BackendError::validation(
+                field,
+                format!("'{}' is not a YYYY-MM-DD date", date),
+            ));
+        }
+    }
+
+    let conn = db.reader().context("Failed to open database connection")?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT l.category_id, c.name, t.type, t.currency, cur.minor_unit, SUM(l.amount),
+                 COUNT(*)
+             FROM transaction_lines l
+             JOIN transactions t ON t.id = l.transaction_id
+             JOIN currencies cur ON cur.code = t.currency
+             LEFT JOIN categories c ON c.id = l.category_id
+             WHERE t.ledger_id = ?1 AND t.type IN ('income', 'expense')
+                 AND (?2 IS NULL OR substr(t.date, 1, 10) >= ?2)
+                 AND (?3 IS NULL OR substr(t.date, 1, 10) <= ?3)
+             GROUP BY l.category_id, t.type, t.currency
+             ORDER BY t.type, l.category_id IS NULL, c.sort_order, c.name, t.currency",
+        )
+        .context("Failed to prepare statement")?;
+
+    let total_iter = stmt
+        .query_map(params![ledger_id, from, to], |row| {
+            Ok(CategoryTotal {
+                category_id: row.get(0)?,
+                category_name: row.get(1)?,
+                transaction_type: row.get(2)?,
+                currency: row.get(3)?,
+                amount: Money::from_minor(row.get(5)?, row.get(4)?),
+                count: row.get(6)?,
+            })
+        })
+        .context("Failed to query category totals")?;
+
+    let mut totals = Vec::new();
+    for total in total_iter {
+        totals.push(total.context("Failed to parse category total row")?);
+    }
+
+    Ok(totals)
+}
+
+// `ordered_ids` must list every child of `parent_id` (every root of `category_type` when it is
+// None) exactly once
+#[tauri::command(rename_all = "snake_case")]
+pub fn reorder_categories(
+    db: State<'_, Database>,
+    ledger_id: i64,
+    category_type: &str, // 'expense', 'income', 'transfer'
+    parent_id: Option<i64>,
+    ordered_ids: Vec<i64>,
+) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    let mut current = sibling_ids(&tx, ledger_id, category_type, parent_id)?;
+    let mut requested = ordered_ids.clone();
+    current.sort_unstable();
+    requested.sort_unstable();
+    if current != requested {
+        return Err(BackendError::validation(
+            "ordered_ids",
+            "The new order must list each sibling category exactly once",
+        ));
+    }
+
+    write_sort_order(&tx, &ordered_ids)?;
+
+    tx.commit().context("Failed to commit category order")?;
+    Ok(())
+}
+
+// Check that a transaction may use this category: it must exist in the same ledger and have
+// the transaction's type
+pub(crate) fn validate_transaction_category(
+    conn: &Connection,
+    ledger_id: i64,
+    transaction_type: &str,
+    category_id: Option<i64>,
+) -> BackendResult<()> {
+    let Some(category_id) = category_id else {
+        return Ok(());
+    };
+
+    let node = load_node(conn, category_id, "category_id")?;
+    if node.ledger_id != ledger_id {
+        return Err(BackendError::validation(
+            "category_id",
+            format!("Category {} belongs to another ledger", category_id),
+        ));
+    }
+    if node.category_type != transaction_type {
+        return Err(BackendError::validation(
+            "category_id",
+            format!(
+                "Category {} is for {} transactions, not {}",
+                category_id, node.category_type, transaction_type
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+// Binds ?1 to the root of the subtree
+const SUBTREE_CTE: &str = "WITH RECURSIVE subtree(id) AS (
+    SELECT ?1
+    UNION ALL
+    SELECT c.id FROM categories c JOIN subtree s ON c.parent_id = s.id
+)";
+
+fn load_node(conn: &Connection, category_id: i64, field: &str) -> BackendResult<Node> {
+    conn.query_row(
+        "SELECT ledger_id, parent_id, type FROM categories WHERE id = ?1",
+        params![category_id],
+        |row| {
+            Ok(Node {
+                ledger_id: row.get(0)?,
+                parent_id: row.get(1)?,
+                category_type: row.get(2)?,
+            })
+        },
+    )
+    .optional()
+    .context("Failed to look up category")?
+    .ok_or_else(|| category_not_found(category_id).with_field(field))
+}
+
+// A category can only sit under a parent from the same ledger and of the same type
+fn check_same_tree(
+    parent: &Node,
+    ledger_id: i64,
+    category_type: &str,
+    field: &str,
+) -> BackendResult<()> {
+    if parent.ledger_id != ledger_id {
+        return Err(BackendError::validation(
+            field,
+            "Parent category belongs to another ledger",
+        ));
+    }
+    if parent.category_type != category_type {
+        return Err(BackendError::validation(
+            field,
+            format!(
+                "Parent category is for {} transactions, not {}",
+                parent.category_type, category_type
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// The category itself and all of its descendants
+fn subtree_ids(conn: &Connection, category_id: i64) -> BackendResult<Vec<i64>> {
+    let mut stmt = conn
+        .prepare(&format!("{} SELECT id FROM subtree", SUBTREE_CTE))
+        .context("Failed to prepare statement")?;
+    let ids = stmt
+        .query_map(params![category_id], |row| row.get(0))
+        .context("Failed to query subcategories")?
+        .collect::<Result<Vec<i64>>>()
+        .context("Failed to parse category row")?;
+    Ok(ids)
+}
+
+// Children share their parent's type, so the type only narrows the roots, which are ordered
+// separately for each type
+fn sibling_ids(
+    conn: &Connection,
+    ledger_id: i64,
+    category_type: &str,
+    parent_id: Option<i64>,
+) -> BackendResult<Vec<i64>> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id FROM categories WHERE ledger_id = ?1 AND type = ?2 AND parent_id IS ?3
+             ORDER BY sort_order, id",
+        )
+        .context("Failed to prepare statement")?;
+    let ids = stmt
+        .query_map(params![ledger_id, category_type, parent_id], |row| {
+            row.get(0)
+        })
+        .context("Failed to query categories")?
+        .collect::<Result<Vec<i64>>>()
+        .context("Failed to parse category row")?;
+    Ok(ids)
+}
+
+fn write_sort_order(conn: &Connection, ordered_ids: &[i64]) -> BackendResult<()> {
+    let mut stmt = conn
+        .prepare("UPDATE categories SET sort_order = ?1 WHERE id = ?2")
+        .context("Failed to prepare statement")?;
+    for (position, id) in ordered_ids.iter().enumerate() {
+        stmt.execute(params![position as i64, id])
+            .context("Failed to reorder categories")?;
+    }
+    Ok(())
+}
+
+fn category_not_found(category_id: i64) -> BackendError {
+    BackendError::not_found(format!("Category {} not found", category_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::recurring::{create_recurring_rule, RecurringRuleInput};
+    use crate::backend::testing::{money, Fixture};
+    use crate::backend::transaction::create_transaction;
+
+    fn category(fixture: &Fixture, ledger: i64, parent_id: Option<i64>, name: &str) -> i64 {
+        insert_category(fixture.db(), ledger, parent_id, name, None, None, "expense").unwrap()
+    }
+
+    // Ids of the categories under `parent_id`, in order
+    fn children(fixture: &Fixture, parent_id: Option<i64>) -> Vec<i64> {
+        let reader = fixture.reader();
+        let mut stmt = reader
+            .prepare("SELECT id FROM categories WHERE parent_id IS ?1 ORDER BY sort_order")
+            .unwrap();
+        let ids = stmt
+            .query_map(params![parent_id], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<Vec<i64>>>()
+            .unwrap();
+        ids
+    }
+
+    #[test]
+    fn merging_moves_transactions_rules_and_subcategories() {
+        let fixture = Fixture::new();
+        let ledger = fixture.ledger("USD");
+        let bank = fixture.account("bank", "debit", "USD", "100");
+        let food = category(&fixture, ledger, None, "Food");
+        let bread = category(&fixture, ledger, Some(food), "Bread");
+        let groceries = category(&fixture, ledger, None, "Groceries");
+        let fruit = category(&fixture, ledger, Some(groceries), "Fruit");
+        let transaction = create_transaction(
+            fixture.db(),
+            ledger,
+            bank,
+            "expense",
+            Some(groceries),
+            money("10"),
+            "USD",
+            "2024-01-01",
+            vec![],
+            None,
+        )
+        .unwrap();
+        let rule = create_recurring_rule(
+            fixture.db(),
+            RecurringRuleInput {
+                ledger_id: ledger,
+                account_id: bank,
+                transaction_type: "expense".to_string(),
+                to_account_id: None,
+                amount: money("10"),
+                to_amount: None,
+                fee: None,
+                category_id: Some(groceries),
+                tags: vec![],
+                note: None,
+                frequency: "monthly".to_string(),
+                interval: None,
+                start_date: "2999-01-01".to_string(),
+                end_date: None,
+                day_of_month: None,
+            },
+        )
+        .unwrap();
+
+        assert!(merge_category(fixture.db(), food, bread).is_err());
+        merge_category(fixture.db(), groceries, food).unwrap();
+
+        let category_of = |table: &str, id: i64| -> i64 {
+            fixture.query(
+                &format!("SELECT category_id FROM {} WHERE id = ?1", table),
+                params![id],
+            )
+        };
+        assert_eq!(category_of("transactions", transaction), food);
+        assert_eq!(category_of("recurring_rules", rule), food);
+        assert_eq!(children(&fixture, Some(food)), [bread, fruit]);
+        assert_eq!(children(&fixture, None), [food]);
+    }
+
+    #[test]
+    fn moving_keeps_the_tree_acyclic_and_places_the_category() {
+        let fixture = Fixture::new();
+        let ledger = fixture.ledger("USD");
+        let food = category(&fixture, ledger, None, "Food");
+        let bread = category(&fixture, ledger, Some(food), "Bread");
+        let rent = category(&fixture, ledger, None, "Rent");
+
+        assert!(move_category(fixture.db(), food, Some(bread), None).is_err());
+        assert!(move_category(fixture.db(), food, Some(food), None).is_err());
+
+        move_category(fixture.db(), bread, None, Some(0)).unwrap();
+        assert_eq!(children(&fixture, None), [bread, food, rent]);
+        move_category(fixture.db(), rent, Some(food), None).unwrap();
+        assert_eq!(children(&fixture, Some(food)), [rent]);
+        assert_eq!(children(&fixture, None), [bread, food]);
+    }
+}
@@ -0,0 +1,369 @@
+use crate::backend::currency;
+use crate::backend::date::today;
+use crate::backend::db::Database;
+use crate::backend::error::{BackendError, BackendResult, Context};
+use crate::backend::exchange_rate::{convert, pivot_currency};
+use crate::backend::money::{add, Money};
+use crate::backend::settings::get_setting;
+use crate::backend::statement::credit_statements;
+use rusqlite::{params, Connection, Result};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+// Settings read from the store on every check, so changes apply without a restart
+pub const UTILISATION_THRESHOLD_SETTING: &str = "credit_utilisation_threshold";
+pub const REMINDER_DAYS_SETTING: &str = "payment_reminder_days";
+pub const REMINDER_INTERVAL_SETTING: &str = "reminder_interval_minutes";
+const DEFAULT_UTILISATION_THRESHOLD: f64 = 0.3;
+const DEFAULT_REMINDER_DAYS: i64 = 3;
+const DEFAULT_REMINDER_INTERVAL_MINUTES: u64 = 60;
+
+// Emitted with a `CreditReminders` payload whenever a check finds something to report
+pub const CREDIT_REMINDERS_EVENT: &str = "credit-reminders";
+// Emitted with a `BackendError` payload when a check fails
+pub const CREDIT_REMINDERS_ERROR_EVENT: &str = "credit-reminders-error";
+
+#[derive(serde::Serialize, Clone)]
+pub struct CardUtilisation {
+    pub account_id: i64,
+    pub name: String,
+    pub currency: String,
+    pub credit_limit: Money,
+    pub owed: Money,
+    pub available: Money,
+    // Owed divided by the limit; None for a card without a limit
+    pub utilisation: Option<f64>,
+    pub over_threshold: bool,
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct CreditUtilisation {
+    // Currency of the totals
+    pub currency: String,
+    pub threshold: f64,
+    // None when a card's currency has no rate into `currency`
+    pub total_limit: Option<Money>,
+    pub total_owed: Option<Money>,
+    pub utilisation: Option<f64>,
+    pub cards: Vec<CardUtilisation>,
+}
+
+// The latest closed statement of a card, with an amount still owed. `days_until_due` is negative
+// once overdue.
+#[derive(serde::Serialize, Clone)]
+pub struct PaymentReminder {
+    pub account_id: i64,
+    pub name: String,
+    pub period_end: String,
+    pub due_date: String,
+    pub amount: Money,
+    pub days_until_due: i64,
+    pub overdue: bool,
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct CreditReminders {
+    pub payments: Vec<PaymentReminder>,
+    pub high_utilisation: Vec<CardUtilisation>,
+}
+
+// Utilisation of every credit card in its own currency, with totals converted into `currency`
+// (the pivot currency when not given) at today's rates
+#[tauri::command(rename_all = "snake_case")]
+pub fn get_credit_utilisation(
+    app: AppHandle,
+    db: State<'_, Database>,
+    currency: Option<&str>,
+) -> BackendResult<CreditUtilisation> {
+    let pivot = pivot_currency(&app);
+    let threshold = utilisation_threshold(&app);
+    let conn = db.reader().context("Failed to open database connection")?;
+    let today = today(&conn)?;
+    credit_utilisation(&conn, currency.unwrap_or(&pivot), &today, &pivot, threshold)
+}
+
+// Overdue statements, then those due within the reminder window, soonest first
+#[tauri::command(rename_all = "snake_case")]
+pub fn get_payment_reminders(
+    app: AppHandle,
+    db: State<'_, Database>,
+) -> BackendResult<Vec<PaymentReminder>> {
+    let days = reminder_days(&app);
+    let conn = db.reader().context("Failed to open database connection")?;
+    let today = today(&conn)?;
+    payment_reminders(&conn, &today, days)
+}
+
+pub fn credit_utilisation(
+    conn: &Connection,
+    currency: &str,
+    date: &str,
+    pivot: &str,
+    threshold: f64,
+) -> BackendResult<CreditUtilisation> {
+    let scale = currency::minor_unit(conn, currency)
+        .context(&format!("Failed to look up currency {}", currency))
+        .map_err(|err| err.with_field("currency"))?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT a.id, a.name, a.currency, cur.minor_unit, c.credit_limit, -a.balance
+             FROM accounts a
+             JOIN currencies cur ON cur.code = a.currency
+             JOIN credit_accounts c ON c.account_id = a.id
+             ORDER BY a.name",
+        )
+        .context("Failed to prepare statement")?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, u32>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, i64>(5)?,
+            ))
+        })
+        .context("Failed to query credit accounts")?
+        .collect::<Result<Vec<_>>>()
+        .context("Failed to parse credit account row")?;
+
+    let mut cards = Vec::new();
+    for (account_id, name, card_currency, card_scale, limit, owed) in rows {
+        let credit_limit = Money::from_minor(limit, card_scale);
+        let owed = Money::from_minor(owed, card_scale);
+        let utilisation = ratio(owed, credit_limit);
+        let available = credit_limit.checked_sub(owed).ok_or_else(|| {
+            BackendError::validation("owed", format!("Card '{}' is out of range", name))
+        })?;
+
+        cards.push(CardUtilisation {
+            account_id,
+            name,
+            currency: card_currency,
+            credit_limit,
+            owed,
+            available,
+            utilisation,
+            over_threshold: utilisation.is_some_and(|u| u >= threshold),
+        });
+    }
+
+    // A card that cannot be converted leaves the totals out rather than failing the whole check
+    let totals = utilisation_totals(conn, &cards, currency, scale, date, pivot).ok();
+    Ok(CreditUtilisation {
+        currency: currency.to_string(),
+        threshold,
+        total_limit: totals.map(|(limit, _)| limit),
+        total_owed: totals.map(|(_, owed)| owed),
+        utilisation: totals.and_then(|(limit, owed)| ratio(owed, limit)),
+        cards,
+    })
+}
+
+// (limit, owed) of every card, converted into `currency`
+fn utilisation_totals(
+    conn: &Connection,
+    cards: &[CardUtilisation],
+    currency: &str,
+    scale: u32,
+    date: &str,
+    pivot: &str,
+) -> BackendResult<(Money, Money)> {
+    let mut total_limit = Money::zero(scale);
+    let mut total_owed = Money::zero(scale);
+    for card in cards {
+        let to_total = |amount: Money| {
+            convert(conn, amount, &card.currency, currency, date, pivot)
+                .map(|conversion| conversion.amount)
+                .map_err(|err| err.context(&format!("Failed to value card '{}'", card.name)))
+        };
+        total_limit = add(total_limit, to_total(card.credit_limit)?)?;
+        total_owed = add(total_owed, to_total(card.owed)?)?;
+    }
+    Ok((total_limit, total_owed))
+}
+
+pub fn payment_reminders(
+    conn: &Connection,
+    today: &str,
+    days: i64,
+) -> BackendResult<Vec<PaymentReminder>> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT a.id, a.name FROM accounts a
+             JOIN credit_accounts c ON c.account_id = a.id
+             ORDER BY a.name",
+        )
+        .context("Failed to prepare statement")?;
+    let cards = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })
+        .context("Failed to query credit accounts")?
+        .collect::<Result<Vec<_>>>()
+        .context("Failed to parse credit account row")?;
+
+    let mut reminders = Vec::new();
+    for (account_id, name) in cards {
+        let statements = credit_statements(conn, account_id, today)
+            .map_err(|err| err.context(&format!("Failed to read statements of '{}'", name)))?;
+        // What is still owed on older statements is carried into the latest one
+        let latest = statements.into_iter().rev().find(|s| s.status != "open");
+        if let Some(statement) = latest {
+            if statement.status != "unpaid" && statement.status != "overdue" {
+                continue;
+            }
+            let days_until_due = days_between(conn, today, &statement.due_date)?;
+            if days_until_due > days {
+                continue;
+            }
+            reminders.push(PaymentReminder {
+                account_id,
+                name,
+                period_end: statement.period_end,
+                due_date: statement.due_date,
+                amount: statement.closing_owed,
+                days_until_due,
+                overdue: days_until_due < 0,
+            });
+        }
+    }
+
+    reminders.sort_by(|a, b| a.due_date.cmp(&b.due_date));
+    Ok(reminders)
+}
+
+// The frontend calls this once its `CREDIT_REMINDERS_EVENT` listener is in place, in case it
+// missed the check made at startup
+#[tauri::command(rename_all = "snake_case")]
+pub fn get_credit_reminders(
+    app: AppHandle,
+    db: State<'_, Database>,
+) -> BackendResult<CreditReminders> {
+    credit_reminders(&app, &db)
+}
+
+// Check at startup and then every `reminder_interval_minutes`, emitting `CREDIT_REMINDERS_EVENT`
+// when a payment is coming up or overdue or a card is over the utilisation threshold, and
+// `CREDIT_REMINDERS_ERROR_EVENT` when the check fails
+pub fn start_reminders(app: AppHandle) {
+    std::thread::spawn(move || loop {
+        if let Err(err) = emit_reminders(&app) {
+            // Nowhere left to report a failure to emit the error itself
+            let _ = app.emit(CREDIT_REMINDERS_ERROR_EVENT, err);
+        }
+
+        let minutes = get_setting::<u64>(&app, REMINDER_INTERVAL_SETTING)
+            .filter(|minutes| *minutes > 0)
+            .unwrap_or(DEFAULT_REMINDER_INTERVAL_MINUTES);
+        std::thread::sleep(Duration::from_secs(minutes * 60));
+    });
+}
+
+fn emit_reminders(app: &AppHandle) -> BackendResult<()> {
+    let reminders = credit_reminders(app, &app.state::<Database>())?;
+    if reminders.payments.is_empty() && reminders.high_utilisation.is_empty() {
+        return Ok(());
+    }
+    app.emit(CREDIT_REMINDERS_EVENT, reminders)
+        .map_err(|err| BackendError::database(format!("Failed to emit reminders: {}", err)))
+}
+
+fn credit_reminders(app: &AppHandle, db: &Database) -> BackendResult<CreditReminders> {
+    let pivot = pivot_currency(app);
+    let conn = db.reader().context("Failed to open database connection")?;
+    let today = today(&conn)?;
+
+    let payments = payment_reminders(&conn, &today, reminder_days(app))?;
+    let high_utilisation: Vec<CardUtilisation> =
+        credit_utilisation(&conn, &pivot, &today, &pivot, utilisation_threshold(app))?
+            .cards
+            .into_iter()
+            .filter(|card| card.over_threshold)
+            .collect();
+
+    Ok(CreditReminders {
+        payments,
+        high_utilisation,
+    })
+}
+
+fn utilisation_threshold(app: &AppHandle) -> f64 {
+    get_setting::<f64>(app, UTILISATION_THRESHOLD_SETTING)
+        .filter(|threshold| threshold.is_finite() && *threshold >= 0.0)
+        .unwrap_or(DEFAULT_UTILISATION_THRESHOLD)
+}
+
+fn reminder_days(app: &AppHandle) -> i64 {
+    get_setting::<i64>(app, REMINDER_DAYS_SETTING)
+        .filter(|days| *days >= 0)
+        .unwrap_or(DEFAULT_REMINDER_DAYS)
+}
+
+// Both amounts are in the same currency, so their minor units compare directly
+fn ratio(owed: Money, limit: Money) -> Option<f64> {
+    if limit.is_zero() || limit.is_negative() {
+        return None;
+    }
+    Some(owed.minor() as f64 / limit.minor() as f64)
+}
+
+fn days_between(conn: &Connection, from: &str, to: &str) -> BackendResult<i64> {
+    conn.query_row(
+        "SELECT CAST(julianday(?2) - julianday(?1) AS INTEGER)",
+        params![from, to],
+        |row| row.get(0),
+    )
+    .context("Failed to compare dates")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::testing::{money, Fixture};
+    use crate::backend::transaction::create_transaction;
+
+    #[test]
+    fn a_card_without_a_rate_leaves_only_the_totals_out() {
+        let fixture = Fixture::new();
+        let ledger = fixture.ledger("USD");
+        let dollars = fixture.credit_card("dollars", "USD", "31", "10");
+        let euros = fixture.credit_card("euros", "EUR", "31", "10");
+        for (card, currency) in [(dollars, "USD"), (euros, "EUR")] {
+            create_transaction(
+                fixture.db(),
+                ledger,
+                card,
+                "expense",
+                None,
+                money("500"),
+                currency,
+                "2024-01-20",
+                vec![],
+                None,
+            )
+            .unwrap();
+        }
+
+        let utilisation =
+            credit_utilisation(&fixture.reader(), "USD", "2024-03-01", "USD", 0.3).unwrap();
+        assert!(utilisation.total_limit.is_none());
+        assert!(utilisation.total_owed.is_none());
+        assert_eq!(utilisation.utilisation, None);
+        // Each card is still measured against its own limit
+        let cards: Vec<_> = utilisation
+            .cards
+            .iter()
+            .map(|card| {
+                (
+                    card.currency.as_str(),
+                    card.utilisation,
+                    card.over_threshold,
+                )
+            })
+            .collect();
+        assert_eq!(cards, [("USD", Some(0.5), true), ("EUR", Some(0.5), true)]);
+    }
+}
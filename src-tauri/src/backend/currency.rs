@@ -1,17 +1,34 @@
-use crate::backend::db::get_connection;
-use rusqlite::Result;
+use crate::backend::db::Database;
+use crate::backend::error::{BackendError, BackendResult, Context};
+use crate::backend::money::MAX_SCALE;
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use tauri::State;
 
 #[derive(serde::Serialize)]
 pub struct Currency {
     pub code: String,
     pub name: String,
     pub symbol: String,
+    pub minor_unit: u32,
+    // Added by the user, e.g. a crypto asset or loyalty points, rather than taken from ISO 4217
+    pub is_custom: bool,
+    // 'before' or 'after' the amount
+    pub symbol_position: String,
+    pub symbol_spacing: bool,
+    pub decimal_separator: String,
+    pub group_separator: String,
+    // Symbol that identifies this currency alone, e.g. JP¥ rather than ¥
+    pub display_symbol: String,
 }
 
-// Function to create the currency table
-pub fn create_currencies_table() -> Result<()> {
-    let conn = get_connection();
+// `display_symbol` for a row of `currencies c`: the stored override, otherwise the plain symbol
+// unless another currency shares it, in which case the code
+pub(crate) const DISPLAY_SYMBOL_SQL: &str = "COALESCE(c.disambiguated_symbol,
+    CASE WHEN EXISTS (SELECT 1 FROM currencies o WHERE o.symbol = c.symbol AND o.code != c.code)
+        THEN c.code ELSE c.symbol END)";
 
+// Function to create the currency table
+pub fn create_currencies_table(conn: &Connection) -> Result<()> {
     let create_table_sql = "
     CREATE TABLE IF NOT EXISTS currencies (
         code TEXT PRIMARY KEY,
@@ -26,11 +43,7 @@ pub fn create_currencies_table() -> Result<()> {
 }
 
 fn insert_into_currencies_table(conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
-    let count: i64 = conn.query_row(
-        "SELECT COUNT(*) FROM currencies",
-        [],
-        |row| row.get(0),
-    )?;
+    let count: i64 = conn.query_row("SELECT COUNT(*) FROM currencies", [], |row| row.get(0))?;
 
     if count == 0 {
         let insert_table_sql = "
@@ -52,4 +65,423 @@ fn insert_into_currencies_table(conn: &rusqlite::Connection) -> Result<(), rusql
     Ok(())
 }
 
-// Add more currency-related functions here as needed
+// Number of decimal digits amounts in this currency are stored with (2 for USD, 0 for JPY)
+pub fn minor_unit(conn: &Connection, code: &str) -> Result<u32> {
+    conn.query_row(
+        "SELECT minor_unit FROM currencies WHERE code = ?1",
+        params![code],
+        |row| row.get(0),
+    )
+}
+
+// Add any ISO 4217 currency the database does not have yet. Existing rows are left alone: their
+// stored amounts depend on `minor_unit`, and the user may have edited the name or symbol.
+pub fn seed_iso_currencies(conn: &Connection) -> Result<()> {
+    let mut insert = conn.prepare(
+        "INSERT INTO currencies (code, name, symbol, minor_unit, is_custom)
+         VALUES (?1, ?2, ?3, ?4, 0)
+         ON CONFLICT (code) DO NOTHING",
+    )?;
+    for (code, name, symbol, minor_unit) in ISO_CURRENCIES {
+        if insert.execute(params![code, name, symbol, minor_unit])? > 0 {
+            apply_iso_format(conn, code)?;
+        }
+    }
+    Ok(())
+}
+
+// Set the display conventions for an ISO currency that differ from the `$1,234.56` defaults
+pub fn apply_iso_format(conn: &Connection, code: &str) -> Result<()> {
+    let Some((_, position, spacing, decimal, group, disambiguated)) =
+        ISO_FORMATS.iter().find(|f| f.0 == code)
+    else {
+        return Ok(());
+    };
+    conn.execute(
+        "UPDATE currencies SET symbol_position = ?1, symbol_spacing = ?2, decimal_separator = ?3,
+             group_separator = ?4, disambiguated_symbol = ?5
+         WHERE code = ?6 AND is_custom = 0",
+        params![position, spacing, decimal, group, disambiguated, code],
+    )?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_currencies(db: State<'_, Database>) -> BackendResult<Vec<Currency>> {
+    let conn = db.reader().context("Failed to open database connection")?;
+
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT c.code, c.name, c.symbol, c.minor_unit, c.is_custom, c.symbol_position,
+                     c.symbol_spacing, c.decimal_separator, c.group_separator, {}
+                 FROM currencies c
+                 ORDER BY c.is_custom DESC, c.code",
+            DISPLAY_SYMBOL_SQL
+        ))
+        .context("Failed to prepare statement")?;
+
+    let currency_iter = stmt
+        .query_map([], |row| {
+            Ok(Currency {
+                code: row.get(0)?,
+                name: row.get(1)?,
+                symbol: row.get(2)?,
+                minor_unit: row.get(3)?,
+                is_custom: row.get(4)?,
+                symbol_position: row.get(5)?,
+                symbol_spacing: row.get(6)?,
+                decimal_separator: row.get(7)?,
+                group_separator: row.get(8)?,
+                display_symbol: row.get(9)?,
+            })
+        })
+        .context("Failed to query currencies")?;
+
+    let mut currencies = Vec::new();
+    for currency in currency_iter {
+        currencies.push(currency.context("Failed to parse currency row")?);
+    }
+
+    Ok(currencies)
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn create_currency(
+    db: State<'_, Database>,
+    code: &str,
+    name: &str,
+    symbol: &str,
+    minor_unit: u32,
+) -> BackendResult<()> {
+    let code = code.trim();
+    if code.is_empty() || !code.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(BackendError::validation(
+            "code",
+            "Currency code must be letters and digits only",
+        ));
+    }
+    validate_minor_unit(minor_unit)?;
+
+    let conn = db.writer();
+    conn.execute(
+        "INSERT INTO currencies (code, name, symbol, minor_unit, is_custom)
+         VALUES (?1, ?2, ?3, ?4, 1)",
+        params![code, name, symbol, minor_unit],
+    )
+    .context("Failed to insert currency")?;
+
+    Ok(())
+}
+
+// Any currency can be renamed or given another symbol. Only an unused custom currency can
+// change its minor unit, since stored amounts are counted in it.
+#[tauri::command(rename_all = "snake_case")]
+pub fn update_currency(
+    db: State<'_, Database>,
+    code: &str,
+    name: &str,
+    symbol: &str,
+    minor_unit: u32,
+) -> BackendResult<()> {
+    validate_minor_unit(minor_unit)?;
+
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    let (current_unit, is_custom) = load_currency(&tx, code)?;
+    if current_unit != minor_unit {
+        if !is_custom {
+            return Err(BackendError::validation(
+                "minor_unit",
+                format!("The minor unit of ISO currency {} is fixed", code),
+            ));
+        }
+        if is_in_use(&tx, code)? {
+            return Err(BackendError::validation(
+                "minor_unit",
+                format!(
+                    "{} already has amounts recorded, so its minor unit cannot change",
+                    code
+                ),
+            ));
+        }
+    }
+
+    tx.execute(
+        "UPDATE currencies SET name = ?1, symbol = ?2, minor_unit = ?3 WHERE code = ?4",
+        params![name, symbol, minor_unit, code],
+    )
+    .context("Failed to update currency")?;
+
+    tx.commit().context("Failed to commit currency")?;
+    Ok(())
+}
+
+// Accounts, ledgers and transactions still using the currency block the delete through their
+// ON DELETE RESTRICT references; its exchange rates are removed with it
+#[tauri::command(rename_all = "snake_case")]
+pub fn delete_currency(db: State<'_, Database>, code: &str) -> BackendResult<()> {
+    let conn = db.writer();
+
+    let (_, is_custom) = load_currency(&conn, code)?;
+    if !is_custom {
+        return Err(BackendError::validation(
+            "code",
+            format!("{} is an ISO currency and cannot be deleted", code),
+        ));
+    }
+
+    conn.execute("DELETE FROM currencies WHERE code = ?1", params![code])
+        .context("Failed to delete currency")?;
+
+    Ok(())
+}
+
+fn validate_minor_unit(minor_unit: u32) -> BackendResult<()> {
+    if minor_unit > MAX_SCALE {
+        return Err(BackendError::validation(
+            "minor_unit",
+            format!("Minor unit must be between 0 and {}", MAX_SCALE),
+        ));
+    }
+    Ok(())
+}
+
+fn load_currency(conn: &Connection, code: &str) -> BackendResult<(u32, bool)> {
+    conn.query_row(
+        "SELECT minor_unit, is_custom FROM currencies WHERE code = ?1",
+        params![code],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+    .optional()
+    .context("Failed to look up currency")?
+    .ok_or_else(|| {
+        BackendError::not_found(format!("Currency {} not found", code)).with_field("code")
+    })
+}
+
+// Whether any stored amount is counted in this currency's minor units
+fn is_in_use(conn: &Connection, code: &str) -> BackendResult<bool> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM accounts WHERE currency = ?1)
+             OR EXISTS (SELECT 1 FROM transactions WHERE currency = ?1 OR to_currency = ?1)",
+        params![code],
+        |row| row.get(0),
+    )
+    .context("Failed to check currency usage")
+}
+
+// (code, symbol position, space between symbol and amount, decimal separator, group separator,
+// disambiguated symbol)
+type IsoFormat = (
+    &'static str,
+    &'static str,
+    bool,
+    &'static str,
+    &'static str,
+    Option<&'static str>,
+);
+
+// Display conventions for ISO currencies that differ from the defaults. Currencies sharing a
+// symbol without an entry here display their code instead.
+const ISO_FORMATS: &[IsoFormat] = &[
+    ("ARS", "before", true, ",", ".", None),
+    ("AUD", "before", false, ".", ",", Some("A$")),
+    ("BGN", "after", true, ",", " ", None),
+    ("BRL", "before", true, ",", ".", None),
+    ("CAD", "before", false, ".", ",", Some("CA$")),
+    ("CHF", "before", true, ".", "'", None),
+    ("CLP", "before", false, ",", ".", Some("CLP$")),
+    ("CNY", "before", false, ".", ",", Some("CN¥")),
+    ("COP", "before", true, ",", ".", Some("COL$")),
+    ("CZK", "after", true, ",", " ", None),
+    ("DKK", "after", true, ",", ".", None),
+    ("GBP", "before", false, ".", ",", Some("GB£")),
+    ("HUF", "after", true, ",", " ", None),
+    ("IDR", "before", true, ",", ".", None),
+    ("ISK", "after", true, ",", ".", None),
+    ("JPY", "before", false, ".", ",", Some("JP¥")),
+    ("KRW", "before", false, ".", ",", Some("KR₩")),
+    ("MXN", "before", false, ".", ",", Some("MX$")),
+    ("NOK", "after", true, ",", " ", None),
+    ("PLN", "after", true, ",", " ", None),
+    ("RON", "after", true, ",", ".", None),
+    ("RUB", "after", true, ",", " ", None),
+    ("SEK", "after", true, ",", " ", None),
+    ("TRY", "before", false, ",", ".", None),
+    ("UAH", "after", true, ",", " ", None),
+    ("USD", "before", false, ".", ",", Some("US$")),
+    ("VND", "after", true, ",", ".", None),
+];
+
+// ISO 4217 currencies with their minor units. Codes without a minor unit (precious metals,
+// testing and special drawing codes) are left out.
+const ISO_CURRENCIES: &[(&str, &str, &str, u32)] = &[
+    ("AED", "UAE Dirham", "AED", 2),
+    ("AFN", "Afghani", "؋", 2),
+    ("ALL", "Lek", "L", 2),
+    ("AMD", "Armenian Dram", "֏", 2),
+    ("ANG", "Netherlands Antillean Guilder", "ƒ", 2),
+    ("AOA", "Kwanza", "Kz", 2),
+    ("ARS", "Argentine Peso", "$", 2),
+    ("AUD", "Australian Dollar", "A$", 2),
+    ("AWG", "Aruban Florin", "ƒ", 2),
+    ("AZN", "Azerbaijan Manat", "₼", 2),
+    ("BAM", "Convertible Mark", "KM", 2),
+    ("BBD", "Barbados Dollar", "Bds$", 2),
+    ("BDT", "Taka", "৳", 2),
+    ("BGN", "Bulgarian Lev", "лв", 2),
+    ("BHD", "Bahraini Dinar", "BD", 3),
+    ("BIF", "Burundi Franc", "FBu", 0),
+    ("BMD", "Bermudian Dollar", "$", 2),
+    ("BND", "Brunei Dollar", "B$", 2),
+    ("BOB", "Boliviano", "Bs.", 2),
+    ("BOV", "Mvdol", "BOV", 2),
+    ("BRL", "Brazilian Real", "R$", 2),
+    ("BSD", "Bahamian Dollar", "$", 2),
+    ("BTN", "Ngultrum", "Nu.", 2),
+    ("BWP", "Pula", "P", 2),
+    ("BYN", "Belarusian Ruble", "Br", 2),
+    ("BZD", "Belize Dollar", "BZ$", 2),
+    ("CAD", "Canadian Dollar", "C$", 2),
+    ("CDF", "Congolese Franc", "FC", 2),
+    ("CHE", "WIR Euro", "CHE", 2),
+    ("CHF", "Swiss Franc", "CHF", 2),
+    ("CHW", "WIR Franc", "CHW", 2),
+    ("CLF", "Unidad de Fomento", "UF", 4),
+    ("CLP", "Chilean Peso", "$", 0),
+    ("CNY", "Yuan Renminbi", "¥", 2),
+    ("COP", "Colombian Peso", "$", 2),
+    ("COU", "Unidad de Valor Real", "COU", 2),
+    ("CRC", "Costa Rican Colon", "₡", 2),
+    ("CUP", "Cuban Peso", "$", 2),
+    ("CVE", "Cabo Verde Escudo", "Esc", 2),
+    ("CZK", "Czech Koruna", "Kč", 2),
+    ("DJF", "Djibouti Franc", "Fdj", 0),
+    ("DKK", "Danish Krone", "kr", 2),
+    ("DOP", "Dominican Peso", "RD$", 2),
+    ("DZD", "Algerian Dinar", "DA", 2),
+    ("EGP", "Egyptian Pound", "E£", 2),
+    ("ERN", "Nakfa", "Nfk", 2),
+    ("ETB", "Ethiopian Birr", "Br", 2),
+    ("EUR", "Euro", "€", 2),
+    ("FJD", "Fiji Dollar", "FJ$", 2),
+    ("FKP", "Falkland Islands Pound", "£", 2),
+    ("GBP", "Pound Sterling", "£", 2),
+    ("GEL", "Lari", "₾", 2),
+    ("GHS", "Ghana Cedi", "GH₵", 2),
+    ("GIP", "Gibraltar Pound", "£", 2),
+    ("GMD", "Dalasi", "D", 2),
+    ("GNF", "Guinean Franc", "FG", 0),
+    ("GTQ", "Quetzal", "Q", 2),
+    ("GYD", "Guyana Dollar", "G$", 2),
+    ("HKD", "Hong Kong Dollar", "HK$", 2),
+    ("HNL", "Lempira", "L", 2),
+    ("HTG", "Gourde", "G", 2),
+    ("HUF", "Forint", "Ft", 2),
+    ("IDR", "Rupiah", "Rp", 2),
+    ("ILS", "New Israeli Sheqel", "₪", 2),
+    ("INR", "Indian Rupee", "₹", 2),
+    ("IQD", "Iraqi Dinar", "IQD", 3),
+    ("IRR", "Iranian Rial", "﷼", 2),
+    ("ISK", "Iceland Krona", "kr", 0),
+    ("JMD", "Jamaican Dollar", "J$", 2),
+    ("JOD", "Jordanian Dinar", "JD", 3),
+    ("JPY", "Yen", "¥", 0),
+    ("KES", "Kenyan Shilling", "KSh", 2),
+    ("KGS", "Som", "KGS", 2),
+    ("KHR", "Riel", "៛", 2),
+    ("KMF", "Comorian Franc", "CF", 0),
+    ("KPW", "North Korean Won", "₩", 2),
+    ("KRW", "Won", "₩", 0),
+    ("KWD", "Kuwaiti Dinar", "KD", 3),
+    ("KYD", "Cayman Islands Dollar", "CI$", 2),
+    ("KZT", "Tenge", "₸", 2),
+    ("LAK", "Lao Kip", "₭", 2),
+    ("LBP", "Lebanese Pound", "LL", 2),
+    ("LKR", "Sri Lanka Rupee", "Rs", 2),
+    ("LRD", "Liberian Dollar", "L$", 2),
+    ("LSL", "Loti", "L", 2),
+    ("LYD", "Libyan Dinar", "LD", 3),
+    ("MAD", "Moroccan Dirham", "DH", 2),
+    ("MDL", "Moldovan Leu", "L", 2),
+    ("MGA", "Malagasy Ariary", "Ar", 2),
+    ("MKD", "Denar", "ден", 2),
+    ("MMK", "Kyat", "K", 2),
+    ("MNT", "Tugrik", "₮", 2),
+    ("MOP", "Pataca", "MOP$", 2),
+    ("MRU", "Ouguiya", "UM", 2),
+    ("MUR", "Mauritius Rupee", "Rs", 2),
+    ("MVR", "Rufiyaa", "Rf", 2),
+    ("MWK", "Malawi Kwacha", "MK", 2),
+    ("MXN", "Mexican Peso", "$", 2),
+    ("MXV", "Mexican Unidad de Inversion (UDI)", "MXV", 2),
+    ("MYR", "Malaysian Ringgit", "RM", 2),
+    ("MZN", "Mozambique Metical", "MT", 2),
+    ("NAD", "Namibia Dollar", "N$", 2),
+    ("NGN", "Naira", "₦", 2),
+    ("NIO", "Cordoba Oro", "C$", 2),
+    ("NOK", "Norwegian Krone", "kr", 2),
+    ("NPR", "Nepalese Rupee", "Rs", 2),
+    ("NZD", "New Zealand Dollar", "NZ$", 2),
+    ("OMR", "Rial Omani", "OMR", 3),
+    ("PAB", "Balboa", "B/.", 2),
+    ("PEN", "Sol", "S/", 2),
+    ("PGK", "Kina", "K", 2),
+    ("PHP", "Philippine Peso", "₱", 2),
+    ("PKR", "Pakistan Rupee", "Rs", 2),
+    ("PLN", "Zloty", "zł", 2),
+    ("PYG", "Guarani", "₲", 0),
+    ("QAR", "Qatari Rial", "QR", 2),
+    ("RON", "Romanian Leu", "lei", 2),
+    ("RSD", "Serbian Dinar", "RSD", 2),
+    ("RUB", "Russian Ruble", "₽", 2),
+    ("RWF", "Rwanda Franc", "FRw", 0),
+    ("SAR", "Saudi Riyal", "SR", 2),
+    ("SBD", "Solomon Islands Dollar", "SI$", 2),
+    ("SCR", "Seychelles Rupee", "SR", 2),
+    ("SDG", "Sudanese Pound", "SDG", 2),
+    ("SEK", "Swedish Krona", "kr", 2),
+    ("SGD", "Singapore Dollar", "S$", 2),
+    ("SHP", "Saint Helena Pound", "£", 2),
+    ("SLE", "Leone", "Le", 2),
+    ("SOS", "Somali Shilling", "Sh", 2),
+    ("SRD", "Surinam Dollar", "$", 2),
+    ("SSP", "South Sudanese Pound", "SSP", 2),
+    ("STN", "Dobra", "Db", 2),
+    ("SVC", "El Salvador Colon", "₡", 2),
+    ("SYP", "Syrian Pound", "LS", 2),
+    ("SZL", "Lilangeni", "E", 2),
+    ("THB", "Baht", "฿", 2),
+    ("TJS", "Somoni", "SM", 2),
+    ("TMT", "Turkmenistan New Manat", "m", 2),
+    ("TND", "Tunisian Dinar", "DT", 3),
+    ("TOP", "Pa'anga", "T$", 2),
+    ("TRY", "Turkish Lira", "₺", 2),
+    ("TTD", "Trinidad and Tobago Dollar", "TT$", 2),
+    ("TWD", "New Taiwan Dollar", "NT$", 2),
+    ("TZS", "Tanzanian Shilling", "TSh", 2),
+    ("UAH", "Hryvnia", "₴", 2),
+    ("UGX", "Uganda Shilling", "USh", 0),
+    ("USD", "US Dollar", "$", 2),
+    ("USN", "US Dollar (Next day)", "USN", 2),
+    ("UYI", "Uruguay Peso en Unidades Indexadas (UI)", "UYI", 0),
+    ("UYU", "Peso Uruguayo", "$U", 2),
+    ("UYW", "Unidad Previsional", "UYW", 4),
+    ("UZS", "Uzbekistan Sum", "UZS", 2),
+    ("VED", "Bolívar Soberano", "Bs.D", 2),
+    ("VES", "Bolívar Soberano", "Bs.S", 2),
+    ("VND", "Dong", "₫", 0),
+    ("VUV", "Vatu", "VT", 0),
+    ("WST", "Tala", "WS$", 2),
+    ("XAF", "CFA Franc BEAC", "FCFA", 0),
+    ("XCD", "East Caribbean Dollar", "EC$", 2),
+    ("XCG", "Caribbean Guilder", "Cg", 2),
+    ("XOF", "CFA Franc BCEAO", "CFA", 0),
+    ("XPF", "CFP Franc", "F", 0),
+    ("YER", "Yemeni Rial", "YR", 2),
+    ("ZAR", "Rand", "R", 2),
+    ("ZMW", "Zambian Kwacha", "ZK", 2),
+    ("ZWG", "Zimbabwe Gold", "ZiG", 2),
+    ("ZWL", "Zimbabwe Dollar", "Z$", 2),
+];
@@ -0,0 +1,148 @@
+use crate::backend::error::{BackendError, BackendResult, Context};
+use rusqlite::Connection;
+
+// Dates are YYYY-MM-DD strings throughout, so they compare and order correctly as text.
+// `is_iso_date` checks them on the way in; the calendar arithmetic below expects dates that
+// passed it and does not check them again.
+
+// The date must also exist, since statements and schedules step through its month and day
+pub(crate) fn is_iso_date(date: &str) -> bool {
+    let bytes = date.as_bytes();
+    let shaped = bytes.len() == 10
+        && bytes.iter().enumerate().all(|(i, b)| match i {
+            4 | 7 => *b == b'-',
+            _ => b.is_ascii_digit(),
+        });
+    if !shaped {
+        return false;
+    }
+
+    let year: i32 = date[0..4].parse().unwrap_or_default();
+    let month: u32 = date[5..7].parse().unwrap_or_default();
+    let day: u32 = date[8..10].parse().unwrap_or_default();
+    (1..=12).contains(&month) && day >= 1 && day <= days_in_month(year, month)
+}
+
+// Report a date `is_iso_date` rejects against `field`
+pub(crate) fn validate_date(field: &str, date: &str) -> BackendResult<()> {
+    if !is_iso_date(date) {
+        return Err(BackendError::validation(
+            field,
+            format!("'{}' is not a YYYY-MM-DD date", date),
+        ));
+    }
+    Ok(())
+}
+
+// Today's date in local time, in the YYYY-MM-DD form rates are stored with
+pub fn today(conn: &Connection) -> BackendResult<String> {
+    conn.query_row("SELECT date('now', 'localtime')", [], |row| row.get(0))
+        .context("Failed to read the current date")
+}
+
+// `day` of the month, or the month's last day when it is shorter
+pub(crate) fn clamped_date(year: i32, month: u32, day: u32) -> String {
+    format!(
+        "{:04}-{:02}-{:02}",
+        year,
+        month,
+        day.min(days_in_month(year, month))
+    )
+}
+
+pub(crate) fn add_days(date: &str, days: i64) -> String {
+    format_days(date_days(date) + days)
+}
+
+pub(crate) fn next_month((year, month): (i32, u32)) -> (i32, u32) {
+    if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    }
+}
+
+pub(crate) fn previous_month((year, month): (i32, u32)) -> (i32, u32) {
+    if month == 1 {
+        (year - 1, 12)
+    } else {
+        (year, month - 1)
+    }
+}
+
+pub(crate) fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+pub(crate) fn year_month(date: &str) -> (i32, u32) {
+    let (year, month, _) = split_date(date);
+    (year, month)
+}
+
+pub(crate) fn split_date(date: &str) -> (i32, u32, u32) {
+    (
+        date[0..4].parse().unwrap_or_default(),
+        date[5..7].parse().unwrap_or_default(),
+        date[8..10].parse().unwrap_or_default(),
+    )
+}
+
+// Days since 1970-01-01 in the proleptic Gregorian calendar
+pub(crate) fn date_days(date: &str) -> i64 {
+    let (year, month, day) = split_date(date);
+    let (year, month, day) = (year as i64, month as i64, day as i64);
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// The date `days` after 1970-01-01
+pub(crate) fn format_days(days: i64) -> String {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_day_arithmetic_round_trips() {
+        for date in ["1970-01-01", "2000-02-29", "2024-12-31", "1969-07-20"] {
+            assert_eq!(format_days(date_days(date)), date);
+        }
+        assert_eq!(add_days("2024-02-28", 1), "2024-02-29");
+        assert_eq!(add_days("2023-02-28", 1), "2023-03-01");
+        assert_eq!(add_days("2024-01-01", -1), "2023-12-31");
+    }
+
+    #[test]
+    fn month_steps_wrap_the_year_and_clamp_the_day() {
+        assert_eq!(next_month((2023, 12)), (2024, 1));
+        assert_eq!(previous_month((2024, 1)), (2023, 12));
+        assert_eq!(clamped_date(2024, 2, 31), "2024-02-29");
+        assert_eq!(clamped_date(2100, 2, 29), "2100-02-28");
+        assert_eq!(clamped_date(2024, 4, 15), "2024-04-15");
+    }
+}
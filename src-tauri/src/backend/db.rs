@@ -1,38 +1,815 @@
-use rusqlite::{Connection, Result};
+use crate::backend::error::{BackendResult, Context};
+use crate::backend::money::Money;
+use crate::backend::settings::get_setting;
+use crate::backend::{
+    account, attachment, balance, category, currency, exchange_rate, invest, invest_event, ledger,
+    recurring, search, security, snapshot, split, statement, tag, transaction,
+};
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, OpenFlags, Result, Transaction, TransactionBehavior};
+use std::fmt;
 use std::fs;
-use std::path::Path;
-use std::sync::Once;
-
-const DB_PATH: &str = ".data/finance.db";
-const DIR_PATH: &str = ".data";
-static INIT: Once = Once::new();
-static mut DB_CONNECTION: Option<Connection> = None;
-
-pub fn init_db() -> Result<()> {
-    INIT.call_once(|| unsafe {
-        if !Path::new(DIR_PATH).exists() {
-            match fs::create_dir(DIR_PATH) {
-                Ok(_) => println!("Directory {} created", DIR_PATH),
-                Err(e) => println!("Failed to create directory: {}", e),
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tauri::{AppHandle, Manager, State};
+
+const DB_FILE_NAME: &str = "finance.db";
+// Overrides the database location, taking precedence over the stored setting
+pub const DB_PATH_ENV: &str = "FINANCE_WIFYOU_DB_PATH";
+// Settings key holding a user-chosen database path; relative paths resolve against app data
+pub const DB_PATH_SETTING: &str = "database_path";
+// Idle read-only connections kept around for reuse; extra ones are closed when returned
+const MAX_IDLE_READERS: usize = 4;
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// A single schema change. Released migrations must never be edited, only appended to.
+struct Migration {
+    version: u32,
+    description: &'static str,
+    up: fn(&Connection) -> Result<()>,
+}
+
+// Ordered list of every schema change; the last entry is the version this build writes.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        up: initial_schema,
+    },
+    Migration {
+        version: 2,
+        description: "add tags.color, transactions.currency and transactions.date",
+        up: add_missing_columns,
+    },
+    Migration {
+        version: 3,
+        description: "store amounts as integer minor units",
+        up: convert_amounts_to_minor_units,
+    },
+    Migration {
+        version: 4,
+        description: "derive account balances from opening balance and transactions",
+        up: derive_balances_from_transactions,
+    },
+    Migration {
+        version: 5,
+        description: "add transfer destination, amount, fee and rate to transactions",
+        up: add_transfer_columns,
+    },
+    Migration {
+        version: 6,
+        description: "add category and subcategory to transactions",
+        up: add_transaction_categories,
+    },
+    Migration {
+        version: 7,
+        description: "turn subcategory lists into a category tree",
+        up: explode_subcategories,
+    },
+    Migration {
+        version: 8,
+        description: "add exchange_rates",
+        up: exchange_rate::create_exchange_rates_table,
+    },
+    Migration {
+        version: 9,
+        description: "add currencies.is_custom",
+        up: add_custom_currency_flag,
+    },
+    Migration {
+        version: 10,
+        description: "add currency display conventions",
+        up: add_currency_formats,
+    },
+    Migration {
+        version: 11,
+        description: "add balance_snapshots",
+        up: snapshot::create_balance_snapshots_table,
+    },
+    Migration {
+        version: 12,
+        description: "add statement_payments",
+        up: statement::create_statement_payments_table,
+    },
+    Migration {
+        version: 13,
+        description: "add invest_trades and invest_lots",
+        up: invest::create_invest_trades_tables,
+    },
+    Migration {
+        version: 14,
+        description: "move invest positions into securities and holdings",
+        up: move_positions_to_holdings,
+    },
+    Migration {
+        version: 15,
+        description: "add invest_events",
+        up: invest_event::create_invest_events_table,
+    },
+    Migration {
+        version: 16,
+        description: "add transaction_search full-text index",
+        up: search::create_transaction_search,
+    },
+    Migration {
+        version: 17,
+        description: "add transaction_splits and split_tags",
+        up: split::create_transaction_splits_tables,
+    },
+    Migration {
+        version: 18,
+        description: "add recurring_rules and link transactions to them",
+        up: recurring::create_recurring_tables,
+    },
+    Migration {
+        version: 19,
+        description: "add attachments and transaction_attachments",
+        up: attachment::create_attachments_tables,
+    },
+    Migration {
+        version: 20,
+        description: "index split line notes, tags and categories for search",
+        up: search::index_split_lines,
+    },
+    Migration {
+        version: 21,
+        description: "drop credit_accounts.owed in favour of the balance",
+        up: drop_credit_account_owed,
+    },
+    Migration {
+        version: 22,
+        description: "add invest_trades.amount",
+        up: invest::add_trade_amounts,
+    },
+];
+
+pub const SCHEMA_VERSION: u32 = MIGRATIONS[MIGRATIONS.len() - 1].version;
+
+#[derive(Debug)]
+pub enum DbError {
+    Sqlite(rusqlite::Error),
+    Io(std::io::Error),
+    // The app data directory could not be determined on this platform.
+    AppDataDir(String),
+    // The database was last written by a newer build of the app.
+    NewerSchema {
+        found: u32,
+        supported: u32,
+    },
+    // A schema change failed and was rolled back.
+    Migration {
+        version: u32,
+        description: &'static str,
+        source: rusqlite::Error,
+    },
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::Sqlite(e) => write!(f, "Database error: {}", e),
+            DbError::Io(e) => write!(f, "Failed to prepare database location: {}", e),
+            DbError::AppDataDir(e) => write!(f, "Failed to resolve app data directory: {}", e),
+            DbError::NewerSchema { found, supported } => write!(
+                f,
+                "Database schema version {} is newer than the supported version {}",
+                found, supported
+            ),
+            DbError::Migration {
+                version,
+                description,
+                source,
+            } => write!(
+                f,
+                "Migration {} ({}) failed: {}",
+                version, description, source
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+impl From<rusqlite::Error> for DbError {
+    fn from(e: rusqlite::Error) -> Self {
+        DbError::Sqlite(e)
+    }
+}
+
+impl From<std::io::Error> for DbError {
+    fn from(e: std::io::Error) -> Self {
+        DbError::Io(e)
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct DatabaseInfo {
+    pub path: String,
+    pub size_bytes: u64,
+    pub schema_version: u32,
+}
+
+// Shared connection state handed to every command through `tauri::State`. SQLite in WAL mode
+// allows a single writer alongside any number of readers, so writes are serialised through one
+// mutex-guarded connection while reads check out a read-only connection from a small pool.
+pub struct Database {
+    path: PathBuf,
+    writer: Mutex<Connection>,
+    readers: Mutex<Vec<Connection>>,
+}
+
+impl Database {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, DbError> {
+        let path = path.as_ref().to_path_buf();
+        let conn = Connection::open(&path)?;
+        configure_connection(&conn)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        run_migrations(&conn)?;
+
+        Ok(Database {
+            path,
+            writer: Mutex::new(conn),
+            readers: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // The only connection allowed to write; held for the duration of a command's writes
+    pub fn writer(&self) -> MutexGuard<'_, Connection> {
+        // A panic while holding the lock leaves the connection itself usable
+        self.writer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn reader(&self) -> Result<ReadConnection<'_>> {
+        let pooled = self
+            .readers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop();
+
+        let conn = match pooled {
+            Some(conn) => conn,
+            None => {
+                let conn = Connection::open_with_flags(
+                    &self.path,
+                    OpenFlags::SQLITE_OPEN_READ_ONLY
+                        | OpenFlags::SQLITE_OPEN_NO_MUTEX
+                        | OpenFlags::SQLITE_OPEN_URI,
+                )?;
+                configure_connection(&conn)?;
+                conn
+            }
+        };
+
+        Ok(ReadConnection {
+            db: self,
+            conn: Some(conn),
+        })
+    }
+}
+
+// A pooled read-only connection, returned to the pool when dropped
+pub struct ReadConnection<'a> {
+    db: &'a Database,
+    conn: Option<Connection>,
+}
+
+impl Deref for ReadConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn
+            .as_ref()
+            .expect("read connection already returned")
+    }
+}
+
+impl Drop for ReadConnection<'_> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            let mut readers = self
+                .db
+                .readers
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            if readers.len() < MAX_IDLE_READERS {
+                readers.push(conn);
             }
         }
-        if !Path::new(DB_PATH).exists() {
-            match fs::File::create(DB_PATH) {
-                Ok(_) => println!("File {} created", DB_PATH),
-                Err(e) => println!("Failed to create file: {}", e),
+    }
+}
+
+// Settings every connection needs, whether it reads or writes
+fn configure_connection(conn: &Connection) -> Result<()> {
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    // SQLite leaves foreign keys off per connection unless asked; without this none of the
+    // ON DELETE CASCADE / SET NULL / RESTRICT clauses in the schema take effect
+    conn.pragma_update(None, "foreign_keys", true)?;
+    Ok(())
+}
+
+pub fn init_db(app: &AppHandle) -> Result<Database, DbError> {
+    let path = resolve_db_path(app)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    Database::open(path)
+}
+
+// Pick the database file: the environment override first, then the stored setting, and
+// otherwise `finance.db` in the platform app data directory
+fn resolve_db_path(app: &AppHandle) -> Result<PathBuf, DbError> {
+    if let Some(path) = std::env::var_os(DB_PATH_ENV).filter(|p| !p.is_empty()) {
+        return Ok(PathBuf::from(path));
+    }
+
+    let app_data_dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| DbError::AppDataDir(e.to_string()))?;
+
+    match get_setting::<String>(app, DB_PATH_SETTING).filter(|p| !p.is_empty()) {
+        Some(path) => Ok(app_data_dir.join(path)),
+        None => Ok(app_data_dir.join(DB_FILE_NAME)),
+    }
+}
+
+#[tauri::command]
+pub fn get_database_info(db: State<'_, Database>) -> BackendResult<DatabaseInfo> {
+    let conn = db.reader().context("Failed to open database connection")?;
+    let schema_version = schema_version(&conn).context("Failed to read schema version")?;
+    let size_bytes = fs::metadata(db.path())
+        .context("Failed to read database file")?
+        .len();
+
+    Ok(DatabaseInfo {
+        path: db.path().display().to_string(),
+        size_bytes,
+        schema_version,
+    })
+}
+
+pub fn schema_version(conn: &Connection) -> Result<u32> {
+    conn.pragma_query_value(None, "user_version", |row| row.get(0))
+}
+
+// Bring the schema up to SCHEMA_VERSION, applying each pending migration in its own transaction
+pub fn run_migrations(conn: &Connection) -> Result<(), DbError> {
+    let current = schema_version(conn)?;
+    if current > SCHEMA_VERSION {
+        return Err(DbError::NewerSchema {
+            found: current,
+            supported: SCHEMA_VERSION,
+        });
+    }
+
+    // Migrations that rebuild a table would otherwise fire its cascades, so foreign keys are
+    // switched off while they run. The pragma is ignored inside a transaction, hence out here.
+    conn.pragma_update(None, "foreign_keys", false)?;
+    let result = apply_migrations(conn, current);
+    conn.pragma_update(None, "foreign_keys", true)?;
+    result?;
+
+    // Views and reference data follow the current build rather than being frozen into a
+    // migration, so they are refreshed on every start
+    let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    balance::create_balance_effects_view(&tx)?;
+    split::create_transaction_lines_view(&tx)?;
+    currency::seed_iso_currencies(&tx)?;
+    tx.commit()?;
+    Ok(())
+}
+
+fn apply_migrations(conn: &Connection, current: u32) -> Result<(), DbError> {
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+        (migration.up)(&tx).map_err(|source| DbError::Migration {
+            version: migration.version,
+            description: migration.description,
+            source,
+        })?;
+        tx.pragma_update(None, "user_version", migration.version)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+// Version 1 is the schema the app shipped with before migrations existed. Every statement is
+// `IF NOT EXISTS`, so databases created by those builds pass through it unchanged.
+fn initial_schema(conn: &Connection) -> Result<()> {
+    currency::create_currencies_table(conn)?;
+    account::create_accounts_table(conn)?;
+    ledger::create_ledgers_table(conn)?;
+    transaction::create_transactions_table(conn)?;
+    category::create_categories_table(conn)?;
+    tag::create_tags_table(conn)?;
+    Ok(())
+}
+
+// Columns the commands have always used but the original DDL never declared
+fn add_missing_columns(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        ALTER TABLE tags ADD COLUMN color TEXT;
+
+        ALTER TABLE transactions RENAME COLUMN date_time TO date;
+        ALTER TABLE transactions
+            ADD COLUMN currency TEXT REFERENCES currencies(code) ON DELETE RESTRICT;
+        UPDATE transactions
+            SET currency = (SELECT a.currency FROM accounts a WHERE a.id = transactions.account_id);
+        ",
+    )
+}
+
+// Amounts used to be REAL values in DECIMAL columns. They are now integers counted in the minor
+// unit of their currency, so each value is scaled by that currency's `minor_unit` and rounded.
+// The column declarations stay NUMERIC, which stores whole numbers as INTEGER.
+fn convert_amounts_to_minor_units(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        ALTER TABLE currencies ADD COLUMN minor_unit INTEGER NOT NULL DEFAULT 2
+            CHECK( minor_unit BETWEEN 0 AND 8 );
+        UPDATE currencies SET minor_unit = 0 WHERE code = 'JPY';
+        ",
+    )?;
+
+    // (select of key, value and scale, update taking value then key)
+    let columns = [
+        (
+            "SELECT a.id, a.balance, cur.minor_unit FROM accounts a
+             LEFT JOIN currencies cur ON cur.code = a.currency",
+            "UPDATE accounts SET balance = ?1 WHERE id = ?2",
+        ),
+        (
+            "SELECT c.account_id, c.credit_limit, cur.minor_unit FROM credit_accounts c
+             JOIN accounts a ON a.id = c.account_id
+             LEFT JOIN currencies cur ON cur.code = a.currency",
+            "UPDATE credit_accounts SET credit_limit = ?1 WHERE account_id = ?2",
+        ),
+        (
+            "SELECT c.account_id, c.owed, cur.minor_unit FROM credit_accounts c
+             JOIN accounts a ON a.id = c.account_id
+             LEFT JOIN currencies cur ON cur.code = a.currency",
+            "UPDATE credit_accounts SET owed = ?1 WHERE account_id = ?2",
+        ),
+        (
+            "SELECT i.account_id, i.avg_cost, cur.minor_unit FROM invest_accounts i
+             JOIN accounts a ON a.id = i.account_id
+             LEFT JOIN currencies cur ON cur.code = a.currency",
+            "UPDATE invest_accounts SET avg_cost = ?1 WHERE account_id = ?2",
+        ),
+        (
+            "SELECT i.account_id, i.total_cap, cur.minor_unit FROM invest_accounts i
+             JOIN accounts a ON a.id = i.account_id
+             LEFT JOIN currencies cur ON cur.code = a.currency",
+            "UPDATE invest_accounts SET total_cap = ?1 WHERE account_id = ?2",
+        ),
+        (
+            "SELECT t.id, t.amount, cur.minor_unit FROM transactions t
+             LEFT JOIN accounts a ON a.id = t.account_id
+             LEFT JOIN currencies cur ON cur.code = COALESCE(t.currency, a.currency)",
+            "UPDATE transactions SET amount = ?1 WHERE id = ?2",
+        ),
+    ];
+
+    for (select_sql, update_sql) in columns {
+        let rows = {
+            let mut stmt = conn.prepare(select_sql)?;
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, Option<f64>>(1)?,
+                        row.get::<_, Option<u32>>(2)?,
+                    ))
+                })?
+                .collect::<Result<Vec<_>>>()?;
+            rows
+        };
+
+        let mut update = conn.prepare(update_sql)?;
+        for (key, value, scale) in rows {
+            let Some(value) = value else { continue };
+            let minor = Money::from_f64(value, scale.unwrap_or(2))
+                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?
+                .minor();
+            update.execute(params![minor, key])?;
+        }
+    }
+
+    Ok(())
+}
+
+// Balances used to be typed in by hand and never moved with transactions. Keep the figure users
+// currently see by back-solving an opening balance from it and the existing history.
+fn derive_balances_from_transactions(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        -- Rows written before the type was required: a negative amount was money going out
+        UPDATE transactions
+            SET type = CASE WHEN amount < 0 THEN 'expense' ELSE 'income' END,
+                amount = ABS(amount)
+            WHERE type IS NULL;
+
+        ALTER TABLE accounts ADD COLUMN opening_balance INTEGER NOT NULL DEFAULT 0;
+
+        UPDATE accounts SET opening_balance = balance - COALESCE(
+            (SELECT SUM(CASE t.type WHEN 'income' THEN t.amount ELSE -t.amount END)
+             FROM transactions t WHERE t.account_id = accounts.id), 0);
+        ",
+    )
+}
+
+// A transfer row keeps the source in `account_id`/`amount`/`currency` and names its destination
+// here. `to_amount` is in the destination currency's minor units and `fee` in the source's.
+fn add_transfer_columns(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        ALTER TABLE transactions
+            ADD COLUMN to_account_id INTEGER REFERENCES accounts(id) ON DELETE CASCADE;
+        ALTER TABLE transactions ADD COLUMN to_amount INTEGER;
+        ALTER TABLE transactions
+            ADD COLUMN to_currency TEXT REFERENCES currencies(code) ON DELETE RESTRICT;
+        ALTER TABLE transactions ADD COLUMN fee INTEGER NOT NULL DEFAULT 0 CHECK( fee >= 0 );
+        ALTER TABLE transactions ADD COLUMN exchange_rate REAL;
+
+        CREATE INDEX IF NOT EXISTS idx_transactions_to_account ON transactions(to_account_id);
+        ",
+    )
+}
+
+fn add_transaction_categories(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        ALTER TABLE transactions
+            ADD COLUMN category_id INTEGER REFERENCES categories(id) ON DELETE SET NULL;
+        ALTER TABLE transactions ADD COLUMN subcategory TEXT;
+
+        CREATE INDEX IF NOT EXISTS idx_transactions_category ON transactions(category_id);
+        ",
+    )
+}
+
+// `categories.subcategories` held a JSON array of names. Each name becomes a child row, and
+// transactions that named a subcategory now point at that child instead.
+fn explode_subcategories(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        ALTER TABLE categories
+            ADD COLUMN parent_id INTEGER REFERENCES categories(id) ON DELETE CASCADE;
+        ALTER TABLE categories ADD COLUMN sort_order INTEGER NOT NULL DEFAULT 0;
+        CREATE INDEX IF NOT EXISTS idx_categories_parent
+            ON categories(ledger_id, parent_id, sort_order);
+
+        -- Existing categories keep the order they were created in
+        UPDATE categories SET sort_order = (
+            SELECT COUNT(*) FROM categories c
+            WHERE c.ledger_id = categories.ledger_id AND c.id < categories.id);
+        ",
+    )?;
+
+    let parents = {
+        let mut stmt = conn.prepare(
+            "SELECT id, ledger_id, type, subcategories FROM categories
+             WHERE TRIM(COALESCE(subcategories, '')) != ''",
+        )?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>>>()?;
+        rows
+    };
+
+    let mut insert = conn.prepare(
+        "INSERT INTO categories (ledger_id, parent_id, name, type, sort_order)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    for (id, ledger_id, category_type, json) in parents {
+        let names: Vec<String> = serde_json::from_str(&json)
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(3, Type::Text, Box::new(e)))?;
+        let mut seen = Vec::new();
+        for name in names {
+            if name.trim().is_empty() || seen.contains(&name) {
+                continue;
             }
-        } else {
-            println!("File {} already exists", DB_PATH);
+            insert.execute(params![
+                ledger_id,
+                id,
+                name,
+                category_type,
+                seen.len() as i64
+            ])?;
+            seen.push(name);
         }
-        DB_CONNECTION = Some(Connection::open(DB_PATH).unwrap());
-    });
+    }
+
+    conn.execute_batch(
+        "
+        UPDATE transactions SET category_id = (
+            SELECT c.id FROM categories c
+            WHERE c.parent_id = transactions.category_id AND c.name = transactions.subcategory)
+        WHERE subcategory IS NOT NULL AND EXISTS (
+            SELECT 1 FROM categories c
+            WHERE c.parent_id = transactions.category_id AND c.name = transactions.subcategory);
+
+        ALTER TABLE transactions DROP COLUMN subcategory;
+        ALTER TABLE categories DROP COLUMN subcategories;
+        ",
+    )
+}
+
+// Currencies seeded by earlier builds all come from ISO 4217
+fn add_custom_currency_flag(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE currencies ADD COLUMN is_custom BOOLEAN NOT NULL DEFAULT 0;")
+}
+
+fn add_currency_formats(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        ALTER TABLE currencies ADD COLUMN symbol_position TEXT NOT NULL DEFAULT 'before'
+            CHECK( symbol_position IN ('before', 'after') );
+        ALTER TABLE currencies ADD COLUMN symbol_spacing BOOLEAN NOT NULL DEFAULT 0;
+        ALTER TABLE currencies ADD COLUMN decimal_separator TEXT NOT NULL DEFAULT '.';
+        ALTER TABLE currencies ADD COLUMN group_separator TEXT NOT NULL DEFAULT ',';
+        ALTER TABLE currencies ADD COLUMN disambiguated_symbol TEXT;
+        ",
+    )?;
+
+    let codes = {
+        let mut stmt = conn.prepare("SELECT code FROM currencies WHERE is_custom = 0")?;
+        let codes = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>>>()?;
+        codes
+    };
+    for code in codes {
+        currency::apply_iso_format(conn, &code)?;
+    }
     Ok(())
 }
 
-pub fn get_connection() -> &'static Connection {
-    unsafe {
-        DB_CONNECTION
-            .as_ref()
-            .expect("Database connection not initialized")
+// An invest account held a single position in `invest_accounts`. Each account with a position,
+// trades or a market price gets a security named after it and one holding of that security,
+// which takes over the position, the trades and lots, and the price.
+fn move_positions_to_holdings(conn: &Connection) -> Result<()> {
+    security::create_securities_tables(conn)?;
+    invest::create_holdings_table(conn)?;
+
+    conn.execute_batch(
+        "
+        INSERT INTO securities (symbol, name, currency, asset_class)
+        SELECT 'ACCOUNT-' || a.id, a.name, a.currency, 'other'
+        FROM accounts a
+        JOIN invest_accounts i ON i.account_id = a.id
+        WHERE COALESCE(i.quantity, 0) > 0
+            OR i.market_price IS NOT NULL
+            OR EXISTS (SELECT 1 FROM invest_trades t WHERE t.account_id = a.id);
+
+        INSERT INTO holdings (account_id, security_id, cost_method, quantity, avg_cost, cost_basis)
+        SELECT i.account_id, s.id, i.cost_method, COALESCE(i.quantity, 0),
+            COALESCE(i.avg_cost, 0), COALESCE(i.total_cap, 0)
+        FROM invest_accounts i
+        JOIN securities s ON s.symbol = 'ACCOUNT-' || i.account_id;
+
+        UPDATE invest_trades SET holding_id = (
+            SELECT h.id FROM holdings h WHERE h.account_id = invest_trades.account_id);
+        UPDATE invest_lots SET holding_id = (
+            SELECT h.id FROM holdings h WHERE h.account_id = invest_lots.account_id);
+
+        INSERT INTO security_prices (security_id, date, price, source)
+        SELECT s.id, COALESCE(i.market_price_date, date('now', 'localtime')), i.market_price,
+            'invest account'
+        FROM invest_accounts i
+        JOIN securities s ON s.symbol = 'ACCOUNT-' || i.account_id
+        WHERE i.market_price IS NOT NULL;
+
+        ALTER TABLE invest_accounts DROP COLUMN avg_cost;
+        ALTER TABLE invest_accounts DROP COLUMN quantity;
+        ALTER TABLE invest_accounts DROP COLUMN total_cap;
+        ALTER TABLE invest_accounts DROP COLUMN cost_method;
+        ALTER TABLE invest_accounts DROP COLUMN market_price;
+        ALTER TABLE invest_accounts DROP COLUMN market_price_date;
+        ",
+    )
+}
+
+// What a card owes now follows from its transactions, so the figure typed in by hand goes
+fn drop_credit_account_owed(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE credit_accounts DROP COLUMN owed;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An in-memory database as an older build left it at `version`
+    fn database_at(version: u32) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        for migration in MIGRATIONS.iter().filter(|m| m.version <= version) {
+            (migration.up)(&conn).unwrap();
+            conn.pragma_update(None, "user_version", migration.version)
+                .unwrap();
+        }
+        conn
+    }
+
+    fn integer(conn: &Connection, sql: &str) -> i64 {
+        conn.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn migration_3_scales_real_amounts_to_minor_units() {
+        let conn = database_at(2);
+        conn.execute_batch(
+            "
+            INSERT INTO accounts (id, name, type, balance, currency) VALUES
+                (1, 'cash', 'debit', 12.5, 'USD'),
+                (2, 'yen', 'debit', 1500.4, 'JPY'),
+                (3, 'card', 'credit', -20.75, 'USD');
+            INSERT INTO credit_accounts (account_id, credit_limit, owed, billing_date, due_date)
+                VALUES (3, 1000, 20.75, '1', '20');
+            INSERT INTO ledgers (id, name, base_currency) VALUES (1, 'L', 'USD');
+            INSERT INTO transactions (ledger_id, account_id, amount, type, date, currency) VALUES
+                (1, 1, 0.125, 'expense', '2024-01-01', 'USD'),
+                (1, 1, 0.115, 'income', '2024-01-02', 'USD'),
+                (1, 2, 300, 'expense', '2024-01-03', 'JPY');
+            ",
+        )
+        .unwrap();
+
+        let migration = MIGRATIONS.iter().find(|m| m.version == 3).unwrap();
+        (migration.up)(&conn).unwrap();
+
+        assert_eq!(
+            integer(&conn, "SELECT balance FROM accounts WHERE id = 1"),
+            1250
+        );
+        assert_eq!(
+            integer(&conn, "SELECT balance FROM accounts WHERE id = 2"),
+            1500
+        );
+        assert_eq!(
+            integer(&conn, "SELECT balance FROM accounts WHERE id = 3"),
+            -2075
+        );
+        assert_eq!(
+            integer(&conn, "SELECT credit_limit FROM credit_accounts"),
+            100_000
+        );
+        assert_eq!(integer(&conn, "SELECT owed FROM credit_accounts"), 2075);
+        // Halves round away from zero, and values read as REAL are rounded rather than truncated
+        assert_eq!(
+            integer(&conn, "SELECT amount FROM transactions WHERE id = 1"),
+            13
+        );
+        assert_eq!(
+            integer(&conn, "SELECT amount FROM transactions WHERE id = 2"),
+            12
+        );
+        assert_eq!(
+            integer(&conn, "SELECT amount FROM transactions WHERE id = 3"),
+            300
+        );
+        assert_eq!(
+            integer(
+                &conn,
+                "SELECT typeof(amount) = 'integer' FROM transactions WHERE id = 1"
+            ),
+            1
+        );
+    }
+
+    #[test]
+    fn legacy_amounts_survive_every_later_migration() {
+        let conn = database_at(2);
+        conn.execute_batch(
+            "
+            INSERT INTO accounts (id, name, type, balance, currency)
+                VALUES (1, 'cash', 'debit', 12.5, 'USD');
+            INSERT INTO ledgers (id, name, base_currency) VALUES (1, 'L', 'USD');
+            INSERT INTO transactions (ledger_id, account_id, amount, type, date, currency)
+                VALUES (1, 1, 0.125, 'expense', '2024-01-01', 'USD');
+            ",
+        )
+        .unwrap();
+
+        run_migrations(&conn).unwrap();
+
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+        // The balance users saw is kept, with the opening balance solved back from it
+        assert_eq!(integer(&conn, "SELECT balance FROM accounts"), 1250);
+        assert_eq!(integer(&conn, "SELECT opening_balance FROM accounts"), 1263);
     }
 }
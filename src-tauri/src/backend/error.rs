@@ -0,0 +1,195 @@
+use crate::backend::db::DbError;
+use crate::backend::money::MoneyError;
+use rusqlite::ffi;
+use std::fmt;
+
+// Error returned by every command. Serialises as `{ "kind": ..., "message": ..., "field": ... }`
+// so the frontend can branch on `kind` and highlight `field` when it is set.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BackendError {
+    // The requested row does not exist
+    NotFound {
+        message: String,
+        field: Option<String>,
+    },
+    // The input was rejected before or by a CHECK / NOT NULL constraint
+    Validation {
+        message: String,
+        field: Option<String>,
+    },
+    // A UNIQUE or PRIMARY KEY constraint was violated, e.g. a duplicate tag name
+    Conflict {
+        message: String,
+        field: Option<String>,
+    },
+    // A referenced row is missing, or the row is still referenced elsewhere
+    ForeignKey {
+        message: String,
+        field: Option<String>,
+    },
+    // Anything else coming out of SQLite or the filesystem
+    Database {
+        message: String,
+        field: Option<String>,
+    },
+}
+
+pub type BackendResult<T> = Result<T, BackendError>;
+
+impl BackendError {
+    pub fn not_found(message: impl Into<String>) -> Self {
+        BackendError::NotFound {
+            message: message.into(),
+            field: None,
+        }
+    }
+
+    pub fn validation(field: &str, message: impl Into<String>) -> Self {
+        BackendError::Validation {
+            message: message.into(),
+            field: Some(field.to_string()),
+        }
+    }
+
+    pub fn database(message: impl Into<String>) -> Self {
+        BackendError::Database {
+            message: message.into(),
+            field: None,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        let (message, _) = self.parts();
+        message
+    }
+
+    pub fn field(&self) -> Option<&str> {
+        let (_, field) = self.parts();
+        field.as_deref()
+    }
+
+    // Attach the offending field, keeping one already derived from a constraint name
+    pub fn with_field(mut self, name: &str) -> Self {
+        let (_, field) = self.parts_mut();
+        field.get_or_insert_with(|| name.to_string());
+        self
+    }
+
+    // Prefix the message with what was being attempted, keeping the kind
+    pub fn context(mut self, context: &str) -> Self {
+        let (message, _) = self.parts_mut();
+        *message = format!("{}: {}", context, message);
+        self
+    }
+
+    fn parts(&self) -> (&String, &Option<String>) {
+        match self {
+            BackendError::NotFound { message, field }
+            | BackendError::Validation { message, field }
+            | BackendError::Conflict { message, field }
+            | BackendError::ForeignKey { message, field }
+            | BackendError::Database { message, field } => (message, field),
+        }
+    }
+
+    fn parts_mut(&mut self) -> (&mut String, &mut Option<String>) {
+        match self {
+            BackendError::NotFound { message, field }
+            | BackendError::Validation { message, field }
+            | BackendError::Conflict { message, field }
+            | BackendError::ForeignKey { message, field }
+            | BackendError::Database { message, field } => (message, field),
+        }
+    }
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+impl From<rusqlite::Error> for BackendError {
+    fn from(err: rusqlite::Error) -> Self {
+        let message = err.to_string();
+        match &err {
+            rusqlite::Error::QueryReturnedNoRows => BackendError::NotFound {
+                message: "No matching record".to_string(),
+                field: None,
+            },
+            rusqlite::Error::SqliteFailure(e, detail) => {
+                let field = detail.as_deref().and_then(constraint_column);
+                match e.extended_code {
+                    ffi::SQLITE_CONSTRAINT_UNIQUE | ffi::SQLITE_CONSTRAINT_PRIMARYKEY => {
+                        BackendError::Conflict { message, field }
+                    }
+                    ffi::SQLITE_CONSTRAINT_FOREIGNKEY => {
+                        BackendError::ForeignKey { message, field }
+                    }
+                    // ON DELETE RESTRICT is enforced through an internal trigger
+                    ffi::SQLITE_CONSTRAINT_TRIGGER if message.contains("FOREIGN KEY") => {
+                        BackendError::ForeignKey { message, field }
+                    }
+                    ffi::SQLITE_CONSTRAINT_NOTNULL | ffi::SQLITE_CONSTRAINT_CHECK => {
+                        BackendError::Validation { message, field }
+                    }
+                    _ => BackendError::Database { message, field },
+                }
+            }
+            _ => BackendError::database(message),
+        }
+    }
+}
+
+// SQLite reports "UNIQUE constraint failed: tags.name"; pull out the column so the frontend can
+// point at the offending input. CHECK and FOREIGN KEY messages carry no column.
+fn constraint_column(detail: &str) -> Option<String> {
+    let (_, columns) = detail.split_once("constraint failed: ")?;
+    let first = columns.split(',').next()?.trim();
+    let (_, column) = first.split_once('.')?;
+    Some(column.to_string())
+}
+
+impl From<MoneyError> for BackendError {
+    fn from(err: MoneyError) -> Self {
+        BackendError::Validation {
+            message: err.to_string(),
+            field: None,
+        }
+    }
+}
+
+impl From<DbError> for BackendError {
+    fn from(err: DbError) -> Self {
+        match err {
+            DbError::Sqlite(e) => e.into(),
+            other => BackendError::database(other.to_string()),
+        }
+    }
+}
+
+impl From<serde_json::Error> for BackendError {
+    fn from(err: serde_json::Error) -> Self {
+        BackendError::database(err.to_string())
+    }
+}
+
+impl From<std::io::Error> for BackendError {
+    fn from(err: std::io::Error) -> Self {
+        BackendError::database(err.to_string())
+    }
+}
+
+// `.context("Failed to insert account")?` in place of `map_err(|e| format!(...))`
+pub trait Context<T> {
+    fn context(self, context: &str) -> BackendResult<T>;
+}
+
+impl<T, E: Into<BackendError>> Context<T> for Result<T, E> {
+    fn context(self, context: &str) -> BackendResult<T> {
+        self.map_err(|err| err.into().context(context))
+    }
+}
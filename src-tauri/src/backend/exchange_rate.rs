@@ -0,0 +1,464 @@
+use crate::backend::currency;
+use crate::backend::date::is_iso_date;
+use crate::backend::db::Database;
+use crate::backend::error::{BackendError, BackendResult, Context};
+use crate::backend::money::Money;
+use crate::backend::settings::get_setting;
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use tauri::{AppHandle, State};
+
+// Settings key naming the currency used to bridge pairs that have no rate of their own
+pub const PIVOT_CURRENCY_SETTING: &str = "pivot_currency";
+const DEFAULT_PIVOT_CURRENCY: &str = "USD";
+
+// One unit of `base` is worth `rate` units of `quote` on `date` (YYYY-MM-DD)
+#[derive(serde::Serialize)]
+pub struct ExchangeRate {
+    pub id: i64,
+    pub base: String,
+    pub quote: String,
+    pub date: String,
+    pub rate: f64,
+    pub source: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct Conversion {
+    pub amount: Money,
+    pub rate: f64,
+    // Date of the rate used; the older of the two legs when going through the pivot
+    pub rate_date: String,
+    // The pivot currency when no rate between the pair itself was found
+    pub via: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct RateImport {
+    pub imported: usize,
+}
+
+// A rate found for a pair, possibly by inverting the stored opposite pair
+struct FoundRate {
+    rate: f64,
+    date: String,
+}
+
+pub fn create_exchange_rates_table(conn: &Connection) -> Result<()> {
+    let create_table_sql = "
+    CREATE TABLE IF NOT EXISTS exchange_rates (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        base TEXT NOT NULL,
+        quote TEXT NOT NULL,
+        date TEXT NOT NULL,
+        rate REAL NOT NULL CHECK( rate > 0 ),
+        source TEXT,
+        UNIQUE (base, quote, date),
+        CHECK( base != quote ),
+        FOREIGN KEY (base) REFERENCES currencies(code) ON DELETE CASCADE,
+        FOREIGN KEY (quote) REFERENCES currencies(code) ON DELETE CASCADE
+    );";
+
+    conn.execute(create_table_sql, [])?;
+    Ok(())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn add_exchange_rate(
+    db: State<'_, Database>,
+    base: &str,
+    quote: &str,
+    date: &str,
+    rate: f64,
+    source: Option<&str>,
+) -> BackendResult<i64> {
+    validate_rate(date, rate)?;
+    let conn = db.writer();
+    conn.execute(
+        "INSERT INTO exchange_rates (base, quote, date, rate, source)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![base, quote, date, rate, source],
+    )
+    .context("Failed to insert exchange rate")?;
+
+    Ok(conn.last_insert_rowid())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn get_exchange_rates(
+    db: State<'_, Database>,
+    base: Option<&str>,
+    quote: Option<&str>,
+) -> BackendResult<Vec<ExchangeRate>> {
+    let conn = db.reader().context("Failed to open database connection")?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, base, quote, date, rate, source FROM exchange_rates
+             WHERE (?1 IS NULL OR base = ?1) AND (?2 IS NULL OR quote = ?2)
+             ORDER BY base, quote, date DESC",
+        )
+        .context("Failed to prepare statement")?;
+
+    let rate_iter = stmt
+        .query_map(params![base, quote], |row| {
+            Ok(ExchangeRate {
+                id: row.get(0)?,
+                base: row.get(1)?,
+                quote: row.get(2)?,
+                date: row.get(3)?,
+                rate: row.get(4)?,
+                source: row.get(5)?,
+            })
+        })
+        .context("Failed to query exchange rates")?;
+
+    let mut rates = Vec::new();
+    for rate in rate_iter {
+        rates.push(rate.context("Failed to parse exchange rate row")?);
+    }
+
+    Ok(rates)
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn update_exchange_rate(
+    db: State<'_, Database>,
+    id: i64,
+    date: &str,
+    rate: f64,
+    source: Option<&str>,
+) -> BackendResult<()> {
+    validate_rate(date, rate)?;
+    let conn = db.writer();
+    let updated = conn
+        .execute(
+            "UPDATE exchange_rates SET date = ?1, rate = ?2, source = ?3 WHERE id = ?4",
+            params![date, rate, source, id],
+        )
+        .context("Failed to update exchange rate")?;
+    if updated == 0 {
+        return Err(rate_not_found(id));
+    }
+
+    Ok(())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn delete_exchange_rate(db: State<'_, Database>, id: i64) -> BackendResult<()> {
+    let conn = db.writer();
+    let deleted = conn
+        .execute("DELETE FROM exchange_rates WHERE id = ?1", params![id])
+        .context("Failed to delete exchange rate")?;
+    if deleted == 0 {
+        return Err(rate_not_found(id));
+    }
+
+    Ok(())
+}
+
+// Import CSV text with a header row naming `date`, `base`, `quote` and `rate` columns in any
+// order. Fields may be quoted. A rate already stored for the same pair and date is replaced.
+// Nothing is imported if any line is invalid.
+#[tauri::command(rename_all = "snake_case")]
+pub fn import_exchange_rates(
+    db: State<'_, Database>,
+    csv: &str,
+    source: Option<&str>,
+) -> BackendResult<RateImport> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(csv.as_bytes());
+    let csv_error =
+        |err: csv::Error| BackendError::validation("csv", format!("Invalid CSV: {}", err));
+
+    let header: Vec<String> = reader
+        .headers()
+        .map_err(csv_error)?
+        .iter()
+        .map(str::to_ascii_lowercase)
+        .collect();
+    if header.iter().all(String::is_empty) {
+        return Err(BackendError::validation("csv", "The file is empty"));
+    }
+    let column = |name: &str| {
+        header.iter().position(|h| h == name).ok_or_else(|| {
+            BackendError::validation("csv", format!("Missing '{}' column in header", name))
+        })
+    };
+    let (date_col, base_col, quote_col, rate_col) = (
+        column("date")?,
+        column("base")?,
+        column("quote")?,
+        column("rate")?,
+    );
+
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+    let mut imported = 0;
+    {
+        let mut upsert = tx
+            .prepare(
+                "INSERT INTO exchange_rates (base, quote, date, rate, source)
+                 VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT (base, quote, date)
+                 DO UPDATE SET rate = excluded.rate, source = excluded.source",
+            )
+            .context("Failed to prepare statement")?;
+
+        for record in reader.records() {
+            let record = record.map_err(csv_error)?;
+            if record.iter().all(str::is_empty) {
+                continue;
+            }
+            let line = record.position().map_or(0, |position| position.line());
+            let line_error = |err: BackendError| err.context(&format!("Line {}", line));
+            let cell = |col: usize| {
+                record
+                    .get(col)
+                    .ok_or_else(|| line_error(BackendError::validation("csv", "Too few columns")))
+            };
+
+            let date = cell(date_col)?;
+            let rate_text = cell(rate_col)?;
+            let rate: f64 = rate_text.parse().map_err(|_| {
+                line_error(BackendError::validation(
+                    "csv",
+                    format!("'{}' is not a valid rate", rate_text),
+                ))
+            })?;
+            validate_rate(date, rate).map_err(line_error)?;
+
+            upsert
+                .execute(params![
+                    cell(base_col)?,
+                    cell(quote_col)?,
+                    date,
+                    rate,
+                    source
+                ])
+                .map_err(|err| line_error(err.into()))?;
+            imported += 1;
+        }
+    }
+
+    tx.commit().context("Failed to commit exchange rates")?;
+    Ok(RateImport { imported })
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn convert_amount(
+    app: AppHandle,
+    db: State<'_, Database>,
+    amount: Money,
+    from: &str,
+    to: &str,
+    date: &str,
+) -> BackendResult<Conversion> {
+    let pivot = pivot_currency(&app);
+    let conn = db.reader().context("Failed to open database connection")?;
+    convert(&conn, amount, from, to, date, &pivot)
+}
+
+pub fn pivot_currency(app: &AppHandle) -> String {
+    get_setting::<String>(app, PIVOT_CURRENCY_SETTING)
+        .filter(|code| !code.is_empty())
+        .unwrap_or_else(|| DEFAULT_PIVOT_CURRENCY.to_string())
+}
+
+// Convert using the closest rate on or before `date`. A pair without rates of its own in
+// either direction is bridged through `pivot`. The result is rounded to the minor units of `to`.
+pub fn convert(
+    conn: &Connection,
+    amount: Money,
+    from: &str,
+    to: &str,
+    date: &str,
+    pivot: &str,
+) -> BackendResult<Conversion> {
+    let to_scale = currency::minor_unit(conn, to)
+        .context(&format!("Failed to look up currency {}", to))
+        .map_err(|err| err.with_field("to"))?;
+
+    let (found, via) = if from == to {
+        (
+            FoundRate {
+                rate: 1.0,
+                date: date.to_string(),
+            },
+            None,
+        )
+    } else if let Some(found) = find_rate(conn, from, to, date)? {
+        (found, None)
+    } else {
+        let legs = if from != pivot && to != pivot {
+            find_rate(conn, from, pivot, date)?.zip(find_rate(conn, pivot, to, date)?)
+        } else {
+            None
+        };
+        let (first, second) = legs.ok_or_else(|| {
+            BackendError::not_found(format!(
+                "No exchange rate from {} to {} on or before {}",
+                from, to, date
+            ))
+        })?;
+        (
+            FoundRate {
+                rate: first.rate * second.rate,
+                date: first.date.min(second.date),
+            },
+            Some(pivot.to_string()),
+        )
+    };
+
+    let major = amount.minor() as f64 / 10f64.powi(amount.scale() as i32);
+    Ok(Conversion {
+        amount: Money::from_f64(major * found.rate, to_scale)?,
+        rate: found.rate,
+        rate_date: found.date,
+        via,
+    })
+}
+
+// Latest rate for the pair on or before `date`, taken from whichever direction was recorded
+// more recently
+fn find_rate(
+    conn: &Connection,
+    base: &str,
+    quote: &str,
+    date: &str,
+) -> BackendResult<Option<FoundRate>> {
+    let latest = |base: &str, quote: &str| {
+        conn.query_row(
+            "SELECT rate, date FROM exchange_rates
+             WHERE base = ?1 AND quote = ?2 AND date <= ?3
+             ORDER BY date DESC LIMIT 1",
+            params![base, quote, date],
+            |row| {
+                Ok(FoundRate {
+                    rate: row.get(0)?,
+                    date: row.get(1)?,
+                })
+            },
+        )
+        .optional()
+        .context("Failed to look up exchange rate")
+    };
+
+    let direct = latest(base, quote)?;
+    let inverse = latest(quote, base)?.map(|found| FoundRate {
+        rate: 1.0 / found.rate,
+        date: found.date,
+    });
+
+    Ok(match (direct, inverse) {
+        (Some(direct), Some(inverse)) if inverse.date > direct.date => Some(inverse),
+        (Some(direct), _) => Some(direct),
+        (None, inverse) => inverse,
+    })
+}
+
+fn validate_rate(date: &str, rate: f64) -> BackendResult<()> {
+    if !is_iso_date(date) {
+        return Err(BackendError::validation(
+            "date",
+            format!("'{}' is not a YYYY-MM-DD date", date),
+        ));
+    }
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err(BackendError::validation(
+            "rate",
+            "Rate must be a positive number",
+        ));
+    }
+    Ok(())
+}
+
+fn rate_not_found(id: i64) -> BackendError {
+    BackendError::not_found(format!("Exchange rate {} not found", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::testing::{money, Fixture};
+
+    // (amount, rate date, via) of converting `amount` on `date` with USD as the pivot
+    fn convert_on(
+        fixture: &Fixture,
+        amount: &str,
+        from: &str,
+        to: &str,
+        date: &str,
+    ) -> BackendResult<(String, String, Option<String>)> {
+        convert(&fixture.reader(), money(amount), from, to, date, "USD")
+            .map(|c| (c.amount.to_string(), c.rate_date, c.via))
+    }
+
+    #[test]
+    fn conversion_uses_the_latest_rate_either_way_or_the_pivot() {
+        let fixture = Fixture::new();
+        add_exchange_rate(fixture.db(), "EUR", "USD", "2024-01-01", 1.1, None).unwrap();
+        add_exchange_rate(fixture.db(), "EUR", "USD", "2024-02-01", 1.2, None).unwrap();
+        add_exchange_rate(fixture.db(), "USD", "JPY", "2024-01-15", 150.0, None).unwrap();
+
+        let found = |amount: &str, from: &str, to: &str, date: &str| {
+            convert_on(&fixture, amount, from, to, date).unwrap()
+        };
+        assert_eq!(
+            found("10", "EUR", "USD", "2024-01-31"),
+            ("11.00".to_string(), "2024-01-01".to_string(), None)
+        );
+        assert_eq!(found("10", "EUR", "USD", "2024-02-01").0, "12.00");
+        // The stored pair read backwards, rounded to the minor units of the target
+        assert_eq!(found("100", "JPY", "USD", "2024-03-01").0, "0.67");
+        assert_eq!(
+            found("10", "EUR", "JPY", "2024-03-01"),
+            (
+                "1800".to_string(),
+                "2024-01-15".to_string(),
+                Some("USD".to_string())
+            )
+        );
+
+        assert!(convert_on(&fixture, "10", "EUR", "USD", "2023-12-31").is_err());
+        assert!(convert_on(&fixture, "10", "EUR", "GBP", "2024-03-01").is_err());
+    }
+
+    #[test]
+    fn importing_reads_quoted_fields_and_rejects_the_whole_file_on_a_bad_line() {
+        let fixture = Fixture::new();
+        let imported = import_exchange_rates(
+            fixture.db(),
+            "Rate,\"date\", base ,quote\n\"1,5\",2024-01-01,EUR,USD\n\n1.25,2024-01-02,EUR,USD\n",
+            Some("bank"),
+        );
+        // "1,5" stays one field, and is not a number
+        let err = imported.err().unwrap();
+        assert_eq!(err.message(), "Line 2: '1,5' is not a valid rate");
+
+        let imported = import_exchange_rates(
+            fixture.db(),
+            "Rate,\"date\", base ,quote\n\"1.5\",2024-01-01,EUR,USD\n\n1.25,2024-01-02,EUR,USD\n",
+            Some("bank"),
+        )
+        .unwrap();
+        assert_eq!(imported.imported, 2);
+        assert_eq!(
+            convert_on(&fixture, "10", "EUR", "USD", "2024-01-01")
+                .unwrap()
+                .0,
+            "15.00"
+        );
+
+        let bad = "date,base,quote,rate\n2024-01-03,EUR,USD,1.3\n2024-02-30,EUR,USD,1.4\n";
+        let err = import_exchange_rates(fixture.db(), bad, None)
+            .err()
+            .unwrap();
+        assert!(err.message().starts_with("Line 3: "));
+        let stored: i64 = fixture.query("SELECT COUNT(*) FROM exchange_rates", []);
+        assert_eq!(stored, 2);
+        assert!(import_exchange_rates(fixture.db(), "date,base,rate\n", None).is_err());
+        assert!(import_exchange_rates(fixture.db(), "", None).is_err());
+    }
+}
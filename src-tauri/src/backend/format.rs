@@ -0,0 +1,252 @@
+use crate::backend::currency::DISPLAY_SYMBOL_SQL;
+use crate::backend::db::Database;
+use crate::backend::error::{BackendError, BackendResult, Context};
+use crate::backend::money::Money;
+use rusqlite::{params, Connection, OptionalExtension};
+use tauri::State;
+
+// How amounts in one currency are written, as stored on its `currencies` row
+pub struct CurrencyFormat {
+    code: String,
+    symbol: String,
+    display_symbol: String,
+    minor_unit: u32,
+    symbol_after: bool,
+    symbol_spacing: bool,
+    decimal_separator: String,
+    group_separator: String,
+}
+
+impl CurrencyFormat {
+    pub fn load(conn: &Connection, code: &str) -> BackendResult<Self> {
+        conn.query_row(
+            &format!(
+                "SELECT c.code, c.symbol, {}, c.minor_unit, c.symbol_position, c.symbol_spacing,
+                     c.decimal_separator, c.group_separator
+                 FROM currencies c WHERE c.code = ?1",
+                DISPLAY_SYMBOL_SQL
+            ),
+            params![code],
+            |row| {
+                Ok(CurrencyFormat {
+                    code: row.get(0)?,
+                    symbol: row.get(1)?,
+                    display_symbol: row.get(2)?,
+                    minor_unit: row.get(3)?,
+                    symbol_after: row.get::<_, String>(4)? == "after",
+                    symbol_spacing: row.get(5)?,
+                    decimal_separator: row.get(6)?,
+                    group_separator: row.get(7)?,
+                })
+            },
+        )
+        .optional()
+        .context("Failed to look up currency")?
+        .ok_or_else(|| {
+            BackendError::not_found(format!("Currency {} not found", code)).with_field("currency")
+        })
+    }
+
+    // e.g. "-US$1,234.56" or "1 234,56 kr". `disambiguate` picks the display symbol, which is
+    // only different from the plain one when the plain one is shared with another currency.
+    pub fn format(&self, amount: Money, disambiguate: bool) -> BackendResult<String> {
+        let amount = amount
+            .to_scale(self.minor_unit)
+            .map_err(|err| BackendError::from(err).with_field("amount"))?;
+
+        let plain = amount
+            .checked_abs()
+            .ok_or_else(|| BackendError::validation("amount", "Amount is out of range"))?
+            .to_string();
+        let (int_part, frac_part) = match plain.split_once('.') {
+            Some((int_part, frac_part)) => (int_part, Some(frac_part)),
+            None => (plain.as_str(), None),
+        };
+
+        let mut number = group_digits(int_part, &self.group_separator);
+        if let Some(frac_part) = frac_part {
+            number.push_str(&self.decimal_separator);
+            number.push_str(frac_part);
+        }
+
+        let symbol = if disambiguate {
+            &self.display_symbol
+        } else {
+            &self.symbol
+        };
+        let space = if self.symbol_spacing { " " } else { "" };
+        let sign = if amount.is_negative() { "-" } else { "" };
+
+        Ok(if self.symbol_after {
+            format!("{}{}{}{}", sign, number, space, symbol)
+        } else {
+            format!("{}{}{}{}", sign, symbol, space, number)
+        })
+    }
+
+    // Accepts what `format` writes with either symbol, the code or no symbol at all, and a sign
+    // before or after the symbol. More decimals than the currency has are rejected, not rounded.
+    pub fn parse(&self, text: &str) -> BackendResult<Money> {
+        let invalid = || {
+            BackendError::validation(
+                "text",
+                format!("'{}' is not a valid {} amount", text, self.code),
+            )
+        };
+
+        // Returns the text after a leading sign and whether the sign was negative, if there was one
+        let strip_sign = |text: &str| {
+            if let Some(rest) = text.strip_prefix('-') {
+                Some((rest.trim_start().to_string(), true))
+            } else {
+                text.strip_prefix('+')
+                    .map(|rest| (rest.trim_start().to_string(), false))
+            }
+        };
+
+        let text = text.trim();
+        let (rest, negative) = match strip_sign(text) {
+            Some((rest, negative)) => (self.strip_symbol(&rest).to_string(), negative),
+            None => {
+                let rest = self.strip_symbol(text);
+                strip_sign(rest).unwrap_or_else(|| (rest.to_string(), false))
+            }
+        };
+
+        let mut digits = rest;
+        for separator in group_separators(&self.group_separator) {
+            digits = digits.replace(separator, "");
+        }
+        if self.decimal_separator != "." {
+            if digits.contains('.') {
+                return Err(invalid());
+            }
+            digits = digits.replace(self.decimal_separator.as_str(), ".");
+        }
+        if digits.is_empty() || digits.starts_with(['-', '+']) {
+            return Err(invalid());
+        }
+
+        let amount = Money::parse(&digits).map_err(|_| invalid())?;
+        let amount = amount
+            .to_scale(self.minor_unit)
+            .map_err(|err| BackendError::from(err).with_field("text"))?;
+        if !negative {
+            return Ok(amount);
+        }
+        amount
+            .checked_neg()
+            .ok_or_else(|| BackendError::validation("text", "Amount is out of range"))
+    }
+
+    // Remove one leading or trailing symbol, trying the longest candidates first so "US$" is
+    // not mistaken for "$" followed by garbage
+    fn strip_symbol<'a>(&self, text: &'a str) -> &'a str {
+        let mut candidates = [
+            self.display_symbol.as_str(),
+            self.symbol.as_str(),
+            self.code.as_str(),
+        ];
+        candidates.sort_by_key(|c| std::cmp::Reverse(c.len()));
+
+        for candidate in candidates.iter().filter(|c| !c.is_empty()) {
+            if let Some(rest) = text.strip_prefix(candidate) {
+                return rest.trim_start();
+            }
+            if let Some(rest) = text.strip_suffix(candidate) {
+                return rest.trim_end();
+            }
+        }
+        text
+    }
+}
+
+// Insert the separator every three digits from the right
+fn group_digits(digits: &str, separator: &str) -> String {
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3 * separator.len());
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push_str(separator);
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+// A space separator also matches the no-break spaces other apps put in copied amounts
+fn group_separators(separator: &str) -> Vec<&str> {
+    match separator {
+        "" => vec![],
+        " " => vec![" ", "\u{a0}", "\u{202f}"],
+        other => vec![other],
+    }
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn format_amount(
+    db: State<'_, Database>,
+    currency: &str,
+    amount: Money,
+    disambiguate: Option<bool>,
+) -> BackendResult<String> {
+    let conn = db.reader().context("Failed to open database connection")?;
+    CurrencyFormat::load(&conn, currency)?.format(amount, disambiguate.unwrap_or(true))
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn parse_amount(db: State<'_, Database>, currency: &str, text: &str) -> BackendResult<Money> {
+    let conn = db.reader().context("Failed to open database connection")?;
+    CurrencyFormat::load(&conn, currency)?.parse(text)
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn update_currency_format(
+    db: State<'_, Database>,
+    code: &str,
+    symbol_position: &str, // 'before', 'after'
+    symbol_spacing: bool,
+    decimal_separator: &str,
+    group_separator: &str,
+    disambiguated_symbol: Option<&str>,
+) -> BackendResult<()> {
+    let is_separator = |s: &str| {
+        !s.chars()
+            .any(|c| c.is_ascii_digit() || c == '-' || c == '+')
+    };
+    if decimal_separator.is_empty() || !is_separator(decimal_separator) {
+        return Err(BackendError::validation(
+            "decimal_separator",
+            "Decimal separator must be set and contain no digits or signs",
+        ));
+    }
+    if !is_separator(group_separator) || group_separator == decimal_separator {
+        return Err(BackendError::validation(
+            "group_separator",
+            "Group separator must differ from the decimal separator and contain no digits or signs",
+        ));
+    }
+
+    let conn = db.writer();
+    let updated = conn
+        .execute(
+            "UPDATE currencies SET symbol_position = ?1, symbol_spacing = ?2,
+                 decimal_separator = ?3, group_separator = ?4, disambiguated_symbol = ?5
+             WHERE code = ?6",
+            params![
+                symbol_position,
+                symbol_spacing,
+                decimal_separator,
+                group_separator,
+                disambiguated_symbol.filter(|s| !s.is_empty()),
+                code
+            ],
+        )
+        .context("Failed to update currency format")?;
+    if updated == 0 {
+        return Err(
+            BackendError::not_found(format!("Currency {} not found", code)).with_field("code"),
+        );
+    }
+
+    Ok(())
+}
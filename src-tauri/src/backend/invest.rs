@@ -0,0 +1,1248 @@
+use crate::backend::attachment;
+use crate::backend::balance;
+use crate::backend::currency;
+use crate::backend::date::{is_iso_date, today};
+use crate::backend::db::Database;
+use crate::backend::error::{BackendError, BackendResult, Context};
+use crate::backend::exchange_rate::{convert, pivot_currency};
+use crate::backend::invest_event::delete_holding_events;
+use crate::backend::money::{add, Money};
+use crate::backend::security::{latest_price, security_scale};
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use tauri::{AppHandle, State};
+
+// Quantities are fractional (funds, crypto); anything closer to zero than this is treated as zero
+const QUANTITY_EPSILON: f64 = 1e-9;
+
+// An invest account holds any number of securities, one `holdings` row each. The position of a
+// holding is derived from its buy and sell trades: every change to a trade replays all of the
+// holding's trades and splits in date order, rebuilding `invest_lots`, the realised gain of each
+// sale and the `quantity`, `avg_cost` and `cost_basis` columns of the holding. Prices, fees and
+// costs are in the security's currency.
+//
+// A trade's `amount` is the cash it moves in the account's currency, fee included: a buy takes it
+// out of the account's balance and a sale pays it in. Opening positions, reinvestments and trades
+// recorded before cash followed trades have none and leave the balance alone.
+
+#[derive(serde::Serialize)]
+pub struct Holding {
+    pub id: i64,
+    pub account_id: i64,
+    pub security_id: i64,
+    pub symbol: String,
+    pub name: String,
+    pub currency: String,
+    pub cost_method: String,
+    pub quantity: f64,
+    pub avg_cost: Money,
+    pub cost_basis: Money,
+    pub realised_gain: Money,
+    // Latest price on or before the valuation date; the rest is None while there is none
+    pub price: Option<Money>,
+    pub price_date: Option<String>,
+    pub market_value: Option<Money>,
+    pub unrealised_gain: Option<Money>,
+}
+
+#[derive(serde::Serialize)]
+pub struct InvestTrade {
+    pub id: i64,
+    pub holding_id: i64,
+    pub side: String,
+    pub date: String,
+    pub quantity: f64,
+    // Per unit
+    pub price: Money,
+    pub fee: Money,
+    // Proceeds after the fee minus the cost of the units sold; None for buys
+    pub realised_gain: Option<Money>,
+    // Cash paid or received in the account's currency; None when the trade moved no cash
+    pub amount: Option<Money>,
+    pub note: Option<String>,
+}
+
+// Units bought by one trade. `cost` covers the units still held, fee included.
+#[derive(serde::Serialize)]
+pub struct InvestLot {
+    pub trade_id: i64,
+    pub holding_id: i64,
+    pub date: String,
+    pub quantity: f64,
+    pub remaining: f64,
+    pub cost: Money,
+}
+
+// An invest account's cash balance plus its holdings at market value, in the account's currency
+#[derive(serde::Serialize)]
+pub struct InvestValuation {
+    pub account_id: i64,
+    pub currency: String,
+    pub date: String,
+    pub cash: Money,
+    pub holdings: Money,
+    pub total: Money,
+}
+
+// A lot while trades are being replayed
+struct OpenLot {
+    trade_id: i64,
+    date: String,
+    quantity: f64,
+    remaining: f64,
+    cost: i64,
+}
+
+// A holding's trades and splits replayed in date order
+struct Replay {
+    lots: Vec<OpenLot>,
+    // (trade id, realised gain) of each sale
+    gains: Vec<(i64, i64)>,
+}
+
+pub fn create_invest_trades_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS invest_trades (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            account_id INTEGER NOT NULL,
+            side TEXT NOT NULL CHECK( side IN ('buy', 'sell') ),
+            date TEXT NOT NULL,
+            quantity REAL NOT NULL CHECK( quantity > 0 ),
+            price INTEGER NOT NULL CHECK( price >= 0 ),
+            fee INTEGER NOT NULL DEFAULT 0 CHECK( fee >= 0 ),
+            realised_gain INTEGER,
+            note TEXT,
+            FOREIGN KEY (account_id) REFERENCES accounts(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS idx_invest_trades_account ON invest_trades (account_id, date);
+
+        CREATE TABLE IF NOT EXISTS invest_lots (
+            trade_id INTEGER PRIMARY KEY,
+            account_id INTEGER NOT NULL,
+            date TEXT NOT NULL,
+            quantity REAL NOT NULL,
+            remaining REAL NOT NULL,
+            cost INTEGER NOT NULL,
+            FOREIGN KEY (trade_id) REFERENCES invest_trades(id) ON DELETE CASCADE,
+            FOREIGN KEY (account_id) REFERENCES accounts(id) ON DELETE CASCADE
+        );
+
+        ALTER TABLE invest_accounts ADD COLUMN cost_method TEXT NOT NULL DEFAULT 'fifo'
+            CHECK( cost_method IN ('fifo', 'average') );
+        ALTER TABLE invest_accounts ADD COLUMN market_price INTEGER;
+        ALTER TABLE invest_accounts ADD COLUMN market_price_date TEXT;
+        ",
+    )
+}
+
+pub fn create_holdings_table(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS holdings (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            account_id INTEGER NOT NULL,
+            security_id INTEGER NOT NULL,
+            cost_method TEXT NOT NULL DEFAULT 'fifo' CHECK( cost_method IN ('fifo', 'average') ),
+            quantity REAL NOT NULL DEFAULT 0,
+            avg_cost INTEGER NOT NULL DEFAULT 0,
+            cost_basis INTEGER NOT NULL DEFAULT 0,
+            UNIQUE (account_id, security_id),
+            FOREIGN KEY (account_id) REFERENCES accounts(id) ON DELETE CASCADE,
+            FOREIGN KEY (security_id) REFERENCES securities(id) ON DELETE RESTRICT
+        );
+
+        ALTER TABLE invest_trades
+            ADD COLUMN holding_id INTEGER REFERENCES holdings(id) ON DELETE CASCADE;
+        ALTER TABLE invest_lots
+            ADD COLUMN holding_id INTEGER REFERENCES holdings(id) ON DELETE CASCADE;
+        CREATE INDEX IF NOT EXISTS idx_invest_trades_holding ON invest_trades (holding_id, date);
+        ",
+    )
+}
+
+// Trades recorded until now keep a NULL amount, since their cash was entered by hand
+pub fn add_trade_amounts(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE invest_trades ADD COLUMN amount INTEGER;")
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn add_holding(
+    db: State<'_, Database>,
+    account_id: i64,
+    security_id: i64,
+    cost_method: Option<&str>,
+) -> BackendResult<i64> {
+    let cost_method = cost_method.unwrap_or("fifo");
+    validate_cost_method(cost_method)?;
+
+    let conn = db.writer();
+    let is_invest: bool = conn
+        .query_row(
+            "SELECT EXISTS (SELECT 1 FROM invest_accounts WHERE account_id = ?1)",
+            params![account_id],
+            |row| row.get(0),
+        )
+        .context("Failed to look up invest account")?;
+    if !is_invest {
+        return Err(
+            BackendError::not_found(format!("Invest account {} not found", account_id))
+                .with_field("account_id"),
+        );
+    }
+
+    conn.execute(
+        "INSERT INTO holdings (account_id, security_id, cost_method) VALUES (?1, ?2, ?3)",
+        params![account_id, security_id, cost_method],
+    )
+    .context("Failed to insert holding")?;
+
+    Ok(conn.last_insert_rowid())
+}
+
+// Every holding of the account, valued at the latest prices on or before `date` (today when
+// not given)
+#[tauri::command(rename_all = "snake_case")]
+pub fn get_holdings(
+    db: State<'_, Database>,
+    account_id: i64,
+    date: Option<&str>,
+) -> BackendResult<Vec<Holding>> {
+    let conn = db.reader().context("Failed to open database connection")?;
+    let date = match date {
+        Some(date) => date.to_string(),
+        None => today(&conn)?,
+    };
+    holdings(&conn, account_id, &date)
+}
+
+// Trades, lots and events go with the holding, along with the events' transactions
+#[tauri::command(rename_all = "snake_case")]
+pub fn delete_holding(db: State<'_, Database>, id: i64) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    let trade_ids = {
+        let mut stmt = tx
+            .prepare("SELECT id FROM invest_trades WHERE holding_id = ?1")
+            .context("Failed to prepare statement")?;
+        let rows = stmt
+            .query_map(params![id], |row| row.get::<_, i64>(0))
+            .context("Failed to query trades")?
+            .collect::<Result<Vec<_>>>()
+            .context("Failed to parse trade row")?;
+        rows
+    };
+    for trade_id in trade_ids {
+        balance::apply_trade_effects(&tx, trade_id, -1)
+            .context("Failed to update account balance")?;
+    }
+    delete_holding_events(&tx, id)?;
+    let deleted = tx
+        .execute("DELETE FROM holdings WHERE id = ?1", params![id])
+        .context("Failed to delete holding")?;
+    if deleted == 0 {
+        return Err(holding_not_found(id));
+    }
+    let orphans = attachment::take_orphans(&tx)?;
+
+    tx.commit().context("Failed to commit holding deletion")?;
+    attachment::remove_files(&db, &orphans)?;
+    Ok(())
+}
+
+// Switch between 'fifo' and 'average' and recompute every sale's realised gain with it
+#[tauri::command(rename_all = "snake_case")]
+pub fn set_cost_method(
+    db: State<'_, Database>,
+    holding_id: i64,
+    cost_method: &str,
+) -> BackendResult<()> {
+    validate_cost_method(cost_method)?;
+
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    let updated = tx
+        .execute(
+            "UPDATE holdings SET cost_method = ?1 WHERE id = ?2",
+            params![cost_method, holding_id],
+        )
+        .context("Failed to update cost method")?;
+    if updated == 0 {
+        return Err(holding_not_found(holding_id).with_field("holding_id"));
+    }
+    replay_trades(&tx, holding_id)?;
+
+    tx.commit().context("Failed to commit cost method")?;
+    Ok(())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+#[allow(clippy::too_many_arguments)]
+pub fn add_invest_trade(
+    db: State<'_, Database>,
+    holding_id: i64,
+    side: &str,
+    date: &str,
+    quantity: f64,
+    price: Money,
+    fee: Option<Money>,
+    amount: Option<Money>,
+    note: Option<&str>,
+) -> BackendResult<i64> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    let (account_id, scale) = holding_account(&tx, holding_id)?;
+    let (price, fee) = validate_trade(side, date, quantity, price, fee, scale)?;
+    let amount = trade_amount(&tx, holding_id, side, quantity, price, fee, amount)?;
+    record_opening_position(&tx, holding_id, date)?;
+
+    tx.execute(
+        "INSERT INTO invest_trades
+             (account_id, holding_id, side, date, quantity, price, fee, amount, note)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            account_id,
+            holding_id,
+            side,
+            date,
+            quantity,
+            price.minor(),
+            fee.minor(),
+            amount.minor(),
+            note
+        ],
+    )
+    .context("Failed to insert trade")?;
+    let trade_id = tx.last_insert_rowid();
+    balance::apply_trade_effects(&tx, trade_id, 1).context("Failed to update account balance")?;
+    replay_trades(&tx, holding_id)?;
+
+    tx.commit().context("Failed to commit trade")?;
+    Ok(trade_id)
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn get_invest_trades(
+    db: State<'_, Database>,
+    holding_id: i64,
+) -> BackendResult<Vec<InvestTrade>> {
+    let conn = db.reader().context("Failed to open database connection")?;
+    let (_, scale) = holding_account(&conn, holding_id)?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT t.id, t.holding_id, t.side, t.date, t.quantity, t.price, t.fee,
+                 t.realised_gain, t.note, t.amount, cur.minor_unit
+             FROM invest_trades t
+             JOIN accounts a ON a.id = t.account_id
+             JOIN currencies cur ON cur.code = a.currency
+             WHERE t.holding_id = ?1
+             ORDER BY t.date, t.id",
+        )
+        .context("Failed to prepare statement")?;
+
+    let trade_iter = stmt
+        .query_map(params![holding_id], |row| {
+            Ok(InvestTrade {
+                id: row.get(0)?,
+                holding_id: row.get(1)?,
+                side: row.get(2)?,
+                date: row.get(3)?,
+                quantity: row.get(4)?,
+                price: Money::from_minor(row.get(5)?, scale),
+                fee: Money::from_minor(row.get(6)?, scale),
+                realised_gain: row
+                    .get::<_, Option<i64>>(7)?
+                    .map(|minor| Money::from_minor(minor, scale)),
+                amount: {
+                    let account_scale = row.get(10)?;
+                    row.get::<_, Option<i64>>(9)?
+                        .map(|minor| Money::from_minor(minor, account_scale))
+                },
+                note: row.get(8)?,
+            })
+        })
+        .context("Failed to query trades")?;
+
+    let mut trades = Vec::new();
+    for trade in trade_iter {
+        trades.push(trade.context("Failed to parse trade row")?);
+    }
+
+    Ok(trades)
+}
+
+#[tauri::command(rename_all = "snake_case")]
+#[allow(clippy::too_many_arguments)]
+pub fn update_invest_trade(
+    db: State<'_, Database>,
+    id: i64,
+    side: &str,
+    date: &str,
+    quantity: f64,
+    price: Money,
+    fee: Option<Money>,
+    amount: Option<Money>,
+    note: Option<&str>,
+) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    let holding_id = trade_holding(&tx, id)?;
+    ensure_plain_trade(&tx, id)?;
+    let (_, scale) = holding_account(&tx, holding_id)?;
+    let (price, fee) = validate_trade(side, date, quantity, price, fee, scale)?;
+    let amount = trade_amount(&tx, holding_id, side, quantity, price, fee, amount)?;
+
+    balance::apply_trade_effects(&tx, id, -1).context("Failed to update account balance")?;
+    tx.execute(
+        "UPDATE invest_trades SET side = ?1, date = ?2, quantity = ?3, price = ?4, fee = ?5,
+             amount = ?6, note = ?7
+         WHERE id = ?8",
+        params![
+            side,
+            date,
+            quantity,
+            price.minor(),
+            fee.minor(),
+            amount.minor(),
+            note,
+            id
+        ],
+    )
+    .context("Failed to update trade")?;
+    balance::apply_trade_effects(&tx, id, 1).context("Failed to update account balance")?;
+    replay_trades(&tx, holding_id)?;
+
+    tx.commit().context("Failed to commit trade")?;
+    Ok(())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn delete_invest_trade(db: State<'_, Database>, id: i64) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    let holding_id = trade_holding(&tx, id)?;
+    ensure_plain_trade(&tx, id)?;
+    balance::apply_trade_effects(&tx, id, -1).context("Failed to update account balance")?;
+    tx.execute("DELETE FROM invest_trades WHERE id = ?1", params![id])
+        .context("Failed to delete trade")?;
+    replay_trades(&tx, holding_id)?;
+
+    tx.commit().context("Failed to commit trade deletion")?;
+    Ok(())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn get_invest_lots(db: State<'_, Database>, holding_id: i64) -> BackendResult<Vec<InvestLot>> {
+    let conn = db.reader().context("Failed to open database connection")?;
+    let (_, scale) = holding_account(&conn, holding_id)?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT trade_id, holding_id, date, quantity, remaining, cost
+             FROM invest_lots WHERE holding_id = ?1
+             ORDER BY date, trade_id",
+        )
+        .context("Failed to prepare statement")?;
+
+    let lot_iter = stmt
+        .query_map(params![holding_id], |row| {
+            Ok(InvestLot {
+                trade_id: row.get(0)?,
+                holding_id: row.get(1)?,
+                date: row.get(2)?,
+                quantity: row.get(3)?,
+                remaining: row.get(4)?,
+                cost: Money::from_minor(row.get(5)?, scale),
+            })
+        })
+        .context("Failed to query lots")?;
+
+    let mut lots = Vec::new();
+    for lot in lot_iter {
+        lots.push(lot.context("Failed to parse lot row")?);
+    }
+
+    Ok(lots)
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn get_invest_valuation(
+    app: AppHandle,
+    db: State<'_, Database>,
+    account_id: i64,
+    date: Option<&str>,
+) -> BackendResult<InvestValuation> {
+    let pivot = pivot_currency(&app);
+    let conn = db.reader().context("Failed to open database connection")?;
+    let date = match date {
+        Some(date) => date.to_string(),
+        None => today(&conn)?,
+    };
+
+    let (currency, balance): (String, i64) = conn
+        .query_row(
+            "SELECT a.currency, a.balance FROM accounts a
+             JOIN invest_accounts i ON i.account_id = a.id
+             WHERE a.id = ?1",
+            params![account_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .context("Failed to look up invest account")?
+        .ok_or_else(|| {
+            BackendError::not_found(format!("Invest account {} not found", account_id))
+                .with_field("account_id")
+        })?;
+    let scale = currency::minor_unit(&conn, &currency)
+        .context(&format!("Failed to look up currency {}", currency))?;
+
+    let cash = Money::from_minor(balance, scale);
+    let holdings = holdings_value(&conn, account_id, &currency, &date, &pivot)?;
+    Ok(InvestValuation {
+        account_id,
+        currency,
+        date,
+        cash,
+        holdings,
+        total: add(cash, holdings)?,
+    })
+}
+
+pub fn holdings(conn: &Connection, account_id: i64, date: &str) -> BackendResult<Vec<Holding>> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT h.id, h.security_id, s.symbol, s.name, s.currency, cur.minor_unit,
+                 h.cost_method, h.quantity, h.avg_cost, h.cost_basis,
+                 (SELECT COALESCE(SUM(realised_gain), 0) FROM invest_trades
+                  WHERE holding_id = h.id)
+             FROM holdings h
+             JOIN securities s ON s.id = h.security_id
+             JOIN currencies cur ON cur.code = s.currency
+             WHERE h.account_id = ?1
+             ORDER BY s.symbol",
+        )
+        .context("Failed to prepare statement")?;
+
+    let rows = stmt
+        .query_map(params![account_id], |row| {
+            let scale: u32 = row.get(5)?;
+            let money =
+                |idx: usize| -> Result<Money> { Ok(Money::from_minor(row.get(idx)?, scale)) };
+            Ok(Holding {
+                id: row.get(0)?,
+                account_id,
+                security_id: row.get(1)?,
+                symbol: row.get(2)?,
+                name: row.get(3)?,
+                currency: row.get(4)?,
+                cost_method: row.get(6)?,
+                quantity: row.get(7)?,
+                avg_cost: money(8)?,
+                cost_basis: money(9)?,
+                realised_gain: money(10)?,
+                price: None,
+                price_date: None,
+                market_value: None,
+                unrealised_gain: None,
+            })
+        })
+        .context("Failed to query holdings")?;
+
+    let mut holdings = Vec::new();
+    for holding in rows {
+        let mut holding = holding.context("Failed to parse holding row")?;
+        if let Some((price, price_date)) = latest_price(conn, holding.security_id, date)? {
+            let value = market_value(holding.quantity, price);
+            holding.price = Some(price);
+            holding.price_date = Some(price_date);
+            holding.market_value = Some(value);
+            holding.unrealised_gain =
+                Some(value.checked_sub(holding.cost_basis).ok_or_else(|| {
+                    BackendError::validation("price", "Unrealised gain is out of range")
+                })?);
+        }
+        holdings.push(holding);
+    }
+
+    Ok(holdings)
+}
+
+// The account's holdings as they stood at the end of `date`, at the latest prices on or before
+// it, converted into `currency`. A holding without any price counts at its cost basis.
+pub fn holdings_value(
+    conn: &Connection,
+    account_id: i64,
+    currency: &str,
+    date: &str,
+    pivot: &str,
+) -> BackendResult<Money> {
+    let scale = currency::minor_unit(conn, currency)
+        .context(&format!("Failed to look up currency {}", currency))
+        .map_err(|err| err.with_field("currency"))?;
+
+    let mut total = Money::zero(scale);
+    for holding in holdings(conn, account_id, date)? {
+        let (quantity, cost_basis) = position_on(conn, holding.id, date)?;
+        if quantity.abs() < QUANTITY_EPSILON {
+            continue;
+        }
+        let value = match holding.price {
+            Some(price) => market_value(quantity, price),
+            None => Money::from_minor(cost_basis, holding.cost_basis.scale()),
+        };
+        let conversion = convert(conn, value, &holding.currency, currency, date, pivot)
+            .map_err(|err| err.context(&format!("Failed to value {}", holding.symbol)))?;
+        total = add(total, conversion.amount)?;
+    }
+
+    Ok(total)
+}
+
+// Rebuild the holding's lots, realised gains and position from its trades and splits
+pub(crate) fn replay_trades(conn: &Connection, holding_id: i64) -> BackendResult<()> {
+    let (account_id, average): (i64, bool) = conn
+        .query_row(
+            "SELECT account_id, cost_method = 'average' FROM holdings WHERE id = ?1",
+            params![holding_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .context("Failed to look up cost method")?;
+
+    let Replay { lots, gains } = replay(conn, holding_id, average, None)?;
+
+    conn.execute(
+        "UPDATE invest_trades SET realised_gain = NULL WHERE holding_id = ?1",
+        params![holding_id],
+    )
+    .context("Failed to clear realised gains")?;
+    for (trade_id, gain) in gains {
+        conn.execute(
+            "UPDATE invest_trades SET realised_gain = ?1 WHERE id = ?2",
+            params![gain, trade_id],
+        )
+        .context("Failed to store realised gain")?;
+    }
+
+    conn.execute(
+        "DELETE FROM invest_lots WHERE holding_id = ?1",
+        params![holding_id],
+    )
+    .context("Failed to clear lots")?;
+    let mut insert = conn
+        .prepare(
+            "INSERT INTO invest_lots
+                 (trade_id, account_id, holding_id, date, quantity, remaining, cost)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )
+        .context("Failed to prepare statement")?;
+    for lot in &lots {
+        insert
+            .execute(params![
+                lot.trade_id,
+                account_id,
+                holding_id,
+                lot.date,
+                lot.quantity,
+                lot.remaining,
+                lot.cost
+            ])
+            .context("Failed to store lot")?;
+    }
+
+    let (quantity, cost_basis) = lots_position(&lots);
+    let avg_cost = if quantity > QUANTITY_EPSILON {
+        (cost_basis as f64 / quantity).round() as i64
+    } else {
+        0
+    };
+    conn.execute(
+        "UPDATE holdings SET quantity = ?1, avg_cost = ?2, cost_basis = ?3 WHERE id = ?4",
+        params![quantity, avg_cost, cost_basis, holding_id],
+    )
+    .context("Failed to update holding")?;
+
+    Ok(())
+}
+
+// Quantity and cost basis of the holding at the end of `date`, from its trades and splits up to
+// then. A position entered by hand, without any trades, is taken as it stands.
+fn position_on(conn: &Connection, holding_id: i64, date: &str) -> BackendResult<(f64, i64)> {
+    let (average, quantity, cost_basis, traded): (bool, f64, i64, bool) = conn
+        .query_row(
+            "SELECT cost_method = 'average', quantity, cost_basis,
+                 EXISTS (SELECT 1 FROM invest_trades WHERE holding_id = h.id)
+             FROM holdings h WHERE id = ?1",
+            params![holding_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .context("Failed to look up holding")?;
+    if !traded {
+        return Ok((quantity, cost_basis));
+    }
+
+    let replay = replay(conn, holding_id, average, Some(date))?;
+    Ok(lots_position(&replay.lots))
+}
+
+// Replay the holding's trades and splits up to and including `until`, or all of them. A split
+// takes effect before any trade on its date.
+fn replay(
+    conn: &Connection,
+    holding_id: i64,
+    average: bool,
+    until: Option<&str>,
+) -> BackendResult<Replay> {
+    let trades = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, side, date, quantity, price, fee FROM (
+                     SELECT id, side, date, quantity, price, fee, 1 AS step
+                     FROM invest_trades WHERE holding_id = ?1
+                     UNION ALL
+                     SELECT e.transaction_id, 'split', substr(t.date, 1, 10), e.ratio, 0, 0, 0
+                     FROM invest_events e
+                     JOIN transactions t ON t.id = e.transaction_id
+                     WHERE e.holding_id = ?1 AND e.kind = 'split'
+                 )
+                 WHERE ?2 IS NULL OR date <= ?2
+                 ORDER BY date, step, id",
+            )
+            .context("Failed to prepare statement")?;
+        let rows = stmt
+            .query_map(params![holding_id, until], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, f64>(3)?,
+                    row.get::<_, i64>(4)?,
+                    row.get::<_, i64>(5)?,
+                ))
+            })
+            .context("Failed to query trades")?
+            .collect::<Result<Vec<_>>>()
+            .context("Failed to parse trade row")?;
+        rows
+    };
+
+    let mut lots: Vec<OpenLot> = Vec::new();
+    let mut gains = Vec::new();
+    for (trade_id, side, date, quantity, price, fee) in trades {
+        if side == "split" {
+            // `quantity` is the number of new units per old one; the cost stays the same
+            for lot in lots.iter_mut() {
+                lot.quantity *= quantity;
+                lot.remaining *= quantity;
+            }
+            continue;
+        }
+
+        let gross = (quantity * price as f64).round() as i64;
+        if side == "buy" {
+            lots.push(OpenLot {
+                trade_id,
+                date,
+                quantity,
+                remaining: quantity,
+                cost: gross + fee,
+            });
+            continue;
+        }
+
+        let held: f64 = lots.iter().map(|lot| lot.remaining).sum();
+        if quantity > held + QUANTITY_EPSILON {
+            return Err(BackendError::validation(
+                "quantity",
+                format!(
+                    "The sale on {} is for {} units but only {} are held then",
+                    date, quantity, held
+                ),
+            ));
+        }
+
+        let mut cost_sold = 0;
+        if average {
+            // Every lot gives up the same share of its units and cost
+            let kept = 1.0 - quantity / held;
+            for lot in lots.iter_mut() {
+                cost_sold += reduce_lot(lot, lot.remaining * kept);
+            }
+        } else {
+            let mut to_sell = quantity;
+            for lot in lots.iter_mut().filter(|lot| lot.remaining > 0.0) {
+                if to_sell <= QUANTITY_EPSILON {
+                    break;
+                }
+                let sold = to_sell.min(lot.remaining);
+                to_sell -= sold;
+                cost_sold += reduce_lot(lot, lot.remaining - sold);
+            }
+        }
+        gains.push((trade_id, gross - fee - cost_sold));
+    }
+
+    Ok(Replay { lots, gains })
+}
+
+// Units still held and their cost
+fn lots_position(lots: &[OpenLot]) -> (f64, i64) {
+    (
+        lots.iter().map(|lot| lot.remaining).sum(),
+        lots.iter().map(|lot| lot.cost).sum(),
+    )
+}
+
+// Shrink a lot to `kept` units, returning the cost of the units removed. The cost kept is
+// rounded and the rest goes with the sale, so costs always add up exactly.
+fn reduce_lot(lot: &mut OpenLot, kept: f64) -> i64 {
+    let kept = if kept < QUANTITY_EPSILON { 0.0 } else { kept };
+    let cost_kept = if lot.remaining > 0.0 {
+        (lot.cost as f64 * kept / lot.remaining).round() as i64
+    } else {
+        0
+    };
+    let cost_removed = lot.cost - cost_kept;
+    lot.remaining = kept;
+    lot.cost = cost_kept;
+    cost_removed
+}
+
+fn market_value(quantity: f64, price: Money) -> Money {
+    Money::from_minor(
+        (quantity * price.minor() as f64).round() as i64,
+        price.scale(),
+    )
+}
+
+// A position carried over from before holdings had trades becomes an opening buy at its average
+// cost, so the first trade adds to it instead of replacing it
+pub(crate) fn record_opening_position(
+    conn: &Connection,
+    holding_id: i64,
+    date: &str,
+) -> BackendResult<()> {
+    conn.execute(
+        "INSERT INTO invest_trades
+             (account_id, holding_id, side, date, quantity, price, fee, note)
+         SELECT account_id, id, 'buy', ?2, quantity, avg_cost, 0, 'Opening position'
+         FROM holdings
+         WHERE id = ?1 AND quantity > 0
+             AND NOT EXISTS (SELECT 1 FROM invest_trades WHERE holding_id = ?1)",
+        params![holding_id, date],
+    )
+    .context("Failed to record opening position")?;
+    Ok(())
+}
+
+fn validate_trade(
+    side: &str,
+    date: &str,
+    quantity: f64,
+    price: Money,
+    fee: Option<Money>,
+    scale: u32,
+) -> BackendResult<(Money, Money)> {
+    if !matches!(side, "buy" | "sell") {
+        return Err(BackendError::validation(
+            "side",
+            format!("Unsupported trade side '{}'", side),
+        ));
+    }
+    if !is_iso_date(date) {
+        return Err(BackendError::validation(
+            "date",
+            format!("'{}' is not a YYYY-MM-DD date", date),
+        ));
+    }
+    if !quantity.is_finite() || quantity <= 0.0 {
+        return Err(BackendError::validation(
+            "quantity",
+            "Quantity must be a positive number",
+        ));
+    }
+
+    let price = to_scale(price, scale, "price")?;
+    if price.is_negative() {
+        return Err(BackendError::validation(
+            "price",
+            "Price must not be negative",
+        ));
+    }
+    let fee = to_scale(fee.unwrap_or_default(), scale, "fee")?;
+    if fee.is_negative() {
+        return Err(BackendError::validation("fee", "Fee must not be negative"));
+    }
+
+    Ok((price, fee))
+}
+
+// The cash a trade moves in the account's currency. Without an amount it is the units' cost plus
+// the fee for a buy, or less the fee for a sale, if the security is in the account's currency.
+fn trade_amount(
+    conn: &Connection,
+    holding_id: i64,
+    side: &str,
+    quantity: f64,
+    price: Money,
+    fee: Money,
+    amount: Option<Money>,
+) -> BackendResult<Money> {
+    let (currency, security_currency): (String, String) = conn
+        .query_row(
+            "SELECT a.currency, s.currency FROM holdings h
+             JOIN accounts a ON a.id = h.account_id
+             JOIN securities s ON s.id = h.security_id
+             WHERE h.id = ?1",
+            params![holding_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .context("Failed to look up holding currencies")?;
+
+    let amount = match amount {
+        Some(amount) => {
+            let scale = currency::minor_unit(conn, &currency)
+                .context(&format!("Failed to look up currency {}", currency))?;
+            to_scale(amount, scale, "amount")?
+        }
+        None if security_currency == currency => {
+            let gross = (quantity * price.minor() as f64).round() as i64;
+            let minor = if side == "buy" {
+                gross.checked_add(fee.minor())
+            } else {
+                gross.checked_sub(fee.minor())
+            };
+            let minor = minor.ok_or_else(|| {
+                BackendError::validation("quantity", "Trade amount is out of range")
+            })?;
+            Money::from_minor(minor, price.scale())
+        }
+        None => {
+            return Err(BackendError::validation(
+                "amount",
+                format!(
+                    "A trade in {} needs the amount in {}",
+                    security_currency, currency
+                ),
+            ))
+        }
+    };
+    if amount.is_negative() {
+        return Err(BackendError::validation(
+            "amount",
+            "Amount must not be negative",
+        ));
+    }
+    Ok(amount)
+}
+
+fn validate_cost_method(cost_method: &str) -> BackendResult<()> {
+    if !matches!(cost_method, "fifo" | "average") {
+        return Err(BackendError::validation(
+            "cost_method",
+            format!("Unsupported cost method '{}'", cost_method),
+        ));
+    }
+    Ok(())
+}
+
+// The holding's account and the minor units of its security's currency
+pub(crate) fn holding_account(conn: &Connection, holding_id: i64) -> BackendResult<(i64, u32)> {
+    let (account_id, security_id): (i64, i64) = conn
+        .query_row(
+            "SELECT account_id, security_id FROM holdings WHERE id = ?1",
+            params![holding_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .context("Failed to look up holding")?
+        .ok_or_else(|| holding_not_found(holding_id).with_field("holding_id"))?;
+
+    Ok((account_id, security_scale(conn, security_id)?))
+}
+
+fn trade_holding(conn: &Connection, id: i64) -> BackendResult<i64> {
+    conn.query_row(
+        "SELECT holding_id FROM invest_trades WHERE id = ?1",
+        params![id],
+        |row| row.get(0),
+    )
+    .optional()
+    .context("Failed to look up trade")?
+    .ok_or_else(|| BackendError::not_found(format!("Trade {} not found", id)))
+}
+
+pub(crate) fn to_scale(amount: Money, scale: u32, field: &str) -> BackendResult<Money> {
+    amount
+        .to_scale(scale)
+        .map_err(|err| BackendError::from(err).with_field(field))
+}
+
+// Trades bought by a reinvestment are edited through the event
+fn ensure_plain_trade(conn: &Connection, id: i64) -> BackendResult<()> {
+    let reinvested: bool = conn
+        .query_row(
+            "SELECT EXISTS (SELECT 1 FROM invest_events WHERE trade_id = ?1)",
+            params![id],
+            |row| row.get(0),
+        )
+        .context("Failed to look up trade")?;
+    if reinvested {
+        return Err(BackendError::validation(
+            "id",
+            "This trade belongs to a reinvestment; change it with update_invest_event",
+        ));
+    }
+    Ok(())
+}
+
+fn holding_not_found(id: i64) -> BackendError {
+    BackendError::not_found(format!("Holding {} not found", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::invest_event::add_invest_event;
+    use crate::backend::security::{add_security_price, create_security};
+    use crate::backend::testing::{money, Fixture};
+
+    // A fixture holding one USD security in a broker account, returned with the holding's id
+    fn holding(cost_method: &str) -> (Fixture, i64) {
+        let fixture = Fixture::new();
+        let account = fixture.account("broker", "invest", "USD", "0");
+        let security = create_security(fixture.db(), "VT", "VT", "USD", "etf").unwrap();
+        let holding = add_holding(fixture.db(), account, security, Some(cost_method)).unwrap();
+        (fixture, holding)
+    }
+
+    fn trade(
+        fixture: &Fixture,
+        holding: i64,
+        side: &str,
+        date: &str,
+        quantity: f64,
+        price: &str,
+    ) -> BackendResult<i64> {
+        add_invest_trade(
+            fixture.db(),
+            holding,
+            side,
+            date,
+            quantity,
+            money(price),
+            None,
+            None,
+            None,
+        )
+    }
+
+    fn split(fixture: &Fixture, holding: i64, date: &str, ratio: f64) {
+        let ledger = fixture.ledger("USD");
+        add_invest_event(
+            fixture.db(),
+            ledger,
+            holding,
+            "split",
+            date,
+            None,
+            Some(ratio),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+    }
+
+    // (quantity, cost basis) of the holding
+    fn position(fixture: &Fixture, holding: i64) -> (f64, String) {
+        let (quantity, cost_basis): (f64, i64) = fixture
+            .reader()
+            .query_row(
+                "SELECT quantity, cost_basis FROM holdings WHERE id = ?1",
+                params![holding],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        (quantity, Money::from_minor(cost_basis, 2).to_string())
+    }
+
+    fn realised_gain(fixture: &Fixture, trade_id: i64) -> String {
+        let gain = fixture.query(
+            "SELECT realised_gain FROM invest_trades WHERE id = ?1",
+            params![trade_id],
+        );
+        Money::from_minor(gain, 2).to_string()
+    }
+
+    #[test]
+    fn fifo_sells_the_oldest_units_first() {
+        let (fixture, holding) = holding("fifo");
+        trade(&fixture, holding, "buy", "2024-01-01", 10.0, "100").unwrap();
+        trade(&fixture, holding, "buy", "2024-02-01", 10.0, "200").unwrap();
+        let sale = trade(&fixture, holding, "sell", "2024-03-01", 15.0, "300").unwrap();
+
+        // The first lot and half the second are sold: 1,000 + 1,000 of cost
+        assert_eq!(position(&fixture, holding), (5.0, "1000.00".to_string()));
+        assert_eq!(realised_gain(&fixture, sale), "2500.00");
+    }
+
+    #[test]
+    fn average_sells_every_lot_in_proportion() {
+        let (fixture, holding) = holding("average");
+        trade(&fixture, holding, "buy", "2024-01-01", 10.0, "100").unwrap();
+        trade(&fixture, holding, "buy", "2024-02-01", 10.0, "200").unwrap();
+        let sale = trade(&fixture, holding, "sell", "2024-03-01", 15.0, "300").unwrap();
+
+        // Three quarters of the 3,000 total cost goes with the sale
+        assert_eq!(position(&fixture, holding), (5.0, "750.00".to_string()));
+        assert_eq!(realised_gain(&fixture, sale), "2250.00");
+    }
+
+    #[test]
+    fn splits_apply_before_trades_on_the_same_day() {
+        let (fixture, holding) = holding("fifo");
+        trade(&fixture, holding, "buy", "2024-01-01", 10.0, "100").unwrap();
+        split(&fixture, holding, "2024-02-01", 2.0);
+        let sale = trade(&fixture, holding, "sell", "2024-02-01", 15.0, "60").unwrap();
+
+        // 20 units costing 1,000 after the split, of which 15 are sold
+        assert_eq!(position(&fixture, holding), (5.0, "250.00".to_string()));
+        assert_eq!(realised_gain(&fixture, sale), "150.00");
+    }
+
+    #[test]
+    fn selling_more_than_is_held_fails() {
+        let (fixture, holding) = holding("fifo");
+        trade(&fixture, holding, "buy", "2024-01-01", 10.0, "100").unwrap();
+
+        assert!(trade(&fixture, holding, "sell", "2023-12-31", 1.0, "100").is_err());
+        assert!(trade(&fixture, holding, "sell", "2024-01-02", 11.0, "100").is_err());
+        assert_eq!(position(&fixture, holding), (10.0, "1000.00".to_string()));
+    }
+
+    #[test]
+    fn holdings_are_valued_at_the_quantity_held_on_the_date() {
+        let (fixture, holding) = holding("fifo");
+        let (account, security): (i64, i64) = fixture
+            .reader()
+            .query_row(
+                "SELECT account_id, security_id FROM holdings WHERE id = ?1",
+                params![holding],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        trade(&fixture, holding, "buy", "2024-01-01", 10.0, "100").unwrap();
+        split(&fixture, holding, "2024-02-01", 2.0);
+        trade(&fixture, holding, "sell", "2024-03-01", 5.0, "60").unwrap();
+        add_security_price(fixture.db(), security, "2023-12-01", money("50"), None).unwrap();
+
+        let value = |date: &str| {
+            holdings_value(&fixture.reader(), account, "USD", date, "USD")
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(value("2023-12-31"), "0.00");
+        assert_eq!(value("2024-01-15"), "500.00");
+        assert_eq!(value("2024-02-15"), "1000.00");
+        assert_eq!(value("2024-03-01"), "750.00");
+    }
+
+    fn cash(fixture: &Fixture, holding: i64) -> String {
+        let balance = fixture.query(
+            "SELECT a.balance FROM holdings h JOIN accounts a ON a.id = h.account_id
+             WHERE h.id = ?1",
+            params![holding],
+        );
+        Money::from_minor(balance, 2).to_string()
+    }
+
+    #[test]
+    fn trades_move_the_accounts_cash() {
+        let (fixture, holding) = holding("fifo");
+        add_invest_trade(
+            fixture.db(),
+            holding,
+            "buy",
+            "2024-01-01",
+            10.0,
+            money("100"),
+            Some(money("5")),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(cash(&fixture, holding), "-1005.00");
+
+        let sale = add_invest_trade(
+            fixture.db(),
+            holding,
+            "sell",
+            "2024-02-01",
+            4.0,
+            money("150"),
+            Some(money("5")),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(cash(&fixture, holding), "-410.00");
+
+        update_invest_trade(
+            fixture.db(),
+            sale,
+            "sell",
+            "2024-02-01",
+            4.0,
+            money("150"),
+            None,
+            Some(money("590")),
+            None,
+        )
+        .unwrap();
+        assert_eq!(cash(&fixture, holding), "-415.00");
+
+        delete_invest_trade(fixture.db(), sale).unwrap();
+        assert_eq!(cash(&fixture, holding), "-1005.00");
+        // The stored balance agrees with one rebuilt from history
+        let account = fixture.query(
+            "SELECT account_id FROM holdings WHERE id = ?1",
+            params![holding],
+        );
+        let computed = balance::computed_balance(&fixture.reader(), account).unwrap();
+        assert_eq!(Money::from_minor(computed, 2).to_string(), "-1005.00");
+
+        delete_holding(fixture.db(), holding).unwrap();
+        let balance: i64 = fixture.query("SELECT balance FROM accounts", []);
+        assert_eq!(balance, 0);
+    }
+
+    #[test]
+    fn a_trade_in_another_currency_needs_the_amount() {
+        let fixture = Fixture::new();
+        let account = fixture.account("broker", "invest", "EUR", "0");
+        let security = create_security(fixture.db(), "VT", "VT", "USD", "etf").unwrap();
+        let holding = add_holding(fixture.db(), account, security, None).unwrap();
+        let buy = |amount: Option<Money>| {
+            add_invest_trade(
+                fixture.db(),
+                holding,
+                "buy",
+                "2024-01-01",
+                1.0,
+                money("100"),
+                None,
+                amount,
+                None,
+            )
+        };
+
+        assert!(buy(None).is_err());
+        buy(Some(money("90"))).unwrap();
+        assert_eq!(cash(&fixture, holding), "-90.00");
+    }
+}
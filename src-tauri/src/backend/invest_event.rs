@@ -0,0 +1,550 @@
+use crate::backend::attachment;
+use crate::backend::balance;
+use crate::backend::currency;
+use crate::backend::date::is_iso_date;
+use crate::backend::db::Database;
+use crate::backend::error::{BackendError, BackendResult, Context};
+use crate::backend::invest::{holding_account, record_opening_position, replay_trades, to_scale};
+use crate::backend::money::Money;
+use crate::backend::transaction::account_currency;
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use tauri::State;
+
+// An investment event is a `transactions` row in the holding's account, in the account's
+// currency, with an `invest_events` row naming the holding and the kind of event:
+//
+// - 'dividend' and 'interest' are income paid into the account's cash
+// - 'fee' is an expense taken from the account's cash
+// - 'split' changes the number of units by `ratio` new units per old one, keeping the cost basis;
+//   its transaction has no amount
+// - 'reinvestment' is a dividend spent on more units straight away. Its transaction records the
+//   income but leaves cash alone (see `balance::create_balance_effects_view`), and the units are
+//   a buy in `invest_trades` linked through `trade_id`.
+
+#[derive(serde::Serialize)]
+pub struct InvestEvent {
+    pub transaction_id: i64,
+    pub ledger_id: i64,
+    pub holding_id: i64,
+    pub kind: String,
+    pub date: String,
+    // In the account's currency
+    pub amount: Money,
+    pub currency: String,
+    // Set on splits
+    pub ratio: Option<f64>,
+    // Set on reinvestments: the units bought and their price in the security's currency
+    pub quantity: Option<f64>,
+    pub price: Option<Money>,
+    pub note: Option<String>,
+}
+
+// Income and fees of one calendar year, in the account's currency
+#[derive(serde::Serialize)]
+pub struct InvestIncomeYear {
+    pub year: String,
+    pub dividends: Money,
+    pub interest: Money,
+    pub reinvested: Money,
+    pub fees: Money,
+    // Dividends, interest and reinvested dividends less fees
+    pub net: Money,
+}
+
+#[derive(serde::Serialize)]
+pub struct InvestIncome {
+    pub account_id: i64,
+    pub currency: String,
+    pub years: Vec<InvestIncomeYear>,
+}
+
+// Validated figures of an event
+struct EventValues {
+    account_id: i64,
+    transaction_type: &'static str,
+    currency: String,
+    amount: Money,
+    ratio: Option<f64>,
+    // Units and price of a reinvestment's buy
+    reinvestment: Option<(f64, Money)>,
+}
+
+pub fn create_invest_events_table(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS invest_events (
+            transaction_id INTEGER PRIMARY KEY,
+            holding_id INTEGER NOT NULL,
+            kind TEXT NOT NULL
+                CHECK( kind IN ('dividend', 'interest', 'fee', 'split', 'reinvestment') ),
+            ratio REAL CHECK( ratio > 0 ),
+            trade_id INTEGER UNIQUE,
+            FOREIGN KEY (transaction_id) REFERENCES transactions(id) ON DELETE CASCADE,
+            FOREIGN KEY (holding_id) REFERENCES holdings(id) ON DELETE CASCADE,
+            FOREIGN KEY (trade_id) REFERENCES invest_trades(id)
+        );
+        CREATE INDEX IF NOT EXISTS idx_invest_events_holding ON invest_events (holding_id);
+        ",
+    )
+}
+
+// `amount` is required except for splits. A split needs `ratio`, and a reinvestment needs the
+// `quantity` bought and its `price`; its amount defaults to their product when the security and
+// the account share a currency.
+#[tauri::command(rename_all = "snake_case")]
+#[allow(clippy::too_many_arguments)]
+pub fn add_invest_event(
+    db: State<'_, Database>,
+    ledger_id: i64,
+    holding_id: i64,
+    kind: &str,
+    date: &str,
+    amount: Option<Money>,
+    ratio: Option<f64>,
+    quantity: Option<f64>,
+    price: Option<Money>,
+    note: Option<&str>,
+) -> BackendResult<i64> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    let event = validate_event(&tx, holding_id, kind, date, amount, ratio, quantity, price)?;
+    if matches!(kind, "split" | "reinvestment") {
+        record_opening_position(&tx, holding_id, date)?;
+    }
+
+    tx.execute(
+        "INSERT INTO transactions (ledger_id, account_id, type, amount, currency, date, note)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            ledger_id,
+            event.account_id,
+            event.transaction_type,
+            event.amount.minor(),
+            event.currency,
+            date,
+            note
+        ],
+    )
+    .context("Failed to insert transaction")?;
+    let transaction_id = tx.last_insert_rowid();
+
+    let trade_id = insert_reinvestment_trade(&tx, holding_id, date, &event, note)?;
+    tx.execute(
+        "INSERT INTO invest_events (transaction_id, holding_id, kind, ratio, trade_id)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![transaction_id, holding_id, kind, event.ratio, trade_id],
+    )
+    .context("Failed to insert investment event")?;
+
+    // Applied once the event row exists, since it decides whether cash moves
+    balance::apply_transaction_effects(&tx, transaction_id, 1)
+        .context("Failed to update account balance")?;
+    replay_trades(&tx, holding_id)?;
+
+    tx.commit().context("Failed to commit investment event")?;
+    Ok(transaction_id)
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn get_invest_events(
+    db: State<'_, Database>,
+    holding_id: i64,
+) -> BackendResult<Vec<InvestEvent>> {
+    let conn = db.reader().context("Failed to open database connection")?;
+    let (_, security_scale) = holding_account(&conn, holding_id)?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT t.id, t.ledger_id, e.kind, t.date, t.amount, t.currency, cur.minor_unit,
+                 e.ratio, it.quantity, it.price, t.note
+             FROM invest_events e
+             JOIN transactions t ON t.id = e.transaction_id
+             JOIN currencies cur ON cur.code = t.currency
+             LEFT JOIN invest_trades it ON it.id = e.trade_id
+             WHERE e.holding_id = ?1
+             ORDER BY t.date, t.id",
+        )
+        .context("Failed to prepare statement")?;
+
+    let event_iter = stmt
+        .query_map(params![holding_id], |row| {
+            Ok(InvestEvent {
+                transaction_id: row.get(0)?,
+                ledger_id: row.get(1)?,
+                holding_id,
+                kind: row.get(2)?,
+                date: row.get(3)?,
+                amount: Money::from_minor(row.get(4)?, row.get(6)?),
+                currency: row.get(5)?,
+                ratio: row.get(7)?,
+                quantity: row.get(8)?,
+                price: row
+                    .get::<_, Option<i64>>(9)?
+                    .map(|minor| Money::from_minor(minor, security_scale)),
+                note: row.get(10)?,
+            })
+        })
+        .context("Failed to query investment events")?;
+
+    let mut events = Vec::new();
+    for event in event_iter {
+        events.push(event.context("Failed to parse investment event row")?);
+    }
+
+    Ok(events)
+}
+
+// The event stays on its holding; the kind may change
+#[tauri::command(rename_all = "snake_case")]
+#[allow(clippy::too_many_arguments)]
+pub fn update_invest_event(
+    db: State<'_, Database>,
+    id: i64,
+    ledger_id: i64,
+    kind: &str,
+    date: &str,
+    amount: Option<Money>,
+    ratio: Option<f64>,
+    quantity: Option<f64>,
+    price: Option<Money>,
+    note: Option<&str>,
+) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    let (holding_id, old_trade_id) = event_holding(&tx, id)?;
+    let event = validate_event(&tx, holding_id, kind, date, amount, ratio, quantity, price)?;
+
+    // Take the old effects off while the old kind still decides them, then apply the new ones
+    balance::apply_transaction_effects(&tx, id, -1).context("Failed to update account balance")?;
+
+    tx.execute(
+        "UPDATE transactions SET ledger_id = ?1, type = ?2, amount = ?3, date = ?4, note = ?5
+         WHERE id = ?6",
+        params![
+            ledger_id,
+            event.transaction_type,
+            event.amount.minor(),
+            date,
+            note,
+            id
+        ],
+    )
+    .context("Failed to update transaction")?;
+
+    // The buy is replaced rather than edited, as the kind may have changed
+    tx.execute(
+        "UPDATE invest_events SET trade_id = NULL WHERE transaction_id = ?1",
+        params![id],
+    )
+    .context("Failed to update investment event")?;
+    if let Some(trade_id) = old_trade_id {
+        tx.execute("DELETE FROM invest_trades WHERE id = ?1", params![trade_id])
+            .context("Failed to delete reinvestment trade")?;
+    }
+    let trade_id = insert_reinvestment_trade(&tx, holding_id, date, &event, note)?;
+    tx.execute(
+        "UPDATE invest_events SET kind = ?1, ratio = ?2, trade_id = ?3 WHERE transaction_id = ?4",
+        params![kind, event.ratio, trade_id, id],
+    )
+    .context("Failed to update investment event")?;
+
+    balance::apply_transaction_effects(&tx, id, 1).context("Failed to update account balance")?;
+    replay_trades(&tx, holding_id)?;
+
+    tx.commit().context("Failed to commit investment event")?;
+    Ok(())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn delete_invest_event(db: State<'_, Database>, id: i64) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    let (holding_id, _) = event_holding(&tx, id)?;
+    delete_events(&tx, "e.transaction_id = ?1", id)?;
+    replay_trades(&tx, holding_id)?;
+    let orphans = attachment::take_orphans(&tx)?;
+
+    tx.commit()
+        .context("Failed to commit investment event deletion")?;
+    attachment::remove_files(&db, &orphans)?;
+    Ok(())
+}
+
+// Dividends, interest, reinvested dividends and fees of every holding in the account, per year
+#[tauri::command(rename_all = "snake_case")]
+pub fn get_invest_income(db: State<'_, Database>, account_id: i64) -> BackendResult<InvestIncome> {
+    let conn = db.reader().context("Failed to open database connection")?;
+
+    let currency: String = conn
+        .query_row(
+            "SELECT a.currency FROM accounts a
+             JOIN invest_accounts i ON i.account_id = a.id
+             WHERE a.id = ?1",
+            params![account_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .context("Failed to look up invest account")?
+        .ok_or_else(|| {
+            BackendError::not_found(format!("Invest account {} not found", account_id))
+                .with_field("account_id")
+        })?;
+    let scale = currency::minor_unit(&conn, &currency)
+        .context(&format!("Failed to look up currency {}", currency))?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT substr(t.date, 1, 4) AS year,
+                 SUM(CASE e.kind WHEN 'dividend' THEN t.amount ELSE 0 END),
+                 SUM(CASE e.kind WHEN 'interest' THEN t.amount ELSE 0 END),
+                 SUM(CASE e.kind WHEN 'reinvestment' THEN t.amount ELSE 0 END),
+                 SUM(CASE e.kind WHEN 'fee' THEN t.amount ELSE 0 END),
+                 SUM(CASE e.kind WHEN 'fee' THEN -t.amount ELSE t.amount END)
+             FROM invest_events e
+             JOIN transactions t ON t.id = e.transaction_id
+             WHERE t.account_id = ?1 AND e.kind != 'split'
+             GROUP BY year
+             ORDER BY year",
+        )
+        .context("Failed to prepare statement")?;
+
+    let year_iter = stmt
+        .query_map(params![account_id], |row| {
+            let money =
+                |idx: usize| -> Result<Money> { Ok(Money::from_minor(row.get(idx)?, scale)) };
+            let (dividends, interest, reinvested, fees) =
+                (money(1)?, money(2)?, money(3)?, money(4)?);
+            Ok(InvestIncomeYear {
+                year: row.get(0)?,
+                dividends,
+                interest,
+                reinvested,
+                fees,
+                net: money(5)?,
+            })
+        })
+        .context("Failed to query investment income")?;
+
+    let mut years = Vec::new();
+    for year in year_iter {
+        years.push(year.context("Failed to parse investment income row")?);
+    }
+
+    Ok(InvestIncome {
+        account_id,
+        currency,
+        years,
+    })
+}
+
+// Remove every event of a holding with its transaction, ahead of deleting the holding
+pub(crate) fn delete_holding_events(conn: &Connection, holding_id: i64) -> BackendResult<()> {
+    delete_events(conn, "e.holding_id = ?1", holding_id)
+}
+
+// Event transactions are changed through the event so the holding stays in step
+pub(crate) fn ensure_not_invest_event(conn: &Connection, transaction_id: i64) -> BackendResult<()> {
+    let is_event: bool = conn
+        .query_row(
+            "SELECT EXISTS (SELECT 1 FROM invest_events WHERE transaction_id = ?1)",
+            params![transaction_id],
+            |row| row.get(0),
+        )
+        .context("Failed to look up investment event")?;
+    if is_event {
+        return Err(BackendError::validation(
+            "id",
+            "This transaction is an investment event; change it with update_invest_event or \
+             delete_invest_event",
+        ));
+    }
+    Ok(())
+}
+
+// Delete the events matching `filter` (on `invest_events e`, binding ?1), their transactions and
+// any reinvestment trades. Holdings are left for the caller to replay.
+fn delete_events(conn: &Connection, filter: &str, key: i64) -> BackendResult<()> {
+    let events = {
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT e.transaction_id, e.trade_id FROM invest_events e WHERE {}",
+                filter
+            ))
+            .context("Failed to prepare statement")?;
+        let rows = stmt
+            .query_map(params![key], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, Option<i64>>(1)?))
+            })
+            .context("Failed to query investment events")?
+            .collect::<Result<Vec<_>>>()
+            .context("Failed to parse investment event row")?;
+        rows
+    };
+
+    for (transaction_id, trade_id) in events {
+        balance::apply_transaction_effects(conn, transaction_id, -1)
+            .context("Failed to update account balance")?;
+        // The event row cascades with its transaction, which frees the trade
+        conn.execute(
+            "DELETE FROM transactions WHERE id = ?1",
+            params![transaction_id],
+        )
+        .context("Failed to delete transaction")?;
+        if let Some(trade_id) = trade_id {
+            conn.execute("DELETE FROM invest_trades WHERE id = ?1", params![trade_id])
+                .context("Failed to delete reinvestment trade")?;
+        }
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn validate_event(
+    conn: &Connection,
+    holding_id: i64,
+    kind: &str,
+    date: &str,
+    amount: Option<Money>,
+    ratio: Option<f64>,
+    quantity: Option<f64>,
+    price: Option<Money>,
+) -> BackendResult<EventValues> {
+    let transaction_type = match kind {
+        "dividend" | "interest" | "split" | "reinvestment" => "income",
+        "fee" => "expense",
+        _ => {
+            return Err(BackendError::validation(
+                "kind",
+                format!("Unsupported investment event '{}'", kind),
+            ))
+        }
+    };
+    if !is_iso_date(date) {
+        return Err(BackendError::validation(
+            "date",
+            format!("'{}' is not a YYYY-MM-DD date", date),
+        ));
+    }
+
+    let (account_id, security_scale) = holding_account(conn, holding_id)?;
+    let currency = account_currency(conn, account_id, "holding_id")?;
+    let scale = currency::minor_unit(conn, &currency)
+        .context(&format!("Failed to look up currency {}", currency))?;
+    let security_currency: String = conn
+        .query_row(
+            "SELECT s.currency FROM holdings h
+             JOIN securities s ON s.id = h.security_id
+             WHERE h.id = ?1",
+            params![holding_id],
+            |row| row.get(0),
+        )
+        .context("Failed to look up security")?;
+
+    let mut event = EventValues {
+        account_id,
+        transaction_type,
+        currency,
+        amount: Money::zero(scale),
+        ratio: None,
+        reinvestment: None,
+    };
+
+    if kind == "split" {
+        let ratio = ratio.filter(|ratio| ratio.is_finite() && *ratio > 0.0);
+        event.ratio = Some(ratio.ok_or_else(|| {
+            BackendError::validation(
+                "ratio",
+                "A split needs a positive number of new units per old one",
+            )
+        })?);
+        return Ok(event);
+    }
+
+    if kind == "reinvestment" {
+        let quantity = quantity
+            .filter(|quantity| quantity.is_finite() && *quantity > 0.0)
+            .ok_or_else(|| {
+                BackendError::validation("quantity", "A reinvestment needs the units bought")
+            })?;
+        let price = price.ok_or_else(|| {
+            BackendError::validation("price", "A reinvestment needs the price per unit")
+        })?;
+        let price = to_scale(price, security_scale, "price")?;
+        if price.is_negative() {
+            return Err(BackendError::validation(
+                "price",
+                "Price must not be negative",
+            ));
+        }
+        event.reinvestment = Some((quantity, price));
+
+        // Without an amount the units' cost is the dividend, if it can be read in the account's
+        // currency
+        if amount.is_none() && security_currency == event.currency {
+            let cost = (quantity * price.minor() as f64).round() as i64;
+            event.amount = to_scale(Money::from_minor(cost, price.scale()), scale, "price")?;
+            return Ok(event);
+        }
+    }
+
+    let amount = amount.ok_or_else(|| {
+        BackendError::validation(
+            "amount",
+            format!("A {} needs the amount in {}", kind, event.currency),
+        )
+    })?;
+    let amount = to_scale(amount, scale, "amount")?;
+    if amount.is_negative() || amount.is_zero() {
+        return Err(BackendError::validation(
+            "amount",
+            "Amount must be positive",
+        ));
+    }
+    event.amount = amount;
+    Ok(event)
+}
+
+// The buy behind a reinvestment, or None for any other event
+fn insert_reinvestment_trade(
+    conn: &Connection,
+    holding_id: i64,
+    date: &str,
+    event: &EventValues,
+    note: Option<&str>,
+) -> BackendResult<Option<i64>> {
+    let Some((quantity, price)) = event.reinvestment else {
+        return Ok(None);
+    };
+
+    conn.execute(
+        "INSERT INTO invest_trades
+             (account_id, holding_id, side, date, quantity, price, fee, note)
+         VALUES (?1, ?2, 'buy', ?3, ?4, ?5, 0, ?6)",
+        params![
+            event.account_id,
+            holding_id,
+            date,
+            quantity,
+            price.minor(),
+            note
+        ],
+    )
+    .context("Failed to insert reinvestment trade")?;
+    Ok(Some(conn.last_insert_rowid()))
+}
+
+// The event's holding and reinvestment trade
+fn event_holding(conn: &Connection, id: i64) -> BackendResult<(i64, Option<i64>)> {
+    conn.query_row(
+        "SELECT holding_id, trade_id FROM invest_events WHERE transaction_id = ?1",
+        params![id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+    .optional()
+    .context("Failed to look up investment event")?
+    .ok_or_else(|| BackendError::not_found(format!("Investment event {} not found", id)))
+}
@@ -1,19 +1,21 @@
-use crate::backend::db::get_connection;
-use rusqlite::{params, Result};
+use crate::backend::attachment;
+use crate::backend::db::Database;
+use crate::backend::error::{BackendError, BackendResult, Context};
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use tauri::State;
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct Ledger {
     pub id: i64,
     pub name: String,
     pub base_currency: String,
-    pub base_account: i64,
+    // Cleared by ON DELETE SET NULL when the account is removed
+    pub base_account: Option<i64>,
     pub is_archived: bool,
 }
 
 // Function to create the ledger table
-pub fn create_ledgers_table() -> Result<()> {
-    let conn = get_connection();
-
+pub fn create_ledgers_table(conn: &Connection) -> Result<()> {
     let create_table_sql = "
     CREATE TABLE IF NOT EXISTS ledgers (
         id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -31,67 +33,60 @@ pub fn create_ledgers_table() -> Result<()> {
 
 #[tauri::command(rename_all = "snake_case")]
 pub fn create_ledger(
+    db: State<'_, Database>,
     name: &str,
     base_currency: &str,
-    base_account: i64,
+    base_account: Option<i64>,
     is_archived: bool,
-) -> Result<i64, String> {
-    let conn = get_connection();
+) -> BackendResult<i64> {
+    let conn = db.writer();
     conn.execute(
         "INSERT INTO ledgers (name, base_currency, base_account, is_archived) 
          VALUES (?1, ?2, ?3, ?4)",
         params![name, base_currency, base_account, is_archived],
     )
-    .map_err(|err| format!("Failed to insert ledger: {}", err))?;
+    .context("Failed to insert ledger")?;
 
     Ok(conn.last_insert_rowid())
 }
 
 #[tauri::command]
-pub fn get_ledgers() -> Result<Vec<Ledger>, String> {
-    let conn = get_connection();
-
-    let mut stmt = match conn
-        .prepare("SELECT id, name, base_currency, base_account, archived, categories FROM ledgers")
-    {
-        Ok(statement) => statement,
-        Err(e) => return Err(format!("Failed to prepare statement: {}", e)),
-    };
-
-    let ledger_iter = match stmt.query_map([], |row| {
-        Ok(Ledger {
-            id: row.get(0)?,
-            name: row.get(1)?,
-            base_currency: row.get(2)?,
-            base_account: row.get(3)?,
-            is_archived: row.get(4)?,
+pub fn get_ledgers(db: State<'_, Database>) -> BackendResult<Vec<Ledger>> {
+    let conn = db.reader().context("Failed to open database connection")?;
+
+    let mut stmt = conn
+        .prepare("SELECT id, name, base_currency, base_account, is_archived FROM ledgers")
+        .context("Failed to prepare statement")?;
+
+    let ledger_iter = stmt
+        .query_map([], |row| {
+            Ok(Ledger {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                base_currency: row.get(2)?,
+                base_account: row.get(3)?,
+                is_archived: row.get(4)?,
+            })
         })
-    }) {
-        Ok(iterator) => iterator,
-        Err(e) => return Err(format!("Failed to query accounts: {}", e)),
-    };
+        .context("Failed to query ledgers")?;
 
     let mut ledgers = Vec::new();
     for ledger in ledger_iter {
-        match ledger {
-            Ok(ledger) => ledgers.push(ledger),
-            Err(e) => return Err(format!("Failed to parse account row: {}", e)),
-        }
+        ledgers.push(ledger.context("Failed to parse ledger row")?);
     }
 
     Ok(ledgers)
 }
 
 #[tauri::command(rename_all = "snake_case")]
-pub fn get_ledger(ledger_id: i64) -> Result<Ledger, String> {
-    let conn = get_connection();
-    let mut stmt = match conn.prepare(
-        "SELECT id, name, base_currency, base_account, is_archived 
+pub fn get_ledger(db: State<'_, Database>, ledger_id: i64) -> BackendResult<Ledger> {
+    let conn = db.reader().context("Failed to open database connection")?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, name, base_currency, base_account, is_archived 
          FROM ledgers WHERE id = ?1",
-    ) {
-        Ok(statement) => statement,
-        Err(e) => return Err(format!("Failed to prepare statement: {}", e)),
-    };
+        )
+        .context("Failed to prepare statement")?;
 
     let ledger = stmt
         .query_row(params![ledger_id], |row| {
@@ -103,35 +98,69 @@ pub fn get_ledger(ledger_id: i64) -> Result<Ledger, String> {
                 is_archived: row.get(4)?,
             })
         })
-        .map_err(|err| format!("Failed to get ledger: {}", err))?;
+        .context("Failed to get ledger")?;
 
     Ok(ledger)
 }
 
 #[tauri::command(rename_all = "snake_case")]
 pub fn update_ledger(
+    db: State<'_, Database>,
     ledger_id: i64,
     name: &str,
     base_currency: &str,
-    base_account: i64,
+    base_account: Option<i64>,
     is_archived: bool,
-) -> Result<(), String> {
-    let conn = get_connection();
-    conn.execute(
-        "UPDATE ledgers SET name = ?1, base_currency = ?2, base_account = ?3, is_archived = ?4 
-         WHERE id = ?5",
-        params![name, base_currency, base_account, is_archived, ledger_id],
-    )
-    .map_err(|err| format!("Failed to update ledger: {}", err))?;
+) -> BackendResult<()> {
+    let conn = db.writer();
+    let updated = conn
+        .execute(
+            "UPDATE ledgers SET name = ?1, base_currency = ?2, base_account = ?3, is_archived = ?4 
+             WHERE id = ?5",
+            params![name, base_currency, base_account, is_archived, ledger_id],
+        )
+        .context("Failed to update ledger")?;
+    if updated == 0 {
+        return Err(BackendError::not_found(format!(
+            "Ledger {} not found",
+            ledger_id
+        )));
+    }
 
     Ok(())
 }
 
 #[tauri::command(rename_all = "snake_case")]
-pub fn delete_ledger(ledger_id: i64) -> Result<(), String> {
-    let conn = get_connection();
-    conn.execute("DELETE FROM ledgers WHERE id = ?1", params![ledger_id])
-        .map_err(|err| format!("Failed to delete ledger: {}", err))?;
+pub fn delete_ledger(db: State<'_, Database>, ledger_id: i64) -> BackendResult<()> {
+    let conn = db.writer();
+    let deleted = conn
+        .execute("DELETE FROM ledgers WHERE id = ?1", params![ledger_id])
+        .context("Failed to delete ledger")?;
+    if deleted == 0 {
+        return Err(BackendError::not_found(format!(
+            "Ledger {} not found",
+            ledger_id
+        )));
+    }
+    let orphans = attachment::take_orphans(&conn)?;
+    attachment::remove_files(&db, &orphans)?;
 
     Ok(())
 }
+
+// The base currency of the ledger a report is for, when no currency is given
+pub(crate) fn ledger_currency(conn: &Connection, ledger_id: Option<i64>) -> BackendResult<String> {
+    let ledger_id = ledger_id.ok_or_else(|| {
+        BackendError::validation("currency", "Give a currency or a ledger to report in")
+    })?;
+    conn.query_row(
+        "SELECT base_currency FROM ledgers WHERE id = ?1",
+        params![ledger_id],
+        |row| row.get(0),
+    )
+    .optional()
+    .context("Failed to look up ledger")?
+    .ok_or_else(|| {
+        BackendError::not_found(format!("Ledger {} not found", ledger_id)).with_field("ledger_id")
+    })
+}
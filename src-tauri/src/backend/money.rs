@@ -0,0 +1,381 @@
+use crate::backend::error::{BackendError, BackendResult};
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+
+// Largest scale we accept; ISO 4217 tops out at 4 but custom units may need more
+pub const MAX_SCALE: u32 = 8;
+
+// Exact monetary amount held as integer minor units of its currency. `scale` is the number of
+// decimal digits, so 12.34 USD is `{ minor: 1234, scale: 2 }` and 1234 JPY is
+// `{ minor: 1234, scale: 0 }`. Only `minor` is stored; the scale comes from the currency row.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Money {
+    minor: i64,
+    scale: u32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MoneyError {
+    Invalid(String),
+    Overflow,
+    // The value has more decimal digits than the currency allows
+    TooPrecise { value: String, scale: u32 },
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::Invalid(s) => write!(f, "'{}' is not a valid amount", s),
+            MoneyError::Overflow => write!(f, "Amount is out of range"),
+            MoneyError::TooPrecise { value, scale } => write!(
+                f,
+                "{} has more than {} decimal places for this currency",
+                value, scale
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MoneyError {}
+
+impl Money {
+    pub fn from_minor(minor: i64, scale: u32) -> Self {
+        Money { minor, scale }
+    }
+
+    pub fn zero(scale: u32) -> Self {
+        Money { minor: 0, scale }
+    }
+
+    pub fn minor(&self) -> i64 {
+        self.minor
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.minor == 0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.minor < 0
+    }
+
+    pub fn checked_abs(self) -> Option<Money> {
+        Some(Money {
+            minor: self.minor.checked_abs()?,
+            scale: self.scale,
+        })
+    }
+
+    pub fn checked_neg(self) -> Option<Money> {
+        Some(Money {
+            minor: self.minor.checked_neg()?,
+            scale: self.scale,
+        })
+    }
+
+    // Parse a plain decimal string such as "-1234.5". The scale is the number of digits
+    // written after the point; call `to_scale` to fit it to a currency.
+    pub fn parse(s: &str) -> Result<Self, MoneyError> {
+        let invalid = || MoneyError::Invalid(s.to_string());
+        let trimmed = s.trim();
+        let (negative, digits) = match trimmed.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+        };
+        let (int_part, frac_part) = match digits.split_once('.') {
+            Some((i, f)) => (i, f),
+            None => (digits, ""),
+        };
+        if int_part.is_empty() && frac_part.is_empty() {
+            return Err(invalid());
+        }
+        if !int_part
+            .chars()
+            .chain(frac_part.chars())
+            .all(|c| c.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+
+        // Trailing zeros carry no value, so drop them rather than reject "1.500" for JPY-like scales
+        let frac_part = frac_part.trim_end_matches('0');
+        let scale = frac_part.len() as u32;
+        if scale > MAX_SCALE {
+            return Err(MoneyError::TooPrecise {
+                value: s.to_string(),
+                scale: MAX_SCALE,
+            });
+        }
+
+        let mut minor: i64 = 0;
+        for c in int_part.chars().chain(frac_part.chars()) {
+            minor = minor
+                .checked_mul(10)
+                .and_then(|m| m.checked_add(i64::from(c as u8 - b'0')))
+                .ok_or(MoneyError::Overflow)?;
+        }
+
+        Ok(Money {
+            minor: if negative { -minor } else { minor },
+            scale,
+        })
+    }
+
+    // Convert a REAL value read from a legacy column, rounding half away from zero
+    pub fn from_f64(value: f64, scale: u32) -> Result<Self, MoneyError> {
+        let scaled = (value * 10f64.powi(scale as i32)).round();
+        if !scaled.is_finite() || scaled.abs() >= i64::MAX as f64 {
+            return Err(MoneyError::Overflow);
+        }
+        Ok(Money {
+            minor: scaled as i64,
+            scale,
+        })
+    }
+
+    // Re-express the amount with `scale` decimals, failing instead of dropping digits
+    pub fn to_scale(self, scale: u32) -> Result<Self, MoneyError> {
+        match scale.cmp(&self.scale) {
+            Ordering::Equal => Ok(self),
+            Ordering::Greater => {
+                let factor = pow10(scale - self.scale).ok_or(MoneyError::Overflow)?;
+                let minor = self.minor.checked_mul(factor).ok_or(MoneyError::Overflow)?;
+                Ok(Money { minor, scale })
+            }
+            Ordering::Less => {
+                let factor = pow10(self.scale - scale).ok_or(MoneyError::Overflow)?;
+                if self.minor % factor != 0 {
+                    return Err(MoneyError::TooPrecise {
+                        value: self.to_string(),
+                        scale,
+                    });
+                }
+                Ok(Money {
+                    minor: self.minor / factor,
+                    scale,
+                })
+            }
+        }
+    }
+
+    // Re-express the amount with `scale` decimals, rounding half away from zero if digits
+    // have to be dropped
+    pub fn round_to_scale(self, scale: u32) -> Result<Self, MoneyError> {
+        if scale >= self.scale {
+            return self.to_scale(scale);
+        }
+        let factor = pow10(self.scale - scale).ok_or(MoneyError::Overflow)?;
+        Ok(Money {
+            minor: div_round(i128::from(self.minor), i128::from(factor)) as i64,
+            scale,
+        })
+    }
+
+    pub fn checked_add(self, other: Money) -> Option<Money> {
+        let (a, b, scale) = align(self, other)?;
+        Some(Money {
+            minor: a.checked_add(b)?,
+            scale,
+        })
+    }
+
+    pub fn checked_sub(self, other: Money) -> Option<Money> {
+        let (a, b, scale) = align(self, other)?;
+        Some(Money {
+            minor: a.checked_sub(b)?,
+            scale,
+        })
+    }
+}
+
+fn pow10(exp: u32) -> Option<i64> {
+    10i64.checked_pow(exp)
+}
+
+// Integer division rounding half away from zero
+pub(crate) fn div_round(numerator: i128, denominator: i128) -> i128 {
+    let quotient = numerator / denominator;
+    let remainder = numerator % denominator;
+    if remainder.abs() * 2 >= denominator.abs() {
+        if (numerator < 0) != (denominator < 0) {
+            quotient - 1
+        } else {
+            quotient + 1
+        }
+    } else {
+        quotient
+    }
+}
+
+// Bring two amounts to the larger of their scales so their minor units are comparable
+fn align(a: Money, b: Money) -> Option<(i64, i64, u32)> {
+    let scale = a.scale.max(b.scale);
+    let a = a.to_scale(scale).ok()?;
+    let b = b.to_scale(scale).ok()?;
+    Some((a.minor, b.minor, scale))
+}
+
+impl PartialEq for Money {
+    fn eq(&self, other: &Money) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Money {}
+
+impl PartialOrd for Money {
+    fn partial_cmp(&self, other: &Money) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Money {
+    fn cmp(&self, other: &Money) -> Ordering {
+        // Compare in i128 so aligning scales can never overflow
+        let scale = self.scale.max(other.scale);
+        let widen = |m: &Money| i128::from(m.minor) * 10i128.pow(scale - m.scale);
+        widen(self).cmp(&widen(other))
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.minor < 0 { "-" } else { "" };
+        let digits = self.minor.unsigned_abs().to_string();
+        if self.scale == 0 {
+            return write!(f, "{}{}", sign, digits);
+        }
+        let scale = self.scale as usize;
+        let padded = format!("{:0>width$}", digits, width = scale + 1);
+        let (int_part, frac_part) = padded.split_at(padded.len() - scale);
+        write!(f, "{}{}.{}", sign, int_part, frac_part)
+    }
+}
+
+// Amounts cross the IPC boundary as decimal strings so no precision is lost in JavaScript
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MoneyVisitor;
+
+        impl Visitor<'_> for MoneyVisitor {
+            type Value = Money;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a decimal amount as a string or number")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Money, E> {
+                Money::parse(v).map_err(E::custom)
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Money, E> {
+                Ok(Money::from_minor(v, 0))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Money, E> {
+                let minor = i64::try_from(v).map_err(|_| E::custom(MoneyError::Overflow))?;
+                Ok(Money::from_minor(minor, 0))
+            }
+
+            // JSON numbers arrive as f64; the shortest round-trip representation is exactly
+            // what the user typed, so parse that rather than doing float arithmetic
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Money, E> {
+                if !v.is_finite() {
+                    return Err(E::custom(MoneyError::Invalid(v.to_string())));
+                }
+                Money::parse(&v.to_string()).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(MoneyVisitor)
+    }
+}
+
+// Running totals in reports fail rather than wrap when they overflow
+pub(crate) fn add(total: Money, value: Money) -> BackendResult<Money> {
+    total
+        .checked_add(value)
+        .ok_or_else(|| BackendError::validation("currency", "Total is out of range"))
+}
+
+pub(crate) fn sub(total: Money, value: Money) -> BackendResult<Money> {
+    total
+        .checked_sub(value)
+        .ok_or_else(|| BackendError::validation("currency", "Total is out of range"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> (i64, u32) {
+        let money = Money::parse(s).unwrap();
+        (money.minor, money.scale)
+    }
+
+    #[test]
+    fn parse_reads_plain_decimals() {
+        assert_eq!(parse("1234.5"), (12345, 1));
+        assert_eq!(parse("-0.05"), (-5, 2));
+        assert_eq!(parse(" +7 "), (7, 0));
+        assert_eq!(parse(".5"), (5, 1));
+        assert_eq!(parse("3."), (3, 0));
+        // Trailing zeros do not count towards the scale
+        assert_eq!(parse("1.500"), (15, 1));
+        assert_eq!(parse("2.00"), (2, 0));
+    }
+
+    #[test]
+    fn parse_rejects_malformed_input() {
+        for s in ["", "-", ".", "1,000", "1.2.3", "1e5", "--1", "abc", "1 000"] {
+            assert!(
+                matches!(Money::parse(s), Err(MoneyError::Invalid(_))),
+                "{:?}",
+                s
+            );
+        }
+        assert!(matches!(
+            Money::parse("0.123456789"),
+            Err(MoneyError::TooPrecise { .. })
+        ));
+        assert!(matches!(
+            Money::parse("99999999999999999999"),
+            Err(MoneyError::Overflow)
+        ));
+    }
+
+    #[test]
+    fn to_scale_widens_and_narrows_exactly() {
+        let money = Money::parse("12.5").unwrap();
+        assert_eq!(money.to_scale(2).unwrap().minor(), 1250);
+        assert_eq!(money.to_scale(2).unwrap().to_string(), "12.50");
+        assert_eq!(Money::from_minor(1200, 2).to_scale(0).unwrap().minor(), 12);
+        assert!(matches!(
+            Money::from_minor(1250, 2).to_scale(0),
+            Err(MoneyError::TooPrecise { .. })
+        ));
+        assert!(matches!(
+            Money::from_minor(i64::MAX / 10, 0).to_scale(2),
+            Err(MoneyError::Overflow)
+        ));
+    }
+
+    #[test]
+    fn display_pads_the_fraction() {
+        assert_eq!(Money::from_minor(5, 2).to_string(), "0.05");
+        assert_eq!(Money::from_minor(-5, 2).to_string(), "-0.05");
+        assert_eq!(Money::from_minor(1234, 0).to_string(), "1234");
+    }
+}
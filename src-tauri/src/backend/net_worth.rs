@@ -0,0 +1,237 @@
+use crate::backend::currency;
+use crate::backend::date::{today, validate_date};
+use crate::backend::db::Database;
+use crate::backend::error::{BackendResult, Context};
+use crate::backend::exchange_rate::{convert, pivot_currency};
+use crate::backend::invest::holdings_value;
+use crate::backend::ledger::ledger_currency;
+use crate::backend::money::{add, sub, Money};
+use rusqlite::{params, Connection};
+use tauri::{AppHandle, State};
+
+#[derive(serde::Serialize)]
+pub struct NetWorth {
+    pub currency: String,
+    pub date: String,
+    pub assets: Money,
+    pub liabilities: Money,
+    pub net_worth: Money,
+    pub by_type: Vec<AccountTypeWorth>,
+    pub accounts: Vec<AccountWorth>,
+}
+
+#[derive(serde::Serialize)]
+pub struct AccountTypeWorth {
+    pub account_type: String,
+    pub value: Money,
+}
+
+#[derive(serde::Serialize)]
+pub struct AccountWorth {
+    pub account_id: i64,
+    pub name: String,
+    pub account_type: String,
+    // What the account adds to net worth in its own currency at the end of the date: the balance
+    // from its opening balance and transactions, negative for what is owed on a credit account,
+    // plus holdings on an invest account
+    pub native_value: Money,
+    pub native_currency: String,
+    pub value: Money,
+    pub rate: f64,
+    // False when the account is excluded with `count_in_asset`; it is listed but not summed
+    pub counted: bool,
+}
+
+// Net worth of every account at the end of `date`, which defaults to today, converted into
+// `currency` (the ledger's base currency when only a ledger is given) at the closest rates on or
+// before it
+#[tauri::command(rename_all = "snake_case")]
+pub fn get_net_worth(
+    app: AppHandle,
+    db: State<'_, Database>,
+    ledger_id: Option<i64>,
+    currency: Option<&str>,
+    date: Option<&str>,
+) -> BackendResult<NetWorth> {
+    let pivot = pivot_currency(&app);
+    let conn = db.reader().context("Failed to open database connection")?;
+
+    let currency = match currency {
+        Some(currency) => currency.to_string(),
+        None => ledger_currency(&conn, ledger_id)?,
+    };
+    let date = match date {
+        Some(date) => date.to_string(),
+        None => today(&conn)?,
+    };
+
+    net_worth(&conn, &currency, &date, &pivot)
+}
+
+pub fn net_worth(
+    conn: &Connection,
+    currency: &str,
+    date: &str,
+    pivot: &str,
+) -> BackendResult<NetWorth> {
+    validate_date("date", date)?;
+    let scale = currency::minor_unit(conn, currency)
+        .context(&format!("Failed to look up currency {}", currency))
+        .map_err(|err| err.with_field("currency"))?;
+
+    // Balances replay the transactions dated up to the end of `date`
+    let mut stmt = conn
+        .prepare(
+            "SELECT a.id, a.name, a.type, a.currency, cur.minor_unit, a.count_in_asset,
+                 a.opening_balance + COALESCE((
+                     SELECT SUM(e.delta) FROM balance_effects e
+                     WHERE e.account_id = a.id AND e.day <= ?1
+                 ), 0)
+             FROM accounts a
+             JOIN currencies cur ON cur.code = a.currency
+             ORDER BY a.type, a.name",
+        )
+        .context("Failed to prepare statement")?;
+
+    let rows = stmt
+        .query_map(params![date], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, u32>(4)?,
+                row.get::<_, bool>(5)?,
+                row.get::<_, i64>(6)?,
+            ))
+        })
+        .context("Failed to query accounts")?;
+
+    let mut worth = NetWorth {
+        currency: currency.to_string(),
+        date: date.to_string(),
+        assets: Money::zero(scale),
+        liabilities: Money::zero(scale),
+        net_worth: Money::zero(scale),
+        by_type: Vec::new(),
+        accounts: Vec::new(),
+    };
+
+    for row in rows {
+        let (account_id, name, account_type, native_currency, native_scale, counted, minor) =
+            row.context("Failed to parse account row")?;
+        let mut native_value = Money::from_minor(minor, native_scale);
+        if account_type == "invest" {
+            let holdings = holdings_value(conn, account_id, &native_currency, date, pivot)?;
+            native_value = add(native_value, holdings)?;
+        }
+
+        let conversion = convert(conn, native_value, &native_currency, currency, date, pivot)
+            .map_err(|err| err.context(&format!("Failed to value account '{}'", name)))?;
+        let value = conversion.amount;
+
+        if counted {
+            if value.is_negative() {
+                worth.liabilities = sub(worth.liabilities, value)?;
+            } else {
+                worth.assets = add(worth.assets, value)?;
+            }
+            worth.net_worth = add(worth.net_worth, value)?;
+
+            match worth
+                .by_type
+                .iter_mut()
+                .find(|t| t.account_type == account_type)
+            {
+                Some(total) => total.value = add(total.value, value)?,
+                None => worth.by_type.push(AccountTypeWorth {
+                    account_type: account_type.clone(),
+                    value,
+                }),
+            }
+        }
+
+        worth.accounts.push(AccountWorth {
+            account_id,
+            name,
+            account_type,
+            native_value,
+            native_currency,
+            value,
+            rate: conversion.rate,
+            counted,
+        });
+    }
+
+    Ok(worth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::exchange_rate::add_exchange_rate;
+    use crate::backend::testing::{money, Fixture};
+    use crate::backend::transaction::create_transaction;
+
+    #[test]
+    fn worth_is_taken_at_the_end_of_the_date_in_the_chosen_currency() {
+        let fixture = Fixture::new();
+        let ledger = fixture.ledger("USD");
+        let cash = fixture.account("cash", "debit", "USD", "100");
+        fixture.account("euros", "debit", "EUR", "50");
+        let card = fixture.credit_card("card", "USD", "1", "20");
+        let hidden = fixture.account("hidden", "debit", "USD", "999");
+        fixture
+            .db()
+            .writer()
+            .execute(
+                "UPDATE accounts SET count_in_asset = 0 WHERE id = ?1",
+                [hidden],
+            )
+            .unwrap();
+        add_exchange_rate(fixture.db(), "EUR", "USD", "2024-01-01", 1.2, None).unwrap();
+        for (account, kind, amount, date) in [
+            (card, "expense", "30", "2024-02-01"),
+            (cash, "income", "5", "2024-07-01"),
+        ] {
+            create_transaction(
+                fixture.db(),
+                ledger,
+                account,
+                kind,
+                None,
+                money(amount),
+                "USD",
+                date,
+                vec![],
+                None,
+            )
+            .unwrap();
+        }
+        let reader = fixture.reader();
+
+        let worth = net_worth(&reader, "USD", "2024-01-31", "USD").unwrap();
+        assert_eq!(worth.net_worth, money("160.00"));
+        assert_eq!(worth.liabilities, money("0.00"));
+
+        // The card expense is owed from its date on; the excluded account is listed but not summed
+        let worth = net_worth(&reader, "USD", "2024-06-30", "USD").unwrap();
+        assert_eq!(
+            (worth.assets, worth.liabilities, worth.net_worth),
+            (money("160.00"), money("30.00"), money("130.00"))
+        );
+        assert_eq!(worth.accounts.len(), 4);
+        let excluded = worth.accounts.iter().find(|a| a.account_id == hidden);
+        assert!(!excluded.unwrap().counted);
+        let debit = worth.by_type.iter().find(|t| t.account_type == "debit");
+        assert_eq!(debit.unwrap().value, money("160.00"));
+
+        let worth = net_worth(&reader, "EUR", "2024-07-01", "USD").unwrap();
+        assert_eq!(worth.net_worth, money("112.50"));
+
+        // No rate yet for the euro account, and no such date
+        assert!(net_worth(&reader, "USD", "2023-12-31", "USD").is_err());
+        let worth = net_worth(&reader, "USD", "2024-02-30", "USD");
+        assert!(matches!(worth, Err(err) if err.field() == Some("date")));
+    }
+}
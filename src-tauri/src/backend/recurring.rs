@@ -0,0 +1,1084 @@
+use crate::backend::attachment;
+use crate::backend::balance;
+use crate::backend::category;
+use crate::backend::date::{add_days, clamped_date, date_days, split_date, today, validate_date};
+use crate::backend::db::Database;
+use crate::backend::error::{BackendError, BackendResult, Context};
+use crate::backend::money::Money;
+use crate::backend::transaction::{
+    account_currency, insert_or_get_tag, link_tags, to_currency_scale, validate_transaction,
+};
+use crate::backend::transfer::validate_transfer;
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use tauri::State;
+
+// Most occurrences worked through in one pass over a rule; the rest wait for the next pass
+const MAX_OCCURRENCES: usize = 10_000;
+
+// A recurring rule is a template transaction plus a schedule. Its occurrences fall every
+// `interval` days, weeks, months or years from `start_date` up to `end_date`; monthly and yearly
+// ones land on `day_of_month`, or on the last day of a shorter month. Each due occurrence becomes
+// a transaction pointing back through `recurring_rule_id` and `occurrence_date`, and
+// `generated_until` marks the last occurrence dealt with so none is recorded twice, even after
+// its transaction is deleted. `recurring_overrides` skips or changes single occurrences before
+// they are recorded.
+
+#[derive(serde::Serialize)]
+pub struct RecurringRule {
+    pub id: i64,
+    pub ledger_id: i64,
+    pub account_id: i64,
+    pub transaction_type: String,
+    pub to_account_id: Option<i64>,
+    pub amount: Money,
+    pub currency: String,
+    pub to_amount: Option<Money>,
+    pub to_currency: Option<String>,
+    pub fee: Money,
+    pub category_id: Option<i64>,
+    pub tags: Vec<String>,
+    pub note: Option<String>,
+    pub frequency: String,
+    pub interval: i64,
+    pub start_date: String,
+    pub end_date: Option<String>,
+    pub day_of_month: Option<u32>,
+    // The last occurrence recorded or skipped
+    pub generated_until: Option<String>,
+    // None once the rule has run out
+    pub next_date: Option<String>,
+}
+
+// The rule as sent to create and update
+#[derive(serde::Deserialize)]
+pub struct RecurringRuleInput {
+    pub ledger_id: i64,
+    pub account_id: i64,
+    pub transaction_type: String, // 'expense', 'income', 'transfer'
+    pub to_account_id: Option<i64>,
+    pub amount: Money,
+    // As for `create_transfer`: needed when a transfer crosses currencies
+    pub to_amount: Option<Money>,
+    pub fee: Option<Money>,
+    pub category_id: Option<i64>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub note: Option<String>,
+    pub frequency: String, // 'daily', 'weekly', 'monthly', 'yearly'
+    pub interval: Option<i64>,
+    pub start_date: String,
+    pub end_date: Option<String>,
+    // Monthly and yearly rules only; defaults to the day of `start_date`
+    pub day_of_month: Option<u32>,
+}
+
+#[derive(serde::Serialize)]
+pub struct RecurringOccurrence {
+    pub date: String,
+    // 'recorded', 'skipped', 'edited' or 'scheduled'
+    pub status: String,
+    pub transaction_id: Option<i64>,
+    // What was or will be recorded
+    pub amount: Money,
+    pub note: Option<String>,
+}
+
+// When a rule's occurrences fall
+struct Schedule {
+    frequency: String,
+    interval: i64,
+    start: String,
+    end: Option<String>,
+    day_of_month: u32,
+}
+
+// Validated amounts of a template or an edited occurrence, in minor units
+struct Amounts {
+    amount: Money,
+    to_amount: Option<Money>,
+    fee: Money,
+    exchange_rate: Option<f64>,
+}
+
+pub fn create_recurring_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS recurring_rules (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            ledger_id INTEGER NOT NULL,
+            account_id INTEGER NOT NULL,
+            type TEXT NOT NULL CHECK( type IN ('expense', 'income', 'transfer') ),
+            to_account_id INTEGER,
+            amount INTEGER NOT NULL CHECK( amount >= 0 ),
+            to_amount INTEGER,
+            fee INTEGER NOT NULL DEFAULT 0 CHECK( fee >= 0 ),
+            exchange_rate REAL,
+            category_id INTEGER,
+            note TEXT,
+            frequency TEXT NOT NULL
+                CHECK( frequency IN ('daily', 'weekly', 'monthly', 'yearly') ),
+            interval INTEGER NOT NULL DEFAULT 1 CHECK( interval >= 1 ),
+            start_date TEXT NOT NULL,
+            end_date TEXT,
+            day_of_month INTEGER CHECK( day_of_month BETWEEN 1 AND 31 ),
+            generated_until TEXT,
+            FOREIGN KEY (ledger_id) REFERENCES ledgers(id) ON DELETE CASCADE,
+            FOREIGN KEY (account_id) REFERENCES accounts(id) ON DELETE CASCADE,
+            FOREIGN KEY (to_account_id) REFERENCES accounts(id) ON DELETE CASCADE,
+            FOREIGN KEY (category_id) REFERENCES categories(id) ON DELETE SET NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS recurring_rule_tags (
+            rule_id INTEGER NOT NULL,
+            tag_id INTEGER NOT NULL,
+            PRIMARY KEY (rule_id, tag_id),
+            FOREIGN KEY (rule_id) REFERENCES recurring_rules(id) ON DELETE CASCADE,
+            FOREIGN KEY (tag_id) REFERENCES tags(id) ON DELETE CASCADE
+        );
+
+        -- A skipped occurrence, or one recorded with its own amounts or note
+        CREATE TABLE IF NOT EXISTS recurring_overrides (
+            rule_id INTEGER NOT NULL,
+            date TEXT NOT NULL,
+            skipped BOOLEAN NOT NULL DEFAULT 0,
+            amount INTEGER,
+            to_amount INTEGER,
+            exchange_rate REAL,
+            note TEXT,
+            PRIMARY KEY (rule_id, date),
+            FOREIGN KEY (rule_id) REFERENCES recurring_rules(id) ON DELETE CASCADE
+        );
+
+        ALTER TABLE transactions
+            ADD COLUMN recurring_rule_id INTEGER REFERENCES recurring_rules(id) ON DELETE SET NULL;
+        ALTER TABLE transactions ADD COLUMN occurrence_date TEXT;
+        CREATE INDEX IF NOT EXISTS idx_transactions_recurring
+            ON transactions (recurring_rule_id, occurrence_date);
+        ",
+    )
+}
+
+// Occurrences already due are recorded straight away
+#[tauri::command(rename_all = "snake_case")]
+pub fn create_recurring_rule(
+    db: State<'_, Database>,
+    rule: RecurringRuleInput,
+) -> BackendResult<i64> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    let (schedule, amounts) = validate_rule(&tx, &rule)?;
+    tx.execute(
+        "INSERT INTO recurring_rules (ledger_id, account_id, type, to_account_id, amount,
+             to_amount, fee, exchange_rate, category_id, note, frequency, interval, start_date,
+             end_date, day_of_month)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+        params![
+            rule.ledger_id,
+            rule.account_id,
+            rule.transaction_type,
+            rule.to_account_id,
+            amounts.amount.minor(),
+            amounts.to_amount.map(|m| m.minor()),
+            amounts.fee.minor(),
+            amounts.exchange_rate,
+            rule.category_id,
+            rule.note,
+            schedule.frequency,
+            schedule.interval,
+            schedule.start,
+            schedule.end,
+            stored_day_of_month(&schedule)
+        ],
+    )
+    .context("Failed to insert recurring rule")?;
+    let rule_id = tx.last_insert_rowid();
+    link_rule_tags(&tx, rule_id, &rule.tags)?;
+
+    let today = today(&tx)?;
+    record_due_occurrences(&tx, &today)?;
+
+    tx.commit().context("Failed to commit recurring rule")?;
+    Ok(rule_id)
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn get_recurring_rules(
+    db: State<'_, Database>,
+    ledger_id: Option<i64>,
+) -> BackendResult<Vec<RecurringRule>> {
+    let conn = db.reader().context("Failed to open database connection")?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT r.id, r.ledger_id, r.account_id, r.type, r.to_account_id, r.amount,
+                 a.currency, cur.minor_unit, r.to_amount, ta.currency, to_cur.minor_unit, r.fee,
+                 r.category_id, r.note, r.frequency, r.interval, r.start_date, r.end_date,
+                 r.day_of_month, r.generated_until
+             FROM recurring_rules r
+             JOIN accounts a ON a.id = r.account_id
+             JOIN currencies cur ON cur.code = a.currency
+             LEFT JOIN accounts ta ON ta.id = r.to_account_id
+             LEFT JOIN currencies to_cur ON to_cur.code = ta.currency
+             WHERE ?1 IS NULL OR r.ledger_id = ?1
+             ORDER BY r.id",
+        )
+        .context("Failed to prepare statement")?;
+
+    let rule_iter = stmt
+        .query_map(params![ledger_id], |row| {
+            let scale: u32 = row.get(7)?;
+            let to_amount = match (row.get::<_, Option<i64>>(8)?, row.get(10)?) {
+                (Some(minor), Some(to_scale)) => Some(Money::from_minor(minor, to_scale)),
+                _ => None,
+            };
+            Ok(RecurringRule {
+                id: row.get(0)?,
+                ledger_id: row.get(1)?,
+                account_id: row.get(2)?,
+                transaction_type: row.get(3)?,
+                to_account_id: row.get(4)?,
+                amount: Money::from_minor(row.get(5)?, scale),
+                currency: row.get(6)?,
+                to_amount,
+                to_currency: row.get(9)?,
+                fee: Money::from_minor(row.get(11)?, scale),
+                category_id: row.get(12)?,
+                tags: Vec::new(),
+                note: row.get(13)?,
+                frequency: row.get(14)?,
+                interval: row.get(15)?,
+                start_date: row.get(16)?,
+                end_date: row.get(17)?,
+                day_of_month: row.get(18)?,
+                generated_until: row.get(19)?,
+                next_date: None,
+            })
+        })
+        .context("Failed to query recurring rules")?;
+
+    let mut rules = Vec::new();
+    for rule in rule_iter {
+        let mut rule = rule.context("Failed to parse recurring rule row")?;
+        rule.tags = rule_tags(&conn, rule.id)?;
+        rule.next_date = load_schedule(&conn, rule.id)?.next_after(rule.generated_until.as_deref());
+        rules.push(rule);
+    }
+
+    Ok(rules)
+}
+
+// Changes apply to occurrences not yet recorded; recorded transactions keep what they were
+// recorded with
+#[tauri::command(rename_all = "snake_case")]
+pub fn update_recurring_rule(
+    db: State<'_, Database>,
+    id: i64,
+    rule: RecurringRuleInput,
+) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    let (schedule, amounts) = validate_rule(&tx, &rule)?;
+    let updated = tx
+        .execute(
+            "UPDATE recurring_rules SET ledger_id = ?1, account_id = ?2, type = ?3,
+                 to_account_id = ?4, amount = ?5, to_amount = ?6, fee = ?7, exchange_rate = ?8,
+                 category_id = ?9, note = ?10, frequency = ?11, interval = ?12,
+                 start_date = ?13, end_date = ?14, day_of_month = ?15
+             WHERE id = ?16",
+            params![
+                rule.ledger_id,
+                rule.account_id,
+                rule.transaction_type,
+                rule.to_account_id,
+                amounts.amount.minor(),
+                amounts.to_amount.map(|m| m.minor()),
+                amounts.fee.minor(),
+                amounts.exchange_rate,
+                rule.category_id,
+                rule.note,
+                schedule.frequency,
+                schedule.interval,
+                schedule.start,
+                schedule.end,
+                stored_day_of_month(&schedule),
+                id
+            ],
+        )
+        .context("Failed to update recurring rule")?;
+    if updated == 0 {
+        return Err(rule_not_found(id));
+    }
+
+    tx.execute(
+        "DELETE FROM recurring_rule_tags WHERE rule_id = ?1",
+        params![id],
+    )
+    .context("Failed to clear recurring rule tags")?;
+    link_rule_tags(&tx, id, &rule.tags)?;
+
+    let today = today(&tx)?;
+    record_due_occurrences(&tx, &today)?;
+
+    tx.commit().context("Failed to commit recurring rule")?;
+    Ok(())
+}
+
+// Recorded transactions stay, no longer linked to a rule
+#[tauri::command(rename_all = "snake_case")]
+pub fn delete_recurring_rule(db: State<'_, Database>, id: i64) -> BackendResult<()> {
+    let conn = db.writer();
+    let deleted = conn
+        .execute("DELETE FROM recurring_rules WHERE id = ?1", params![id])
+        .context("Failed to delete recurring rule")?;
+    if deleted == 0 {
+        return Err(rule_not_found(id));
+    }
+
+    Ok(())
+}
+
+// Every occurrence from `from` to `to` inclusive, with what was or will be recorded for it
+#[tauri::command(rename_all = "snake_case")]
+pub fn get_recurring_occurrences(
+    db: State<'_, Database>,
+    rule_id: i64,
+    from: &str,
+    to: &str,
+) -> BackendResult<Vec<RecurringOccurrence>> {
+    for (field, date) in [("from", from), ("to", to)] {
+        validate_date(field, date)?;
+    }
+
+    let conn = db.reader().context("Failed to open database connection")?;
+    let schedule = load_schedule(&conn, rule_id)?;
+    let (amount, scale, note): (i64, u32, Option<String>) = conn
+        .query_row(
+            "SELECT r.amount, cur.minor_unit, r.note FROM recurring_rules r
+             JOIN accounts a ON a.id = r.account_id
+             JOIN currencies cur ON cur.code = a.currency
+             WHERE r.id = ?1",
+            params![rule_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .context("Failed to look up recurring rule")?;
+
+    let mut recorded = conn
+        .prepare(
+            "SELECT id, amount, note FROM transactions
+             WHERE recurring_rule_id = ?1 AND occurrence_date = ?2",
+        )
+        .context("Failed to prepare statement")?;
+    let mut occurrences = Vec::new();
+    let day_before = add_days(from, -1);
+    for date in schedule.between(Some(&day_before), to) {
+        let transaction = recorded
+            .query_row(params![rule_id, date], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                ))
+            })
+            .optional()
+            .context("Failed to look up recorded occurrence")?;
+        let occurrence = if let Some((transaction_id, amount, note)) = transaction {
+            RecurringOccurrence {
+                date,
+                status: "recorded".to_string(),
+                transaction_id: Some(transaction_id),
+                amount: Money::from_minor(amount, scale),
+                note,
+            }
+        } else {
+            let (status, amount, note) = match occurrence_override(&conn, rule_id, &date)? {
+                Some((true, ..)) => ("skipped", amount, note.clone()),
+                Some((false, edited_amount, _, _, edited_note)) => (
+                    "edited",
+                    edited_amount.unwrap_or(amount),
+                    edited_note.or_else(|| note.clone()),
+                ),
+                None => ("scheduled", amount, note.clone()),
+            };
+            RecurringOccurrence {
+                date,
+                status: status.to_string(),
+                transaction_id: None,
+                amount: Money::from_minor(amount, scale),
+                note,
+            }
+        };
+        occurrences.push(occurrence);
+    }
+
+    Ok(occurrences)
+}
+
+// Leave one occurrence out. One already recorded has its transaction deleted.
+#[tauri::command(rename_all = "snake_case")]
+pub fn skip_recurring_occurrence(
+    db: State<'_, Database>,
+    rule_id: i64,
+    date: &str,
+) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    ensure_occurrence(&tx, rule_id, date)?;
+    if let Some(transaction_id) = recorded_occurrence(&tx, rule_id, date)? {
+        delete_recorded(&tx, transaction_id)?;
+    }
+    tx.execute(
+        "INSERT INTO recurring_overrides (rule_id, date, skipped) VALUES (?1, ?2, 1)
+         ON CONFLICT (rule_id, date) DO UPDATE SET skipped = 1",
+        params![rule_id, date],
+    )
+    .context("Failed to skip occurrence")?;
+
+    let orphans = attachment::take_orphans(&tx)?;
+
+    tx.commit().context("Failed to commit skipped occurrence")?;
+    attachment::remove_files(&db, &orphans)?;
+    Ok(())
+}
+
+// Give one occurrence its own amounts and note. A skipped occurrence is brought back; one whose
+// date has already been worked through is recorded straight away, and one already recorded is
+// changed through its transaction instead.
+#[tauri::command(rename_all = "snake_case")]
+pub fn edit_recurring_occurrence(
+    db: State<'_, Database>,
+    rule_id: i64,
+    date: &str,
+    amount: Money,
+    to_amount: Option<Money>,
+    note: Option<&str>,
+) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    ensure_occurrence(&tx, rule_id, date)?;
+    if let Some(transaction_id) = recorded_occurrence(&tx, rule_id, date)? {
+        return Err(BackendError::validation(
+            "date",
+            format!(
+                "This occurrence is already recorded as transaction {}; edit that instead",
+                transaction_id
+            ),
+        ));
+    }
+
+    let (account_id, transaction_type, to_account_id, fee, scale): (
+        i64,
+        String,
+        Option<i64>,
+        i64,
+        u32,
+    ) = tx
+        .query_row(
+            "SELECT r.account_id, r.type, r.to_account_id, r.fee, cur.minor_unit
+             FROM recurring_rules r
+             JOIN accounts a ON a.id = r.account_id
+             JOIN currencies cur ON cur.code = a.currency
+             WHERE r.id = ?1",
+            params![rule_id],
+            |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            },
+        )
+        .context("Failed to look up recurring rule")?;
+    let amounts = validate_amounts(
+        &tx,
+        account_id,
+        &transaction_type,
+        to_account_id,
+        amount,
+        to_amount,
+        Some(Money::from_minor(fee, scale)),
+    )?;
+
+    tx.execute(
+        "INSERT INTO recurring_overrides (rule_id, date, skipped, amount, to_amount,
+             exchange_rate, note)
+         VALUES (?1, ?2, 0, ?3, ?4, ?5, ?6)
+         ON CONFLICT (rule_id, date) DO UPDATE SET skipped = 0, amount = excluded.amount,
+             to_amount = excluded.to_amount, exchange_rate = excluded.exchange_rate,
+             note = excluded.note",
+        params![
+            rule_id,
+            date,
+            amounts.amount.minor(),
+            amounts.to_amount.map(|m| m.minor()),
+            amounts.exchange_rate,
+            note
+        ],
+    )
+    .context("Failed to edit occurrence")?;
+
+    // Generation only looks past `generated_until`, so an earlier date would never be recorded
+    let generated_until: Option<String> = tx
+        .query_row(
+            "SELECT generated_until FROM recurring_rules WHERE id = ?1",
+            params![rule_id],
+            |row| row.get(0),
+        )
+        .context("Failed to look up recurring rule")?;
+    if generated_until.is_some_and(|until| date <= until.as_str()) {
+        let tags = rule_tags(&tx, rule_id)?;
+        record_occurrence(&tx, rule_id, date, &tags)?;
+    }
+
+    tx.commit().context("Failed to commit edited occurrence")?;
+    Ok(())
+}
+
+// Make the occurrence on `date` the rule's last. Occurrences already recorded after it are
+// deleted along with their transactions.
+#[tauri::command(rename_all = "snake_case")]
+pub fn end_recurring_rule(db: State<'_, Database>, rule_id: i64, date: &str) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    ensure_occurrence(&tx, rule_id, date)?;
+    tx.execute(
+        "UPDATE recurring_rules SET end_date = ?1 WHERE id = ?2",
+        params![date, rule_id],
+    )
+    .context("Failed to end recurring rule")?;
+
+    let later = {
+        let mut stmt = tx
+            .prepare(
+                "SELECT id FROM transactions
+                 WHERE recurring_rule_id = ?1 AND occurrence_date > ?2",
+            )
+            .context("Failed to prepare statement")?;
+        let rows = stmt
+            .query_map(params![rule_id, date], |row| row.get::<_, i64>(0))
+            .context("Failed to query recorded occurrences")?
+            .collect::<Result<Vec<_>>>()
+            .context("Failed to parse recorded occurrence row")?;
+        rows
+    };
+    for transaction_id in later {
+        delete_recorded(&tx, transaction_id)?;
+    }
+    let orphans = attachment::take_orphans(&tx)?;
+
+    tx.commit().context("Failed to commit recurring rule")?;
+    attachment::remove_files(&db, &orphans)?;
+    Ok(())
+}
+
+// Record everything due up to today, for when the app stays open past midnight. Returns the
+// number of transactions recorded.
+#[tauri::command(rename_all = "snake_case")]
+pub fn generate_recurring_transactions(db: State<'_, Database>) -> BackendResult<usize> {
+    record_due(&db)
+}
+
+// Run at startup, before any window can read balances
+pub fn record_due(db: &Database) -> BackendResult<usize> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+    let today = today(&tx)?;
+    let recorded = record_due_occurrences(&tx, &today)?;
+    tx.commit()
+        .context("Failed to commit recurring transactions")?;
+    Ok(recorded)
+}
+
+// Record every rule's occurrences from after `generated_until` up to `today`
+pub fn record_due_occurrences(conn: &Connection, today: &str) -> BackendResult<usize> {
+    let rules = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, generated_until FROM recurring_rules
+                 WHERE start_date <= ?1
+                     AND (generated_until IS NULL OR generated_until < ?1)
+                     AND (end_date IS NULL OR generated_until IS NULL
+                          OR generated_until < end_date)",
+            )
+            .context("Failed to prepare statement")?;
+        let rows = stmt
+            .query_map(params![today], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, Option<String>>(1)?))
+            })
+            .context("Failed to query recurring rules")?
+            .collect::<Result<Vec<_>>>()
+            .context("Failed to parse recurring rule row")?;
+        rows
+    };
+
+    let mut recorded = 0;
+    for (rule_id, generated_until) in rules {
+        let schedule = load_schedule(conn, rule_id)?;
+        let tags = rule_tags(conn, rule_id)?;
+        let dates = schedule.between(generated_until.as_deref(), today);
+
+        for date in &dates {
+            if record_occurrence(conn, rule_id, date, &tags)? {
+                recorded += 1;
+            }
+        }
+
+        if let Some(last) = dates.last() {
+            conn.execute(
+                "UPDATE recurring_rules SET generated_until = ?1 WHERE id = ?2",
+                params![last, rule_id],
+            )
+            .context("Failed to update recurring rule")?;
+        }
+    }
+
+    Ok(recorded)
+}
+
+// Record the rule's occurrence on `date` with any edits made to it. Returns false for a skipped
+// occurrence, which records nothing.
+fn record_occurrence(
+    conn: &Connection,
+    rule_id: i64,
+    date: &str,
+    tags: &[String],
+) -> BackendResult<bool> {
+    let (amount, to_amount, exchange_rate, note) = match occurrence_override(conn, rule_id, date)? {
+        Some((true, ..)) => return Ok(false),
+        Some((false, amount, to_amount, exchange_rate, note)) => {
+            (amount, to_amount, exchange_rate, note)
+        }
+        None => (None, None, None, None),
+    };
+
+    conn.execute(
+        "INSERT INTO transactions (ledger_id, account_id, type, amount, currency, date,
+                     note, category_id, to_account_id, to_amount, to_currency, fee,
+                     exchange_rate, recurring_rule_id, occurrence_date)
+                 SELECT r.ledger_id, r.account_id, r.type, COALESCE(?2, r.amount), a.currency,
+                     ?1, COALESCE(?5, r.note), r.category_id, r.to_account_id,
+                     COALESCE(?3, r.to_amount), ta.currency, r.fee,
+                     COALESCE(?4, r.exchange_rate), r.id, ?1
+                 FROM recurring_rules r
+                 JOIN accounts a ON a.id = r.account_id
+                 LEFT JOIN accounts ta ON ta.id = r.to_account_id
+                 WHERE r.id = ?6",
+        params![date, amount, to_amount, exchange_rate, note, rule_id],
+    )
+    .context("Failed to record recurring transaction")?;
+    let transaction_id = conn.last_insert_rowid();
+    balance::apply_transaction_effects(conn, transaction_id, 1)
+        .context("Failed to update account balance")?;
+    link_tags(conn, transaction_id, tags)?;
+    Ok(true)
+}
+
+impl Schedule {
+    // The occurrence `n` steps after the start, which is occurrence 0
+    fn occurrence(&self, n: i64) -> String {
+        let (year, month, _) = split_date(&self.start);
+        let steps = n * self.interval;
+        match self.frequency.as_str() {
+            "daily" => add_days(&self.start, steps),
+            "weekly" => add_days(&self.start, 7 * steps),
+            "monthly" => {
+                let months = (month as i64 - 1) + steps;
+                let year = year as i64 + months.div_euclid(12);
+                clamped_date(
+                    year as i32,
+                    months.rem_euclid(12) as u32 + 1,
+                    self.day_of_month,
+                )
+            }
+            _ => clamped_date((year as i64 + steps) as i32, month, self.day_of_month),
+        }
+    }
+
+    // Occurrences after `after` (from the start when None) up to `until`, both inclusive of
+    // `until` and the end date
+    fn between(&self, after: Option<&str>, until: &str) -> Vec<String> {
+        let until = match &self.end {
+            Some(end) if end.as_str() < until => end.as_str(),
+            _ => until,
+        };
+
+        let mut n = self.first_step(after);
+        let mut dates = Vec::new();
+        while dates.len() < MAX_OCCURRENCES {
+            let date = self.occurrence(n);
+            if date.as_str() > until {
+                break;
+            }
+            if after.is_none_or(|after| date.as_str() > after) {
+                dates.push(date);
+            }
+            n += 1;
+        }
+        dates
+    }
+
+    // The first occurrence after `after` (from the start when None), or None past the end date
+    fn next_after(&self, after: Option<&str>) -> Option<String> {
+        let mut n = self.first_step(after);
+        loop {
+            let date = self.occurrence(n);
+            if self.end.as_deref().is_some_and(|end| date.as_str() > end) {
+                return None;
+            }
+            if after.is_none_or(|after| date.as_str() > after) {
+                return Some(date);
+            }
+            n += 1;
+        }
+    }
+
+    fn contains(&self, date: &str) -> bool {
+        self.next_after(Some(&add_days(date, -1))).as_deref() == Some(date)
+    }
+
+    // A step whose occurrence falls no later than `after`'s month (its day, for daily and weekly
+    // rules), so every earlier step is on or before `after` and can be skipped
+    fn first_step(&self, after: Option<&str>) -> i64 {
+        let after = match after {
+            Some(after) if after > self.start.as_str() => after,
+            _ => return 0,
+        };
+        let (start_year, start_month, _) = split_date(&self.start);
+        let (after_year, after_month, _) = split_date(after);
+        let elapsed = match self.frequency.as_str() {
+            "daily" => date_days(after) - date_days(&self.start),
+            "weekly" => (date_days(after) - date_days(&self.start)) / 7,
+            "monthly" => {
+                (after_year - start_year) as i64 * 12 + after_month as i64 - start_month as i64
+            }
+            _ => (after_year - start_year) as i64,
+        };
+        elapsed / self.interval
+    }
+}
+
+fn validate_rule(
+    conn: &Connection,
+    rule: &RecurringRuleInput,
+) -> BackendResult<(Schedule, Amounts)> {
+    if !matches!(
+        rule.frequency.as_str(),
+        "daily" | "weekly" | "monthly" | "yearly"
+    ) {
+        return Err(BackendError::validation(
+            "frequency",
+            format!("Unsupported frequency '{}'", rule.frequency),
+        ));
+    }
+    let interval = rule.interval.unwrap_or(1);
+    if interval < 1 {
+        return Err(BackendError::validation(
+            "interval",
+            "Interval must be at least 1",
+        ));
+    }
+    validate_date("start_date", &rule.start_date)?;
+    if let Some(end_date) = &rule.end_date {
+        validate_date("end_date", end_date)?;
+        if end_date < &rule.start_date {
+            return Err(BackendError::validation(
+                "end_date",
+                "End date is before start date",
+            ));
+        }
+    }
+    let day_of_month = rule
+        .day_of_month
+        .unwrap_or_else(|| split_date(&rule.start_date).2);
+    if !(1..=31).contains(&day_of_month) {
+        return Err(BackendError::validation(
+            "day_of_month",
+            "Day of month must be from 1 to 31",
+        ));
+    }
+
+    let amounts = validate_amounts(
+        conn,
+        rule.account_id,
+        &rule.transaction_type,
+        rule.to_account_id,
+        rule.amount,
+        rule.to_amount,
+        rule.fee,
+    )?;
+    category::validate_transaction_category(
+        conn,
+        rule.ledger_id,
+        &rule.transaction_type,
+        rule.category_id,
+    )?;
+
+    let schedule = Schedule {
+        frequency: rule.frequency.clone(),
+        interval,
+        start: rule.start_date.clone(),
+        end: rule.end_date.clone(),
+        day_of_month,
+    };
+    Ok((schedule, amounts))
+}
+
+// The same checks `create_transaction` and `create_transfer` make
+fn validate_amounts(
+    conn: &Connection,
+    account_id: i64,
+    transaction_type: &str,
+    to_account_id: Option<i64>,
+    amount: Money,
+    to_amount: Option<Money>,
+    fee: Option<Money>,
+) -> BackendResult<Amounts> {
+    if transaction_type == "transfer" {
+        let to_account_id = to_account_id.ok_or_else(|| {
+            BackendError::validation("to_account_id", "A transfer needs a destination account")
+        })?;
+        let transfer = validate_transfer(conn, account_id, to_account_id, amount, to_amount, fee)?;
+        return Ok(Amounts {
+            amount: transfer.amount,
+            to_amount: Some(transfer.to_amount),
+            fee: transfer.fee,
+            exchange_rate: transfer.exchange_rate,
+        });
+    }
+
+    if to_account_id.is_some() || to_amount.is_some() {
+        return Err(BackendError::validation(
+            "to_account_id",
+            "Only transfers have a destination",
+        ));
+    }
+    if fee.is_some_and(|fee| !fee.is_zero()) {
+        return Err(BackendError::validation(
+            "fee",
+            "Only transfers carry a fee",
+        ));
+    }
+    let currency = account_currency(conn, account_id, "account_id")?;
+    let amount = validate_transaction(conn, account_id, transaction_type, amount, &currency)?;
+    Ok(Amounts {
+        amount,
+        to_amount: None,
+        fee: to_currency_scale(conn, Money::default(), &currency, "fee")?,
+        exchange_rate: None,
+    })
+}
+
+fn load_schedule(conn: &Connection, rule_id: i64) -> BackendResult<Schedule> {
+    conn.query_row(
+        "SELECT frequency, interval, start_date, end_date, day_of_month
+         FROM recurring_rules WHERE id = ?1",
+        params![rule_id],
+        |row| {
+            let start: String = row.get(2)?;
+            let day_of_month = row
+                .get::<_, Option<u32>>(4)?
+                .unwrap_or_else(|| split_date(&start).2);
+            Ok(Schedule {
+                frequency: row.get(0)?,
+                interval: row.get(1)?,
+                end: row.get(3)?,
+                start,
+                day_of_month,
+            })
+        },
+    )
+    .optional()
+    .context("Failed to look up recurring rule")?
+    .ok_or_else(|| rule_not_found(rule_id).with_field("rule_id"))
+}
+
+// Daily and weekly rules have no day of the month
+fn stored_day_of_month(schedule: &Schedule) -> Option<u32> {
+    matches!(schedule.frequency.as_str(), "monthly" | "yearly").then_some(schedule.day_of_month)
+}
+
+fn ensure_occurrence(conn: &Connection, rule_id: i64, date: &str) -> BackendResult<()> {
+    validate_date("date", date)?;
+    if !load_schedule(conn, rule_id)?.contains(date) {
+        return Err(BackendError::validation(
+            "date",
+            format!("Rule {} has no occurrence on {}", rule_id, date),
+        ));
+    }
+    Ok(())
+}
+
+fn recorded_occurrence(conn: &Connection, rule_id: i64, date: &str) -> BackendResult<Option<i64>> {
+    conn.query_row(
+        "SELECT id FROM transactions WHERE recurring_rule_id = ?1 AND occurrence_date = ?2",
+        params![rule_id, date],
+        |row| row.get(0),
+    )
+    .optional()
+    .context("Failed to look up recorded occurrence")
+}
+
+// (skipped, amount, to_amount, exchange_rate, note) set for one occurrence
+#[allow(clippy::type_complexity)]
+fn occurrence_override(
+    conn: &Connection,
+    rule_id: i64,
+    date: &str,
+) -> BackendResult<Option<(bool, Option<i64>, Option<i64>, Option<f64>, Option<String>)>> {
+    conn.query_row(
+        "SELECT skipped, amount, to_amount, exchange_rate, note FROM recurring_overrides
+         WHERE rule_id = ?1 AND date = ?2",
+        params![rule_id, date],
+        |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+            ))
+        },
+    )
+    .optional()
+    .context("Failed to look up occurrence")
+}
+
+fn delete_recorded(conn: &Connection, transaction_id: i64) -> BackendResult<()> {
+    balance::apply_transaction_effects(conn, transaction_id, -1)
+        .context("Failed to update account balance")?;
+    conn.execute(
+        "DELETE FROM transactions WHERE id = ?1",
+        params![transaction_id],
+    )
+    .context("Failed to delete recorded occurrence")?;
+    Ok(())
+}
+
+fn link_rule_tags(conn: &Connection, rule_id: i64, tags: &[String]) -> BackendResult<()> {
+    for tag in tags {
+        let tag_id = insert_or_get_tag(conn, tag)?;
+        conn.execute(
+            "INSERT OR IGNORE INTO recurring_rule_tags (rule_id, tag_id) VALUES (?1, ?2)",
+            params![rule_id, tag_id],
+        )
+        .context("Failed to link tag to recurring rule")?;
+    }
+    Ok(())
+}
+
+fn rule_tags(conn: &Connection, rule_id: i64) -> BackendResult<Vec<String>> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT t.name FROM tags t
+             JOIN recurring_rule_tags rt ON rt.tag_id = t.id
+             WHERE rt.rule_id = ?1",
+        )
+        .context("Failed to prepare statement")?;
+    let tags = stmt
+        .query_map(params![rule_id], |row| row.get(0))
+        .context("Failed to query tags")?
+        .collect::<Result<Vec<String>>>()
+        .context("Failed to parse tag row")?;
+    Ok(tags)
+}
+
+fn rule_not_found(id: i64) -> BackendError {
+    BackendError::not_found(format!("Recurring rule {} not found", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(frequency: &str, interval: i64, start: &str, end: Option<&str>) -> Schedule {
+        Schedule {
+            frequency: frequency.to_string(),
+            interval,
+            start: start.to_string(),
+            end: end.map(str::to_string),
+            day_of_month: start[8..10].parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn monthly_occurrences_clamp_to_the_end_of_short_months() {
+        let rule = schedule("monthly", 1, "2024-01-31", None);
+        assert_eq!(
+            rule.between(None, "2024-05-31"),
+            [
+                "2024-01-31",
+                "2024-02-29",
+                "2024-03-31",
+                "2024-04-30",
+                "2024-05-31"
+            ]
+        );
+        // Clamping one month does not pull later occurrences off the 31st
+        assert_eq!(
+            rule.between(Some("2024-02-29"), "2024-04-30"),
+            ["2024-03-31", "2024-04-30"]
+        );
+
+        let every_other = schedule("monthly", 2, "2023-12-31", None);
+        assert_eq!(
+            every_other.between(None, "2024-04-30"),
+            ["2023-12-31", "2024-02-29", "2024-04-30"]
+        );
+    }
+
+    #[test]
+    fn yearly_occurrences_on_a_leap_day_fall_back_to_february_28() {
+        let rule = schedule("yearly", 1, "2024-02-29", None);
+        assert_eq!(
+            rule.between(None, "2028-12-31"),
+            [
+                "2024-02-29",
+                "2025-02-28",
+                "2026-02-28",
+                "2027-02-28",
+                "2028-02-29"
+            ]
+        );
+    }
+
+    #[test]
+    fn between_stops_at_the_end_date_and_skips_up_to_after() {
+        let rule = schedule("weekly", 1, "2024-01-01", Some("2024-03-15"));
+        assert_eq!(
+            rule.between(Some("2024-03-01"), "2024-12-31"),
+            ["2024-03-04", "2024-03-11"]
+        );
+        assert_eq!(
+            rule.next_after(Some("2024-03-04")).as_deref(),
+            Some("2024-03-11")
+        );
+        assert_eq!(rule.next_after(Some("2024-03-11")), None);
+        assert_eq!(
+            schedule("monthly", 1, "2024-01-31", None)
+                .next_after(Some("9000-02-15"))
+                .as_deref(),
+            Some("9000-02-28")
+        );
+        assert_eq!(
+            schedule("yearly", 2, "2024-02-29", None)
+                .next_after(Some("2028-02-29"))
+                .as_deref(),
+            Some("2030-02-28")
+        );
+        assert!(rule.between(None, "2023-12-31").is_empty());
+
+        let daily = schedule("daily", 3, "2024-02-27", None);
+        assert_eq!(
+            daily.between(None, "2024-03-05"),
+            ["2024-02-27", "2024-03-01", "2024-03-04"]
+        );
+        assert!(daily.contains("2024-03-04"));
+        assert!(!daily.contains("2024-03-03"));
+    }
+}
@@ -0,0 +1,456 @@
+use crate::backend::db::Database;
+use crate::backend::error::{BackendError, BackendResult, Context};
+use crate::backend::split::load_splits;
+use crate::backend::transaction::{
+    load_tags, transaction_from_row, Transaction, TRANSACTION_FROM, TRANSACTION_SELECT,
+};
+use rusqlite::{params, Connection, Result};
+use tauri::State;
+
+const DEFAULT_SEARCH_LIMIT: u32 = 50;
+const MAX_SEARCH_LIMIT: u32 = 500;
+// Placed around matches by FTS5 and turned into <mark> once the text is escaped. Control
+// characters, so they cannot be confused with anything a user typed.
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+
+// `transaction_search` is an FTS5 index with one row per transaction, keyed by its id. Each
+// column holds text gathered from the transaction and the rows it points at, and triggers on
+// those tables rewrite a transaction's row whenever any of that text changes. The triggers are
+// part of migrations 16 and 20, so changing what is indexed takes a new migration.
+
+// One indexed row per transaction matched by `filter`, a condition on `transactions t`.
+// `{split_*}` are filled in by `search_row_sql`.
+const SEARCH_ROW_SQL: &str = "
+    INSERT INTO transaction_search (rowid, note, tags, category, accounts, month)
+    SELECT t.id,
+        COALESCE(t.note, ''){split_notes},
+        COALESCE((SELECT group_concat(g.name, ' ') FROM transaction_tags tt
+                  JOIN tags g ON g.id = tt.tag_id
+                  WHERE tt.transaction_id = t.id), ''){split_tags},
+        COALESCE(c.name, ''){split_categories},
+        TRIM(COALESCE(a.name, '') || ' ' || COALESCE(ta.name, '')),
+        CASE substr(t.date, 6, 2)
+            WHEN '01' THEN 'January' WHEN '02' THEN 'February' WHEN '03' THEN 'March'
+            WHEN '04' THEN 'April' WHEN '05' THEN 'May' WHEN '06' THEN 'June'
+            WHEN '07' THEN 'July' WHEN '08' THEN 'August' WHEN '09' THEN 'September'
+            WHEN '10' THEN 'October' WHEN '11' THEN 'November' WHEN '12' THEN 'December'
+            ELSE '' END || ' ' || substr(t.date, 1, 4)
+    FROM transactions t
+    LEFT JOIN categories c ON c.id = t.category_id
+    LEFT JOIN accounts a ON a.id = t.account_id
+    LEFT JOIN accounts ta ON ta.id = t.to_account_id
+    WHERE {filter};";
+
+// Since migration 20 the note, tags and category of every split line are indexed along with the
+// transaction's own
+const SPLIT_NOTES_SQL: &str = " || COALESCE(' ' || (SELECT group_concat(s.note, ' ')
+                  FROM transaction_splits s WHERE s.transaction_id = t.id), '')";
+const SPLIT_TAGS_SQL: &str = " || COALESCE(' ' || (SELECT group_concat(g.name, ' ')
+                  FROM transaction_splits s
+                  JOIN split_tags st ON st.split_id = s.id
+                  JOIN tags g ON g.id = st.tag_id
+                  WHERE s.transaction_id = t.id), '')";
+const SPLIT_CATEGORIES_SQL: &str = " || COALESCE(' ' || (SELECT group_concat(sc.name, ' ')
+                  FROM transaction_splits s
+                  JOIN categories sc ON sc.id = s.category_id
+                  WHERE s.transaction_id = t.id), '')";
+
+// (trigger, event, transactions to reindex) as created by migration 16
+const SEARCH_TRIGGERS: &[(&str, &str, &str)] = &[
+    (
+        "transactions_search_insert",
+        "AFTER INSERT ON transactions",
+        "t.id = NEW.id",
+    ),
+    (
+        "transactions_search_update",
+        "AFTER UPDATE OF note, category_id, account_id, to_account_id, date ON transactions",
+        "t.id IN (OLD.id, NEW.id)",
+    ),
+    (
+        "transaction_tags_search_insert",
+        "AFTER INSERT ON transaction_tags",
+        "t.id = NEW.transaction_id",
+    ),
+    (
+        "transaction_tags_search_delete",
+        "AFTER DELETE ON transaction_tags",
+        "t.id = OLD.transaction_id",
+    ),
+    (
+        "tags_search_update",
+        "AFTER UPDATE OF name ON tags",
+        "t.id IN (SELECT transaction_id FROM transaction_tags WHERE tag_id = NEW.id)",
+    ),
+    (
+        "categories_search_update",
+        "AFTER UPDATE OF name ON categories",
+        "t.category_id = NEW.id",
+    ),
+    (
+        "accounts_search_update",
+        "AFTER UPDATE OF name ON accounts",
+        "NEW.id IN (t.account_id, t.to_account_id)",
+    ),
+];
+
+// The triggers as replaced and added by migration 20, which indexes split lines
+const SPLIT_SEARCH_TRIGGERS: &[(&str, &str, &str)] = &[
+    (
+        "transactions_search_insert",
+        "AFTER INSERT ON transactions",
+        "t.id = NEW.id",
+    ),
+    (
+        "transactions_search_update",
+        "AFTER UPDATE OF note, category_id, account_id, to_account_id, date ON transactions",
+        "t.id IN (OLD.id, NEW.id)",
+    ),
+    (
+        "transaction_tags_search_insert",
+        "AFTER INSERT ON transaction_tags",
+        "t.id = NEW.transaction_id",
+    ),
+    (
+        "transaction_tags_search_delete",
+        "AFTER DELETE ON transaction_tags",
+        "t.id = OLD.transaction_id",
+    ),
+    (
+        "tags_search_update",
+        "AFTER UPDATE OF name ON tags",
+        "t.id IN (SELECT transaction_id FROM transaction_tags WHERE tag_id = NEW.id
+                  UNION SELECT s.transaction_id FROM transaction_splits s
+                  JOIN split_tags st ON st.split_id = s.id WHERE st.tag_id = NEW.id)",
+    ),
+    (
+        "categories_search_update",
+        "AFTER UPDATE OF name ON categories",
+        "t.category_id = NEW.id
+         OR t.id IN (SELECT transaction_id FROM transaction_splits WHERE category_id = NEW.id)",
+    ),
+    (
+        "accounts_search_update",
+        "AFTER UPDATE OF name ON accounts",
+        "NEW.id IN (t.account_id, t.to_account_id)",
+    ),
+    (
+        "transaction_splits_search_insert",
+        "AFTER INSERT ON transaction_splits",
+        "t.id = NEW.transaction_id",
+    ),
+    (
+        "transaction_splits_search_update",
+        "AFTER UPDATE OF transaction_id, category_id, note ON transaction_splits",
+        "t.id IN (OLD.transaction_id, NEW.transaction_id)",
+    ),
+    (
+        "transaction_splits_search_delete",
+        "AFTER DELETE ON transaction_splits",
+        "t.id = OLD.transaction_id",
+    ),
+    (
+        "split_tags_search_insert",
+        "AFTER INSERT ON split_tags",
+        "t.id IN (SELECT transaction_id FROM transaction_splits WHERE id = NEW.split_id)",
+    ),
+    (
+        "split_tags_search_delete",
+        "AFTER DELETE ON split_tags",
+        "t.id IN (SELECT transaction_id FROM transaction_splits WHERE id = OLD.split_id)",
+    ),
+];
+
+#[derive(serde::Serialize)]
+pub struct SearchHit {
+    pub transaction: Transaction,
+    // Higher is a better match
+    pub score: f64,
+    // The best matching field as HTML-escaped text, with each match wrapped in <mark></mark>
+    pub snippet: String,
+}
+
+// Create the index and its triggers and fill it from the existing transactions
+pub fn create_transaction_search(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        CREATE VIRTUAL TABLE IF NOT EXISTS transaction_search USING fts5(
+            note, tags, category, accounts, month,
+            tokenize = 'unicode61 remove_diacritics 2',
+            prefix = '2 3'
+        );
+
+        CREATE TRIGGER IF NOT EXISTS transactions_search_delete
+        AFTER DELETE ON transactions BEGIN
+            DELETE FROM transaction_search WHERE rowid = OLD.id;
+        END;
+        ",
+    )?;
+
+    create_search_triggers(conn, SEARCH_TRIGGERS, false)?;
+    conn.execute_batch(&search_row_sql("TRUE", false))
+}
+
+// Migration 20: index the note, tags and category of split lines, and keep them up to date
+pub fn index_split_lines(conn: &Connection) -> Result<()> {
+    for (name, _, _) in SEARCH_TRIGGERS {
+        conn.execute_batch(&format!("DROP TRIGGER IF EXISTS {};", name))?;
+    }
+    create_search_triggers(conn, SPLIT_SEARCH_TRIGGERS, true)?;
+
+    conn.execute_batch("DELETE FROM transaction_search;")?;
+    conn.execute_batch(&search_row_sql("TRUE", true))
+}
+
+fn create_search_triggers(
+    conn: &Connection,
+    triggers: &[(&str, &str, &str)],
+    splits: bool,
+) -> Result<()> {
+    for (name, event, filter) in triggers {
+        conn.execute_batch(&format!(
+            "CREATE TRIGGER IF NOT EXISTS {name} {event} BEGIN
+                 DELETE FROM transaction_search WHERE rowid IN (
+                     SELECT t.id FROM transactions t WHERE {filter});
+                 {insert}
+             END;",
+            name = name,
+            event = event,
+            filter = filter,
+            insert = search_row_sql(filter, splits),
+        ))?;
+    }
+    Ok(())
+}
+
+fn search_row_sql(filter: &str, splits: bool) -> String {
+    let part = |sql| if splits { sql } else { "" };
+    SEARCH_ROW_SQL
+        .replace("{split_notes}", part(SPLIT_NOTES_SQL))
+        .replace("{split_tags}", part(SPLIT_TAGS_SQL))
+        .replace("{split_categories}", part(SPLIT_CATEGORIES_SQL))
+        .replace("{filter}", filter)
+}
+
+// Transactions whose note, tags, category, accounts or month and year, or the note, tags or
+// category of one of their split lines, contain every word of `query`, best match first. Words
+// match as prefixes, so "gra mar" finds a Grab ride in March.
+#[tauri::command(rename_all = "snake_case")]
+pub fn search_transactions(
+    db: State<'_, Database>,
+    query: &str,
+    ledger_id: Option<i64>,
+    limit: Option<u32>,
+) -> BackendResult<Vec<SearchHit>> {
+    let expression = match_expression(query)
+        .ok_or_else(|| BackendError::validation("query", "Enter something to search for"))?;
+    let limit = limit
+        .unwrap_or(DEFAULT_SEARCH_LIMIT)
+        .clamp(1, MAX_SEARCH_LIMIT);
+
+    let conn = db.reader().context("Failed to open database connection")?;
+    // Notes weigh most, then tags, category, accounts and finally the month
+    let mut stmt = conn
+        .prepare(&format!(
+            "{select},
+                 snippet(transaction_search, -1, char(2), char(3), '…', 12),
+                 bm25(transaction_search, 10.0, 5.0, 3.0, 2.0, 1.0) AS rank
+             {from}
+             JOIN transaction_search ON transaction_search.rowid = t.id
+             WHERE transaction_search MATCH ?1 AND (?2 IS NULL OR t.ledger_id = ?2)
+             ORDER BY rank, t.date DESC
+             LIMIT ?3",
+            select = TRANSACTION_SELECT,
+            from = TRANSACTION_FROM,
+        ))
+        .context("Failed to prepare statement")?;
+
+    let row_iter = stmt
+        .query_map(params![expression, ledger_id, limit], |row| {
+            // bm25 is lower for better matches
+            Ok((
+                transaction_from_row(row)?,
+                row.get::<_, String>(18)?,
+                -row.get::<_, f64>(19)?,
+            ))
+        })
+        .context("Failed to search transactions")?;
+
+    let mut transactions = Vec::new();
+    let mut matches = Vec::new();
+    for row in row_iter {
+        let (transaction, snippet, score) = row.context("Failed to parse search result")?;
+        transactions.push(transaction);
+        matches.push((highlight(&snippet), score));
+    }
+    load_tags(&conn, &mut transactions)?;
+    load_splits(&conn, &mut transactions)?;
+
+    Ok(transactions
+        .into_iter()
+        .zip(matches)
+        .map(|(transaction, (snippet, score))| SearchHit {
+            transaction,
+            score,
+            snippet,
+        })
+        .collect())
+}
+
+// Escape the snippet's text so it can be rendered as HTML, and mark its matches
+fn highlight(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len());
+    for c in snippet.chars() {
+        match c {
+            MATCH_START => html.push_str("<mark>"),
+            MATCH_END => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+// Each word becomes a quoted prefix term, so user input can never be read as FTS5 syntax
+fn match_expression(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect();
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::account::update_account;
+    use crate::backend::category::{insert_category, update_category};
+    use crate::backend::split::{set_transaction_splits, SplitLine};
+    use crate::backend::tag::{get_tags, update_tag};
+    use crate::backend::testing::{money, Fixture};
+    use crate::backend::transaction::{create_transaction, delete_transaction, update_transaction};
+
+    fn ids(fixture: &Fixture, query: &str) -> Vec<i64> {
+        search_transactions(fixture.db(), query, None, None)
+            .unwrap()
+            .iter()
+            .map(|hit| hit.transaction.id)
+            .collect()
+    }
+
+    fn line(amount: &str, category_id: Option<i64>, tags: &[&str], note: &str) -> SplitLine {
+        SplitLine {
+            amount: money(amount),
+            category_id,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            note: Some(note.to_string()),
+        }
+    }
+
+    #[test]
+    fn triggers_keep_the_index_in_step_with_every_searched_field() {
+        let fixture = Fixture::new();
+        let ledger = fixture.ledger("USD");
+        let wallet = fixture.account("Wallet", "debit", "USD", "0");
+        let transport = insert_category(
+            fixture.db(),
+            ledger,
+            None,
+            "Transport",
+            None,
+            None,
+            "expense",
+        )
+        .unwrap();
+        let food =
+            insert_category(fixture.db(), ledger, None, "Food", None, None, "expense").unwrap();
+        let id = create_transaction(
+            fixture.db(),
+            ledger,
+            wallet,
+            "expense",
+            Some(transport),
+            money("12"),
+            "USD",
+            "2024-03-14",
+            vec!["work".to_string()],
+            Some("Grab ride"),
+        )
+        .unwrap();
+        assert_eq!(ids(&fixture, "grab work transport wallet march"), vec![id]);
+
+        update_transaction(
+            fixture.db(),
+            id,
+            ledger,
+            wallet,
+            "expense",
+            Some(transport),
+            money("12"),
+            "USD",
+            "2024-04-02",
+            vec![],
+            Some("Taxi home"),
+        )
+        .unwrap();
+        assert!(ids(&fixture, "grab").is_empty());
+        assert!(ids(&fixture, "work").is_empty());
+        assert!(ids(&fixture, "march").is_empty());
+        assert_eq!(ids(&fixture, "taxi april"), vec![id]);
+
+        update_category(fixture.db(), transport, "Travel", None, None, "expense").unwrap();
+        update_account(
+            fixture.db(),
+            wallet,
+            "Purse",
+            "debit",
+            money("0"),
+            "USD",
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(ids(&fixture, "transport").is_empty());
+        assert!(ids(&fixture, "wallet").is_empty());
+        assert_eq!(ids(&fixture, "travel purse"), vec![id]);
+
+        // Split lines are searched through their own note, tags and category
+        set_transaction_splits(
+            fixture.db(),
+            id,
+            vec![
+                line("8", Some(transport), &[], "fare"),
+                line("4", Some(food), &["snack"], "crisps"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(ids(&fixture, "crisps snack food"), vec![id]);
+        let snack = get_tags(fixture.db())
+            .unwrap()
+            .into_iter()
+            .find(|tag| tag.name == "snack")
+            .unwrap();
+        update_tag(fixture.db(), snack.id, "treat", None).unwrap();
+        update_category(fixture.db(), food, "Groceries", None, None, "expense").unwrap();
+        assert!(ids(&fixture, "snack").is_empty());
+        assert_eq!(ids(&fixture, "treat groceries"), vec![id]);
+
+        set_transaction_splits(fixture.db(), id, vec![]).unwrap();
+        assert!(ids(&fixture, "crisps").is_empty());
+        assert!(ids(&fixture, "treat").is_empty());
+
+        delete_transaction(fixture.db(), id).unwrap();
+        assert!(ids(&fixture, "taxi").is_empty());
+    }
+}
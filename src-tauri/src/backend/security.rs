@@ -0,0 +1,418 @@
+use crate::backend::currency;
+use crate::backend::date::is_iso_date;
+use crate::backend::db::Database;
+use crate::backend::error::{BackendError, BackendResult, Context};
+use crate::backend::money::Money;
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use tauri::State;
+
+// A tradable instrument. Its prices, and the trades and cost basis of every holding of it, are
+// in its own currency.
+#[derive(serde::Serialize)]
+pub struct Security {
+    pub id: i64,
+    pub symbol: String,
+    pub name: String,
+    pub currency: String,
+    pub asset_class: String,
+    pub latest_price: Option<Money>,
+    pub latest_price_date: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SecurityPrice {
+    pub id: i64,
+    pub security_id: i64,
+    pub date: String,
+    pub price: Money,
+    pub source: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct PriceImport {
+    pub imported: usize,
+}
+
+pub fn create_securities_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS securities (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            symbol TEXT NOT NULL UNIQUE,
+            name TEXT NOT NULL,
+            currency TEXT NOT NULL,
+            asset_class TEXT NOT NULL DEFAULT 'other' CHECK( asset_class IN
+                ('stock', 'bond', 'fund', 'etf', 'crypto', 'commodity', 'cash', 'other') ),
+            FOREIGN KEY (currency) REFERENCES currencies(code) ON DELETE RESTRICT
+        );
+
+        CREATE TABLE IF NOT EXISTS security_prices (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            security_id INTEGER NOT NULL,
+            date TEXT NOT NULL,
+            price INTEGER NOT NULL CHECK( price >= 0 ),
+            source TEXT,
+            UNIQUE (security_id, date),
+            FOREIGN KEY (security_id) REFERENCES securities(id) ON DELETE CASCADE
+        );
+        ",
+    )
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn create_security(
+    db: State<'_, Database>,
+    symbol: &str,
+    name: &str,
+    currency: &str,
+    asset_class: &str,
+) -> BackendResult<i64> {
+    let symbol = validate_symbol(symbol)?;
+    let conn = db.writer();
+    conn.execute(
+        "INSERT INTO securities (symbol, name, currency, asset_class) VALUES (?1, ?2, ?3, ?4)",
+        params![symbol, name, currency, asset_class],
+    )
+    .context("Failed to insert security")?;
+
+    Ok(conn.last_insert_rowid())
+}
+
+#[tauri::command]
+pub fn get_securities(db: State<'_, Database>) -> BackendResult<Vec<Security>> {
+    let conn = db.reader().context("Failed to open database connection")?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT s.id, s.symbol, s.name, s.currency, s.asset_class, cur.minor_unit,
+                 p.price, p.date
+             FROM securities s
+             JOIN currencies cur ON cur.code = s.currency
+             LEFT JOIN security_prices p ON p.id = (
+                 SELECT id FROM security_prices WHERE security_id = s.id
+                 ORDER BY date DESC LIMIT 1)
+             ORDER BY s.symbol",
+        )
+        .context("Failed to prepare statement")?;
+
+    let security_iter = stmt
+        .query_map([], |row| {
+            let scale: u32 = row.get(5)?;
+            Ok(Security {
+                id: row.get(0)?,
+                symbol: row.get(1)?,
+                name: row.get(2)?,
+                currency: row.get(3)?,
+                asset_class: row.get(4)?,
+                latest_price: row
+                    .get::<_, Option<i64>>(6)?
+                    .map(|minor| Money::from_minor(minor, scale)),
+                latest_price_date: row.get(7)?,
+            })
+        })
+        .context("Failed to query securities")?;
+
+    let mut securities = Vec::new();
+    for security in security_iter {
+        securities.push(security.context("Failed to parse security row")?);
+    }
+
+    Ok(securities)
+}
+
+// The currency can only change while nothing is priced or traded in it
+#[tauri::command(rename_all = "snake_case")]
+pub fn update_security(
+    db: State<'_, Database>,
+    id: i64,
+    symbol: &str,
+    name: &str,
+    currency: &str,
+    asset_class: &str,
+) -> BackendResult<()> {
+    let symbol = validate_symbol(symbol)?;
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    let in_use: bool = tx
+        .query_row(
+            "SELECT EXISTS (
+                 SELECT 1 FROM securities s
+                 WHERE s.id = ?1 AND s.currency != ?2 AND (
+                     EXISTS (SELECT 1 FROM security_prices WHERE security_id = s.id)
+                     OR EXISTS (SELECT 1 FROM holdings WHERE security_id = s.id))
+             )",
+            params![id, currency],
+            |row| row.get(0),
+        )
+        .context("Failed to check security usage")?;
+    if in_use {
+        return Err(BackendError::validation(
+            "currency",
+            "Cannot change the currency of a security that has prices or holdings",
+        ));
+    }
+
+    let updated = tx
+        .execute(
+            "UPDATE securities SET symbol = ?1, name = ?2, currency = ?3, asset_class = ?4
+             WHERE id = ?5",
+            params![symbol, name, currency, asset_class, id],
+        )
+        .context("Failed to update security")?;
+    if updated == 0 {
+        return Err(security_not_found(id));
+    }
+
+    tx.commit().context("Failed to commit security")?;
+    Ok(())
+}
+
+// Fails with a foreign key error while any account holds the security
+#[tauri::command(rename_all = "snake_case")]
+pub fn delete_security(db: State<'_, Database>, id: i64) -> BackendResult<()> {
+    let conn = db.writer();
+    let deleted = conn
+        .execute("DELETE FROM securities WHERE id = ?1", params![id])
+        .context("Failed to delete security")?;
+    if deleted == 0 {
+        return Err(security_not_found(id));
+    }
+
+    Ok(())
+}
+
+// Record the price on `date`, replacing one already stored for that day
+#[tauri::command(rename_all = "snake_case")]
+pub fn add_security_price(
+    db: State<'_, Database>,
+    security_id: i64,
+    date: &str,
+    price: Money,
+    source: Option<&str>,
+) -> BackendResult<i64> {
+    let conn = db.writer();
+    upsert_price(&conn, security_id, date, price, source)
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn get_security_prices(
+    db: State<'_, Database>,
+    security_id: i64,
+) -> BackendResult<Vec<SecurityPrice>> {
+    let conn = db.reader().context("Failed to open database connection")?;
+    let scale = security_scale(&conn, security_id)?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, security_id, date, price, source FROM security_prices
+             WHERE security_id = ?1
+             ORDER BY date DESC",
+        )
+        .context("Failed to prepare statement")?;
+
+    let price_iter = stmt
+        .query_map(params![security_id], |row| {
+            Ok(SecurityPrice {
+                id: row.get(0)?,
+                security_id: row.get(1)?,
+                date: row.get(2)?,
+                price: Money::from_minor(row.get(3)?, scale),
+                source: row.get(4)?,
+            })
+        })
+        .context("Failed to query security prices")?;
+
+    let mut prices = Vec::new();
+    for price in price_iter {
+        prices.push(price.context("Failed to parse security price row")?);
+    }
+
+    Ok(prices)
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn delete_security_price(db: State<'_, Database>, id: i64) -> BackendResult<()> {
+    let conn = db.writer();
+    let deleted = conn
+        .execute("DELETE FROM security_prices WHERE id = ?1", params![id])
+        .context("Failed to delete security price")?;
+    if deleted == 0 {
+        return Err(BackendError::not_found(format!(
+            "Security price {} not found",
+            id
+        )));
+    }
+
+    Ok(())
+}
+
+// Import CSV text with a header row naming `date`, `symbol` and `price` columns in any order.
+// Fields may be quoted, and symbols match in any case.
+// Prices are in each security's own currency. Nothing is imported if any line is invalid.
+#[tauri::command(rename_all = "snake_case")]
+pub fn import_security_prices(
+    db: State<'_, Database>,
+    csv: &str,
+    source: Option<&str>,
+) -> BackendResult<PriceImport> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(csv.as_bytes());
+    let csv_error =
+        |err: csv::Error| BackendError::validation("csv", format!("Invalid CSV: {}", err));
+
+    let header: Vec<String> = reader
+        .headers()
+        .map_err(csv_error)?
+        .iter()
+        .map(str::to_ascii_lowercase)
+        .collect();
+    if header.iter().all(String::is_empty) {
+        return Err(BackendError::validation("csv", "The file is empty"));
+    }
+    let column = |name: &str| {
+        header.iter().position(|h| h == name).ok_or_else(|| {
+            BackendError::validation("csv", format!("Missing '{}' column in header", name))
+        })
+    };
+    let (date_col, symbol_col, price_col) = (column("date")?, column("symbol")?, column("price")?);
+
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+    let mut imported = 0;
+    for record in reader.records() {
+        let record = record.map_err(csv_error)?;
+        if record.iter().all(str::is_empty) {
+            continue;
+        }
+        let line = record.position().map_or(0, |position| position.line());
+        let line_error = |err: BackendError| err.context(&format!("Line {}", line));
+        let cell = |col: usize| {
+            record
+                .get(col)
+                .ok_or_else(|| line_error(BackendError::validation("csv", "Too few columns")))
+        };
+
+        // Symbols are stored uppercase
+        let symbol = cell(symbol_col)?.to_uppercase();
+        let security_id: i64 = tx
+            .query_row(
+                "SELECT id FROM securities WHERE symbol = ?1",
+                params![symbol],
+                |row| row.get(0),
+            )
+            .optional()
+            .context("Failed to look up security")?
+            .ok_or_else(|| {
+                line_error(BackendError::not_found(format!(
+                    "Security '{}' not found",
+                    symbol
+                )))
+            })?;
+
+        let price_text = cell(price_col)?;
+        let price = Money::parse(price_text).map_err(|_| {
+            line_error(BackendError::validation(
+                "csv",
+                format!("'{}' is not a valid price", price_text),
+            ))
+        })?;
+
+        upsert_price(&tx, security_id, cell(date_col)?, price, source).map_err(line_error)?;
+        imported += 1;
+    }
+
+    tx.commit().context("Failed to commit security prices")?;
+    Ok(PriceImport { imported })
+}
+
+// Latest price on or before `date` and the day it was recorded
+pub fn latest_price(
+    conn: &Connection,
+    security_id: i64,
+    date: &str,
+) -> BackendResult<Option<(Money, String)>> {
+    let scale = security_scale(conn, security_id)?;
+    let found = conn
+        .query_row(
+            "SELECT price, date FROM security_prices
+             WHERE security_id = ?1 AND date <= ?2
+             ORDER BY date DESC LIMIT 1",
+            params![security_id, date],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+        )
+        .optional()
+        .context("Failed to look up security price")?;
+
+    Ok(found.map(|(minor, date)| (Money::from_minor(minor, scale), date)))
+}
+
+// Minor units of the security's currency
+pub fn security_scale(conn: &Connection, security_id: i64) -> BackendResult<u32> {
+    let currency: String = conn
+        .query_row(
+            "SELECT currency FROM securities WHERE id = ?1",
+            params![security_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .context("Failed to look up security")?
+        .ok_or_else(|| security_not_found(security_id).with_field("security_id"))?;
+
+    currency::minor_unit(conn, &currency)
+        .context(&format!("Failed to look up currency {}", currency))
+        .map_err(|err| err.with_field("currency"))
+}
+
+fn upsert_price(
+    conn: &Connection,
+    security_id: i64,
+    date: &str,
+    price: Money,
+    source: Option<&str>,
+) -> BackendResult<i64> {
+    if !is_iso_date(date) {
+        return Err(BackendError::validation(
+            "date",
+            format!("'{}' is not a YYYY-MM-DD date", date),
+        ));
+    }
+    let scale = security_scale(conn, security_id)?;
+    let price = price
+        .to_scale(scale)
+        .map_err(|err| BackendError::from(err).with_field("price"))?;
+    if price.is_negative() {
+        return Err(BackendError::validation(
+            "price",
+            "Price must not be negative",
+        ));
+    }
+
+    conn.query_row(
+        "INSERT INTO security_prices (security_id, date, price, source)
+         VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT (security_id, date)
+         DO UPDATE SET price = excluded.price, source = excluded.source
+         RETURNING id",
+        params![security_id, date, price.minor(), source],
+        |row| row.get(0),
+    )
+    .context("Failed to store security price")
+}
+
+fn validate_symbol(symbol: &str) -> BackendResult<String> {
+    let symbol = symbol.trim().to_uppercase();
+    if symbol.is_empty() || symbol.chars().any(char::is_whitespace) {
+        return Err(BackendError::validation(
+            "symbol",
+            "Symbol must be set and contain no spaces",
+        ));
+    }
+    Ok(symbol)
+}
+
+fn security_not_found(id: i64) -> BackendError {
+    BackendError::not_found(format!("Security {} not found", id))
+}
@@ -0,0 +1,17 @@
+use serde::de::DeserializeOwned;
+use tauri::{AppHandle, Manager, Wry};
+use tauri_plugin_store::{with_store, StoreCollection};
+
+// Store file shared with the frontend, which writes settings through the store plugin
+pub const SETTINGS_STORE: &str = "settings.json";
+
+// Read a single setting, treating a missing store, missing key or wrongly typed value as unset
+pub fn get_setting<T: DeserializeOwned>(app: &AppHandle, key: &str) -> Option<T> {
+    let stores = app.try_state::<StoreCollection<Wry>>()?;
+    let value = with_store(app.clone(), stores, SETTINGS_STORE, |store| {
+        Ok(store.get(key).cloned())
+    })
+    .ok()??;
+
+    serde_json::from_value(value).ok()
+}
@@ -0,0 +1,368 @@
+use crate::backend::currency;
+use crate::backend::date::{is_iso_date, today};
+use crate::backend::db::Database;
+use crate::backend::error::{BackendError, BackendResult, Context};
+use crate::backend::exchange_rate::{convert, pivot_currency};
+use crate::backend::invest::holdings_value;
+use crate::backend::ledger::ledger_currency;
+use crate::backend::money::{add, sub, Money};
+use rusqlite::{params, Connection, Result};
+use std::collections::{BTreeMap, HashMap};
+use tauri::{AppHandle, State};
+
+// Longest series a single history request may produce, about 27 years of daily points
+const MAX_POINTS: usize = 10_000;
+
+#[derive(serde::Serialize)]
+pub struct NetWorthHistory {
+    pub currency: String,
+    pub interval: String,
+    pub points: Vec<NetWorthPoint>,
+}
+
+#[derive(serde::Serialize)]
+pub struct NetWorthPoint {
+    pub date: String,
+    pub assets: Money,
+    pub liabilities: Money,
+    pub net_worth: Money,
+}
+
+// An account's figures at the end of a day, in minor units of its currency. `value` is what it
+// adds to net worth: the balance, negative for what is owed on a credit account, plus holdings
+// for an invest account. Captured rows record what the account showed at the time. Days without
+// one are derived from transactions with the same formula when history is read.
+pub fn create_balance_snapshots_table(conn: &Connection) -> Result<()> {
+    let create_table_sql = "
+    CREATE TABLE IF NOT EXISTS balance_snapshots (
+        account_id INTEGER NOT NULL,
+        date TEXT NOT NULL,
+        balance INTEGER NOT NULL,
+        value INTEGER NOT NULL,
+        source TEXT NOT NULL CHECK( source = 'captured' ),
+        PRIMARY KEY (account_id, date),
+        FOREIGN KEY (account_id) REFERENCES accounts(id) ON DELETE CASCADE
+    );";
+
+    conn.execute(create_table_sql, [])?;
+    Ok(())
+}
+
+// Record every account's current balance under today's date, replacing any row already there.
+// Invest accounts are captured with their holdings at today's prices.
+#[tauri::command]
+pub fn capture_balance_snapshot(app: AppHandle, db: State<'_, Database>) -> BackendResult<String> {
+    let pivot = pivot_currency(&app);
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+    let date = today(&tx)?;
+
+    tx.execute(
+        "INSERT INTO balance_snapshots (account_id, date, balance, value, source)
+         SELECT id, ?1, balance, balance, 'captured' FROM accounts WHERE true
+         ON CONFLICT (account_id, date) DO UPDATE SET
+             balance = excluded.balance, value = excluded.value, source = 'captured'",
+        params![date],
+    )
+    .context("Failed to capture balances")?;
+
+    let invest_accounts = {
+        let mut stmt = tx
+            .prepare("SELECT id, currency FROM accounts WHERE type = 'invest'")
+            .context("Failed to prepare statement")?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })
+            .context("Failed to query accounts")?
+            .collect::<Result<Vec<_>>>()
+            .context("Failed to parse account row")?;
+        rows
+    };
+    for (account_id, currency) in invest_accounts {
+        let holdings = holdings_value(&tx, account_id, &currency, &date, &pivot)?;
+        tx.execute(
+            "UPDATE balance_snapshots SET value = value + ?1 WHERE account_id = ?2 AND date = ?3",
+            params![holdings.minor(), account_id, date],
+        )
+        .context("Failed to capture holdings")?;
+    }
+
+    tx.commit().context("Failed to commit balance snapshot")?;
+    Ok(date)
+}
+
+// Net worth at the end of each `interval` ('daily', 'weekly' or 'monthly') from `from` to `to`,
+// with `to` always the last point. Captured snapshots are used where they exist; other days are
+// derived from transactions.
+#[tauri::command(rename_all = "snake_case")]
+pub fn get_net_worth_history(
+    app: AppHandle,
+    db: State<'_, Database>,
+    from: &str,
+    to: &str,
+    interval: &str,
+    ledger_id: Option<i64>,
+    currency: Option<&str>,
+) -> BackendResult<NetWorthHistory> {
+    let pivot = pivot_currency(&app);
+    let conn = db.reader().context("Failed to open database connection")?;
+
+    let currency = match currency {
+        Some(currency) => currency.to_string(),
+        None => ledger_currency(&conn, ledger_id)?,
+    };
+    let points = net_worth_history(&conn, &currency, from, to, interval, &pivot)?;
+
+    Ok(NetWorthHistory {
+        currency,
+        interval: interval.to_string(),
+        points,
+    })
+}
+
+pub fn net_worth_history(
+    conn: &Connection,
+    currency: &str,
+    from: &str,
+    to: &str,
+    interval: &str,
+    pivot: &str,
+) -> BackendResult<Vec<NetWorthPoint>> {
+    let dates = period_ends(conn, from, to, interval)?;
+    let accounts = account_values(conn, &dates, pivot)?;
+
+    let scale = currency::minor_unit(conn, currency)
+        .context(&format!("Failed to look up currency {}", currency))
+        .map_err(|err| err.with_field("currency"))?;
+
+    let mut points = Vec::with_capacity(dates.len());
+    for (index, date) in dates.into_iter().enumerate() {
+        // Counted accounts' values summed per currency and split by sign, so each group needs a
+        // single conversion
+        let mut groups: BTreeMap<&str, (u32, i64, i64)> = BTreeMap::new();
+        for account in &accounts {
+            let value = account.values[index];
+            let group = groups
+                .entry(&account.currency)
+                .or_insert((account.scale, 0, 0));
+            let total = if value > 0 {
+                group.1.checked_add(value).map(|total| group.1 = total)
+            } else {
+                group.2.checked_add(-value).map(|total| group.2 = total)
+            };
+            total
+                .ok_or_else(|| BackendError::validation("currency", "Net worth is out of range"))?;
+        }
+
+        let mut point = NetWorthPoint {
+            date,
+            assets: Money::zero(scale),
+            liabilities: Money::zero(scale),
+            net_worth: Money::zero(scale),
+        };
+        for (native_currency, (native_scale, positive, negative)) in groups {
+            let convert_total = |minor: i64| {
+                convert(
+                    conn,
+                    Money::from_minor(minor, native_scale),
+                    native_currency,
+                    currency,
+                    &point.date,
+                    pivot,
+                )
+                .map(|conversion| conversion.amount)
+            };
+            let assets = convert_total(positive)?;
+            let liabilities = convert_total(negative)?;
+
+            point.assets = add(point.assets, assets)?;
+            point.liabilities = add(point.liabilities, liabilities)?;
+            point.net_worth = add(point.net_worth, sub(assets, liabilities)?)?;
+        }
+        points.push(point);
+    }
+
+    Ok(points)
+}
+// The last day of each period between `from` and `to`, ending with `to` itself
+fn period_ends(
+    conn: &Connection,
+    from: &str,
+    to: &str,
+    interval: &str,
+) -> BackendResult<Vec<String>> {
+    for (field, date) in [("from", from), ("to", to)] {
+        if !is_iso_date(date) {
+            return Err(BackendError::validation(
+                field,
+                format!("'{}' is not a YYYY-MM-DD date", date),
+            ));
+        }
+    }
+    if from > to {
+        return Err(BackendError::validation(
+            "to",
+            "End date is before start date",
+        ));
+    }
+
+    let (first, next) = match interval {
+        "daily" => ("date(?1)", "date(d, '+1 day')"),
+        "weekly" => ("date(?1, '+6 days')", "date(d, '+7 days')"),
+        "monthly" => (
+            "date(?1, 'start of month', '+1 month', '-1 day')",
+            "date(d, '+1 day', '+1 month', '-1 day')",
+        ),
+        _ => {
+            return Err(BackendError::validation(
+                "interval",
+                format!("Unsupported interval '{}'", interval),
+            ))
+        }
+    };
+
+    let mut stmt = conn
+        .prepare(&format!(
+            "WITH RECURSIVE points(d, n) AS (
+                 SELECT {first}, 1
+                 UNION ALL
+                 SELECT {next}, n + 1 FROM points WHERE {next} <= ?2 AND n <= ?3
+             )
+             SELECT d FROM points WHERE d <= ?2"
+        ))
+        .context("Failed to prepare statement")?;
+    let mut dates = stmt
+        .query_map(params![from, to, MAX_POINTS as i64], |row| row.get(0))
+        .context("Failed to list dates")?
+        .collect::<Result<Vec<String>>>()
+        .context("Failed to parse date")?;
+
+    if dates.last().map(String::as_str) != Some(to) {
+        dates.push(to.to_string());
+    }
+    if dates.len() > MAX_POINTS {
+        return Err(BackendError::validation(
+            "interval",
+            format!(
+                "Too many points; use a longer interval or a range under {} points",
+                MAX_POINTS
+            ),
+        ));
+    }
+
+    Ok(dates)
+}
+
+// A counted account's value at the end of each date
+struct AccountValues {
+    currency: String,
+    scale: u32,
+    values: Vec<i64>,
+}
+
+// Every counted account's value at the end of each date: the captured snapshot when there is
+// one, otherwise its balance from replaying transactions in date order plus, for an invest
+// account, its holdings at that day's prices
+fn account_values(
+    conn: &Connection,
+    dates: &[String],
+    pivot: &str,
+) -> BackendResult<Vec<AccountValues>> {
+    let first = dates.first().map(String::as_str).unwrap_or_default();
+    let last = dates.last().map(String::as_str).unwrap_or_default();
+
+    let mut captured: HashMap<(i64, String), i64> = HashMap::new();
+    {
+        let mut stmt = conn
+            .prepare(
+                "SELECT account_id, date, value FROM balance_snapshots
+                 WHERE date BETWEEN ?1 AND ?2",
+            )
+            .context("Failed to prepare statement")?;
+        let rows = stmt
+            .query_map(params![first, last], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get(1)?, row.get(2)?))
+            })
+            .context("Failed to query balance snapshots")?;
+        for row in rows {
+            let (account_id, date, value) = row.context("Failed to parse balance snapshot row")?;
+            captured.insert((account_id, date), value);
+        }
+    }
+
+    let mut effects: HashMap<i64, Vec<(String, i64)>> = HashMap::new();
+    {
+        let mut stmt = conn
+            .prepare(
+                "SELECT account_id, day, delta FROM balance_effects
+                 WHERE day <= ?1
+                 ORDER BY account_id, day",
+            )
+            .context("Failed to prepare statement")?;
+        let rows = stmt
+            .query_map(params![last], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get(1)?, row.get(2)?))
+            })
+            .context("Failed to query transactions")?;
+        for row in rows {
+            let (account_id, day, delta) = row.context("Failed to parse transaction row")?;
+            effects.entry(account_id).or_default().push((day, delta));
+        }
+    }
+
+    let accounts = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT a.id, a.type = 'invest', a.currency, cur.minor_unit, a.opening_balance
+                 FROM accounts a
+                 JOIN currencies cur ON cur.code = a.currency
+                 WHERE a.count_in_asset",
+            )
+            .context("Failed to prepare statement")?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, bool>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, u32>(3)?,
+                    row.get::<_, i64>(4)?,
+                ))
+            })
+            .context("Failed to query accounts")?
+            .collect::<Result<Vec<_>>>()
+            .context("Failed to parse account row")?;
+        rows
+    };
+
+    let mut values = Vec::with_capacity(accounts.len());
+    for (account_id, invest, currency, scale, opening_balance) in accounts {
+        let history = effects.remove(&account_id).unwrap_or_default();
+        let mut history = history.iter().peekable();
+        let mut balance = Money::from_minor(opening_balance, scale);
+        let mut account = AccountValues {
+            currency,
+            scale,
+            values: Vec::with_capacity(dates.len()),
+        };
+        for date in dates {
+            while let Some((_, delta)) = history.next_if(|(day, _)| day <= date) {
+                balance = add(balance, Money::from_minor(*delta, scale))?;
+            }
+            let value = match captured.get(&(account_id, date.clone())) {
+                Some(value) => *value,
+                None if invest => {
+                    let holdings =
+                        holdings_value(conn, account_id, &account.currency, date, pivot)?;
+                    add(balance, holdings)?.minor()
+                }
+                None => balance.minor(),
+            };
+            account.values.push(value);
+        }
+        values.push(account);
+    }
+
+    Ok(values)
+}
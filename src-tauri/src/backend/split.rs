@@ -0,0 +1,473 @@
+use crate::backend::db::Database;
+use crate::backend::error::{BackendError, BackendResult, Context};
+use crate::backend::invest_event::ensure_not_invest_event;
+use crate::backend::money::Money;
+use crate::backend::transaction::{insert_or_get_tag, to_currency_scale, Transaction};
+use crate::backend::{category, currency};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Result};
+use std::collections::HashMap;
+use tauri::State;
+
+// Split lines per query when loading them for a list of transactions
+const SPLIT_BATCH_SIZE: usize = 500;
+
+// An income or expense may be split into lines, each with its own amount, category, tags and
+// note. The lines add up to the transaction's amount, which alone moves the account balance.
+// Reports read `transaction_lines`, where a split transaction appears once per line.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct TransactionSplit {
+    pub id: i64,
+    pub transaction_id: i64,
+    // In the transaction's currency
+    pub amount: Money,
+    pub category_id: Option<i64>,
+    pub category_name: Option<String>,
+    pub tags: Vec<String>,
+    pub note: Option<String>,
+}
+
+// A line as sent to `set_transaction_splits`
+#[derive(serde::Deserialize)]
+pub struct SplitLine {
+    pub amount: Money,
+    pub category_id: Option<i64>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub note: Option<String>,
+}
+
+pub fn create_transaction_splits_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS transaction_splits (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            transaction_id INTEGER NOT NULL,
+            amount INTEGER NOT NULL CHECK( amount >= 0 ),
+            category_id INTEGER,
+            note TEXT,
+            sort_order INTEGER NOT NULL DEFAULT 0,
+            FOREIGN KEY (transaction_id) REFERENCES transactions(id) ON DELETE CASCADE,
+            FOREIGN KEY (category_id) REFERENCES categories(id) ON DELETE SET NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_transaction_splits_transaction
+            ON transaction_splits (transaction_id, sort_order);
+        CREATE INDEX IF NOT EXISTS idx_transaction_splits_category
+            ON transaction_splits (category_id);
+
+        CREATE TABLE IF NOT EXISTS split_tags (
+            split_id INTEGER NOT NULL,
+            tag_id INTEGER NOT NULL,
+            PRIMARY KEY (split_id, tag_id),
+            FOREIGN KEY (split_id) REFERENCES transaction_splits(id) ON DELETE CASCADE,
+            FOREIGN KEY (tag_id) REFERENCES tags(id) ON DELETE CASCADE
+        );
+        ",
+    )
+}
+
+// Every transaction as the lines reports count: one row per split line, or the transaction
+// itself when it has none. `split_id` is NULL on unsplit rows. Like `balance_effects`, it is
+// recreated after every migration run.
+pub fn create_transaction_lines_view(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        DROP VIEW IF EXISTS transaction_lines;
+        CREATE VIEW transaction_lines AS
+            SELECT t.id AS transaction_id, NULL AS split_id, t.category_id, t.amount, t.note
+            FROM transactions t
+            WHERE NOT EXISTS (SELECT 1 FROM transaction_splits s WHERE s.transaction_id = t.id)
+            UNION ALL
+            SELECT transaction_id, id, category_id, amount, note
+            FROM transaction_splits;
+        ",
+    )
+}
+
+// Replace the transaction's split lines. The lines must add up to its amount; an empty list
+// removes the split.
+#[tauri::command(rename_all = "snake_case")]
+pub fn set_transaction_splits(
+    db: State<'_, Database>,
+    transaction_id: i64,
+    splits: Vec<SplitLine>,
+) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    ensure_not_invest_event(&tx, transaction_id)?;
+    let (ledger_id, transaction_type, amount, currency): (i64, String, i64, String) = tx
+        .query_row(
+            "SELECT ledger_id, type, amount, currency FROM transactions WHERE id = ?1",
+            params![transaction_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .optional()
+        .context("Failed to look up transaction")?
+        .ok_or_else(|| {
+            BackendError::not_found(format!("Transaction {} not found", transaction_id))
+                .with_field("transaction_id")
+        })?;
+
+    tx.execute(
+        "DELETE FROM transaction_splits WHERE transaction_id = ?1",
+        params![transaction_id],
+    )
+    .context("Failed to clear split lines")?;
+    if splits.is_empty() {
+        tx.commit().context("Failed to commit split lines")?;
+        return Ok(());
+    }
+
+    if transaction_type == "transfer" {
+        return Err(BackendError::validation(
+            "splits",
+            "Only income and expenses can be split",
+        ));
+    }
+
+    let mut total = 0;
+    for (index, line) in splits.iter().enumerate() {
+        let line_amount = to_currency_scale(&tx, line.amount, &currency, "splits")?;
+        if line_amount.is_negative() {
+            return Err(BackendError::validation(
+                "splits",
+                format!("Line {} has a negative amount", index + 1),
+            ));
+        }
+        category::validate_transaction_category(
+            &tx,
+            ledger_id,
+            &transaction_type,
+            line.category_id,
+        )?;
+        total += line_amount.minor();
+
+        tx.execute(
+            "INSERT INTO transaction_splits
+                 (transaction_id, amount, category_id, note, sort_order)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                transaction_id,
+                line_amount.minor(),
+                line.category_id,
+                line.note,
+                index as i64
+            ],
+        )
+        .context("Failed to insert split line")?;
+        let split_id = tx.last_insert_rowid();
+
+        for tag in &line.tags {
+            let tag_id = insert_or_get_tag(&tx, tag)?;
+            tx.execute(
+                "INSERT OR IGNORE INTO split_tags (split_id, tag_id) VALUES (?1, ?2)",
+                params![split_id, tag_id],
+            )
+            .context("Failed to link tag to split line")?;
+        }
+    }
+
+    if total != amount {
+        let scale = currency::minor_unit(&tx, &currency)
+            .context(&format!("Failed to look up currency {}", currency))?;
+        return Err(BackendError::validation(
+            "splits",
+            format!(
+                "The lines add up to {} but the transaction is {}",
+                Money::from_minor(total, scale),
+                Money::from_minor(amount, scale)
+            ),
+        ));
+    }
+
+    tx.commit().context("Failed to commit split lines")?;
+    Ok(())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+pub fn get_transaction_splits(
+    db: State<'_, Database>,
+    transaction_id: i64,
+) -> BackendResult<Vec<TransactionSplit>> {
+    let conn = db.reader().context("Failed to open database connection")?;
+    let mut splits = split_lines(&conn, &[transaction_id])?;
+    Ok(splits.remove(&transaction_id).unwrap_or_default())
+}
+
+// Fill in the split lines of every transaction, a batch of transactions per query
+pub(crate) fn load_splits(
+    conn: &Connection,
+    transactions: &mut [Transaction],
+) -> BackendResult<()> {
+    let mut splits = HashMap::new();
+    for batch in transactions.chunks(SPLIT_BATCH_SIZE) {
+        let ids: Vec<i64> = batch.iter().map(|t| t.id).collect();
+        splits.extend(split_lines(conn, &ids)?);
+    }
+
+    for transaction in transactions {
+        transaction.splits = splits.remove(&transaction.id).unwrap_or_default();
+    }
+    Ok(())
+}
+
+// A transaction being changed must still match its split lines: same total, and categories
+// that fit its ledger and type. Transfers cannot keep lines at all.
+pub(crate) fn validate_existing_splits(
+    conn: &Connection,
+    transaction_id: i64,
+    ledger_id: i64,
+    transaction_type: &str,
+    amount: Money,
+) -> BackendResult<()> {
+    let lines = {
+        let mut stmt = conn
+            .prepare("SELECT amount, category_id FROM transaction_splits WHERE transaction_id = ?1")
+            .context("Failed to prepare statement")?;
+        let rows = stmt
+            .query_map(params![transaction_id], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, Option<i64>>(1)?))
+            })
+            .context("Failed to query split lines")?
+            .collect::<Result<Vec<_>>>()
+            .context("Failed to parse split line row")?;
+        rows
+    };
+    if lines.is_empty() {
+        return Ok(());
+    }
+
+    if transaction_type == "transfer" {
+        return Err(BackendError::validation(
+            "transaction_type",
+            "Remove the split lines before turning this into a transfer",
+        ));
+    }
+    let total: i64 = lines.iter().map(|(line_amount, _)| line_amount).sum();
+    if total != amount.minor() {
+        return Err(BackendError::validation(
+            "amount",
+            format!(
+                "The split lines add up to {}; change them along with the amount",
+                Money::from_minor(total, amount.scale())
+            ),
+        ));
+    }
+    for (_, category_id) in lines {
+        category::validate_transaction_category(conn, ledger_id, transaction_type, category_id)
+            .map_err(|err| {
+                err.context("A split line no longer fits")
+                    .with_field("splits")
+            })?;
+    }
+
+    Ok(())
+}
+
+// Split lines of the given transactions, keyed by transaction, in line order
+fn split_lines(
+    conn: &Connection,
+    transaction_ids: &[i64],
+) -> BackendResult<HashMap<i64, Vec<TransactionSplit>>> {
+    let mut splits: HashMap<i64, Vec<TransactionSplit>> = HashMap::new();
+    if transaction_ids.is_empty() {
+        return Ok(splits);
+    }
+    let placeholders = vec!["?"; transaction_ids.len()].join(", ");
+
+    let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+    {
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT st.split_id, g.name
+                 FROM split_tags st
+                 JOIN tags g ON g.id = st.tag_id
+                 JOIN transaction_splits s ON s.id = st.split_id
+                 WHERE s.transaction_id IN ({})",
+                placeholders
+            ))
+            .context("Failed to prepare statement")?;
+        let rows = stmt
+            .query_map(params_from_iter(transaction_ids), |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })
+            .context("Failed to query split tags")?;
+        for row in rows {
+            let (split_id, name) = row.context("Failed to parse split tag row")?;
+            tags.entry(split_id).or_default().push(name);
+        }
+    }
+
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT s.id, s.transaction_id, s.amount, cur.minor_unit, s.category_id, c.name,
+                 s.note
+             FROM transaction_splits s
+             JOIN transactions t ON t.id = s.transaction_id
+             JOIN currencies cur ON cur.code = t.currency
+             LEFT JOIN categories c ON c.id = s.category_id
+             WHERE s.transaction_id IN ({})
+             ORDER BY s.transaction_id, s.sort_order",
+            placeholders
+        ))
+        .context("Failed to prepare statement")?;
+    let rows = stmt
+        .query_map(params_from_iter(transaction_ids), |row| {
+            Ok(TransactionSplit {
+                id: row.get(0)?,
+                transaction_id: row.get(1)?,
+                amount: Money::from_minor(row.get(2)?, row.get(3)?),
+                category_id: row.get(4)?,
+                category_name: row.get(5)?,
+                tags: Vec::new(),
+                note: row.get(6)?,
+            })
+        })
+        .context("Failed to query split lines")?;
+    for row in rows {
+        let mut split = row.context("Failed to parse split line row")?;
+        split.tags = tags.remove(&split.id).unwrap_or_default();
+        splits.entry(split.transaction_id).or_default().push(split);
+    }
+
+    Ok(splits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::category::insert_category;
+    use crate::backend::testing::{money, Fixture};
+    use crate::backend::transaction::{create_transaction, update_transaction};
+    use crate::backend::transfer::update_transfer;
+
+    fn line(amount: &str, category_id: Option<i64>) -> SplitLine {
+        SplitLine {
+            amount: money(amount),
+            category_id,
+            tags: vec![],
+            note: None,
+        }
+    }
+
+    fn line_amounts(fixture: &Fixture, transaction_id: i64) -> Vec<Money> {
+        get_transaction_splits(fixture.db(), transaction_id)
+            .unwrap()
+            .iter()
+            .map(|split| split.amount)
+            .collect()
+    }
+
+    #[test]
+    fn lines_must_add_up_and_a_rejected_set_keeps_the_old_lines() {
+        let fixture = Fixture::new();
+        let ledger = fixture.ledger("USD");
+        let bank = fixture.account("bank", "debit", "USD", "100");
+        let food =
+            insert_category(fixture.db(), ledger, None, "Food", None, None, "expense").unwrap();
+        let salary =
+            insert_category(fixture.db(), ledger, None, "Salary", None, None, "income").unwrap();
+        let id = create_transaction(
+            fixture.db(),
+            ledger,
+            bank,
+            "expense",
+            None,
+            money("10"),
+            "USD",
+            "2024-01-01",
+            vec![],
+            None,
+        )
+        .unwrap();
+
+        set_transaction_splits(
+            fixture.db(),
+            id,
+            vec![line("6", Some(food)), line("4", None)],
+        )
+        .unwrap();
+        assert_eq!(
+            line_amounts(&fixture, id),
+            vec![money("6.00"), money("4.00")]
+        );
+
+        for lines in [
+            vec![line("6", None), line("3", None)],
+            vec![line("11", None), line("-1", None)],
+            vec![line("10", Some(salary))],
+            vec![line("9.999", None), line("0.001", None)],
+        ] {
+            let err = set_transaction_splits(fixture.db(), id, lines).unwrap_err();
+            assert!(err.field().is_some());
+            assert_eq!(
+                line_amounts(&fixture, id),
+                vec![money("6.00"), money("4.00")]
+            );
+        }
+
+        // The balance moves by the transaction's amount alone
+        let balance: i64 = fixture.query("SELECT balance FROM accounts WHERE id = ?1", [bank]);
+        assert_eq!(balance, 9000);
+    }
+
+    #[test]
+    fn a_split_transaction_keeps_its_amount_and_type() {
+        let fixture = Fixture::new();
+        let ledger = fixture.ledger("USD");
+        let bank = fixture.account("bank", "debit", "USD", "100");
+        let savings = fixture.account("savings", "debit", "USD", "0");
+        let id = create_transaction(
+            fixture.db(),
+            ledger,
+            bank,
+            "expense",
+            None,
+            money("10"),
+            "USD",
+            "2024-01-01",
+            vec![],
+            None,
+        )
+        .unwrap();
+        set_transaction_splits(fixture.db(), id, vec![line("6", None), line("4", None)]).unwrap();
+        let update = |amount| {
+            update_transaction(
+                fixture.db(),
+                id,
+                ledger,
+                bank,
+                "expense",
+                None,
+                money(amount),
+                "USD",
+                "2024-01-02",
+                vec![],
+                None,
+            )
+        };
+
+        assert_eq!(update("12").unwrap_err().field(), Some("amount"));
+        update("10").unwrap();
+        let err = update_transfer(
+            fixture.db(),
+            id,
+            ledger,
+            bank,
+            savings,
+            money("10"),
+            None,
+            None,
+            "2024-01-02",
+            vec![],
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(err.field(), Some("transaction_type"));
+
+        // Once the lines are gone it can become anything
+        set_transaction_splits(fixture.db(), id, vec![]).unwrap();
+        update("12").unwrap();
+        assert!(line_amounts(&fixture, id).is_empty());
+    }
+}
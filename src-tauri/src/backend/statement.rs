@@ -0,0 +1,479 @@
+use crate::backend::date::{
+    add_days, clamped_date, is_iso_date, next_month, previous_month, split_date, today, year_month,
+};
+use crate::backend::db::Database;
+use crate::backend::error::{BackendError, BackendResult, Context};
+use crate::backend::money::Money;
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use std::collections::HashMap;
+use tauri::State;
+
+// A credit card closes a statement on its billing day every month, with payment due on the next
+// due day after that. Both days come from `credit_accounts.billing_date` and `due_date`; days
+// past the end of a short month fall on its last day.
+
+// One billing cycle, covering `period_start` to `period_end` inclusive. Owed amounts are in the
+// card's currency; a negative owed amount is a credit on the card.
+#[derive(serde::Serialize)]
+pub struct CreditStatement {
+    pub account_id: i64,
+    pub period_start: String,
+    pub period_end: String,
+    pub due_date: String,
+    pub opening_owed: Money,
+    pub purchases: Money,
+    pub payments: Money,
+    pub closing_owed: Money,
+    // The transfer linked with `mark_statement_paid`
+    pub payment_transaction_id: Option<i64>,
+    // 'open' while the cycle runs, then 'paid' once a payment is linked, nothing is owed or
+    // payments received since it closed cover `closing_owed`, otherwise 'unpaid' until the due
+    // date passes and 'overdue' after it
+    pub status: String,
+}
+
+// Which transfer paid which statement, keyed by the statement's closing date
+pub fn create_statement_payments_table(conn: &Connection) -> Result<()> {
+    let create_table_sql = "
+    CREATE TABLE IF NOT EXISTS statement_payments (
+        account_id INTEGER NOT NULL,
+        period_end TEXT NOT NULL,
+        transaction_id INTEGER NOT NULL UNIQUE,
+        PRIMARY KEY (account_id, period_end),
+        FOREIGN KEY (account_id) REFERENCES accounts(id) ON DELETE CASCADE,
+        FOREIGN KEY (transaction_id) REFERENCES transactions(id) ON DELETE CASCADE
+    );";
+
+    conn.execute(create_table_sql, [])?;
+    Ok(())
+}
+
+// Every statement from the cycle holding the card's first transaction up to the open cycle,
+// newest first
+#[tauri::command(rename_all = "snake_case")]
+pub fn get_credit_statements(
+    db: State<'_, Database>,
+    account_id: i64,
+) -> BackendResult<Vec<CreditStatement>> {
+    let conn = db.reader().context("Failed to open database connection")?;
+    let today = today(&conn)?;
+    let mut statements = credit_statements(&conn, account_id, &today)?;
+    statements.reverse();
+    Ok(statements)
+}
+
+// Link a transfer from a debit account into the card as the payment of the statement closing on
+// `period_end`. The transfer must be dated after the statement closed.
+#[tauri::command(rename_all = "snake_case")]
+pub fn mark_statement_paid(
+    db: State<'_, Database>,
+    account_id: i64,
+    period_end: &str,
+    transaction_id: i64,
+) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    let today = today(&tx)?;
+    let statement = credit_statements(&tx, account_id, &today)?
+        .into_iter()
+        .find(|s| s.period_end == period_end)
+        .ok_or_else(|| {
+            BackendError::not_found(format!(
+                "Account {} has no statement closing on {}",
+                account_id, period_end
+            ))
+            .with_field("period_end")
+        })?;
+    if statement.status == "open" {
+        return Err(BackendError::validation(
+            "period_end",
+            "The statement has not closed yet",
+        ));
+    }
+
+    let payment = tx
+        .query_row(
+            "SELECT t.type, t.to_account_id, a.type, substr(t.date, 1, 10)
+             FROM transactions t
+             JOIN accounts a ON a.id = t.account_id
+             WHERE t.id = ?1",
+            params![transaction_id],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<i64>>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, String>(3)?,
+                ))
+            },
+        )
+        .optional()
+        .context("Failed to look up transaction")?
+        .ok_or_else(|| {
+            BackendError::not_found(format!("Transaction {} not found", transaction_id))
+                .with_field("transaction_id")
+        })?;
+
+    let (transaction_type, to_account_id, from_type, date) = payment;
+    if transaction_type != "transfer"
+        || to_account_id != Some(account_id)
+        || from_type.as_deref() != Some("debit")
+    {
+        return Err(BackendError::validation(
+            "transaction_id",
+            "A statement is paid by a transfer from a debit account into the card",
+        ));
+    }
+    if date.as_str() <= period_end {
+        return Err(BackendError::validation(
+            "transaction_id",
+            "The payment is dated before the statement closed",
+        ));
+    }
+
+    tx.execute(
+        "INSERT INTO statement_payments (account_id, period_end, transaction_id)
+         VALUES (?1, ?2, ?3)
+         ON CONFLICT (account_id, period_end)
+         DO UPDATE SET transaction_id = excluded.transaction_id",
+        params![account_id, period_end, transaction_id],
+    )
+    .context("Failed to link statement payment")?;
+
+    tx.commit().context("Failed to commit statement payment")?;
+    Ok(())
+}
+
+// Statements in date order, computed from the card's transactions as of `today`
+pub fn credit_statements(
+    conn: &Connection,
+    account_id: i64,
+    today: &str,
+) -> BackendResult<Vec<CreditStatement>> {
+    if !is_iso_date(today) {
+        return Err(BackendError::validation(
+            "today",
+            format!("'{}' is not a YYYY-MM-DD date", today),
+        ));
+    }
+
+    let card = conn
+        .query_row(
+            "SELECT a.opening_balance, cur.minor_unit, c.billing_date, c.due_date
+             FROM accounts a
+             JOIN currencies cur ON cur.code = a.currency
+             JOIN credit_accounts c ON c.account_id = a.id
+             WHERE a.id = ?1",
+            params![account_id],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, u32>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                ))
+            },
+        )
+        .optional()
+        .context("Failed to look up credit account")?
+        .ok_or_else(|| {
+            BackendError::not_found(format!("Credit account {} not found", account_id))
+                .with_field("account_id")
+        })?;
+    let (opening_balance, scale, billing_date, due_date) = card;
+    let billing_day = statement_day(&billing_date, "billing_date")?;
+    let due_day = statement_day(&due_date, "due_date")?;
+
+    // (day, delta) for every transaction on the card, oldest first
+    let effects = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT day, delta FROM balance_effects
+                 WHERE account_id = ?1
+                 ORDER BY day",
+            )
+            .context("Failed to prepare statement")?;
+        let rows = stmt
+            .query_map(params![account_id], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })
+            .context("Failed to query transactions")?
+            .collect::<Result<Vec<_>>>()
+            .context("Failed to parse transaction row")?;
+        rows
+    };
+
+    let payments: HashMap<String, i64> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT period_end, transaction_id FROM statement_payments WHERE account_id = ?1",
+            )
+            .context("Failed to prepare statement")?;
+        let rows = stmt
+            .query_map(params![account_id], |row| Ok((row.get(0)?, row.get(1)?)))
+            .context("Failed to query statement payments")?
+            .collect::<Result<HashMap<_, _>>>()
+            .context("Failed to parse statement payment row")?;
+        rows
+    };
+
+    // (day, running total) of money paid into the card up to today, to settle closed statements
+    let mut received = Vec::new();
+    let mut total_received = 0;
+    for (day, delta) in effects.iter().filter(|(day, _)| day.as_str() <= today) {
+        if *delta > 0 {
+            total_received += delta;
+            received.push((day.as_str(), total_received));
+        }
+    }
+    let received_after = |date: &str| {
+        let before = received.partition_point(|(day, _)| *day <= date);
+        let through = before.checked_sub(1).map_or(0, |i| received[i].1);
+        total_received - through
+    };
+
+    let first_day = effects
+        .iter()
+        .map(|(day, _)| day.as_str())
+        .find(|day| is_iso_date(day))
+        .filter(|day| *day < today)
+        .unwrap_or(today);
+    let (year, month, _) = split_date(first_day);
+
+    // The cycle ending on the first closing date on or after the first transaction
+    let mut period_end = clamped_date(year, month, billing_day);
+    if period_end.as_str() < first_day {
+        let (year, month) = next_month((year, month));
+        period_end = clamped_date(year, month, billing_day);
+    }
+    let (year, month) = previous_month(year_month(&period_end));
+    let mut period_start = add_days(&clamped_date(year, month, billing_day), 1);
+
+    // The open cycle closes this month or next, which bounds the loop whatever the dates hold
+    let last_month = next_month(year_month(today));
+
+    let mut owed = -opening_balance;
+    let mut effects = effects.iter().peekable();
+    let mut statements = Vec::new();
+    while year_month(&period_end) <= last_month {
+        // Anything dated before the first cycle counts towards its opening amount
+        while let Some((_, delta)) = effects.next_if(|(day, _)| *day < period_start) {
+            owed -= delta;
+        }
+
+        let opening_owed = owed;
+        let (mut purchases, mut payments_in) = (0, 0);
+        while let Some((_, delta)) = effects.next_if(|(day, _)| *day <= period_end) {
+            if *delta < 0 {
+                purchases -= delta;
+            } else {
+                payments_in += delta;
+            }
+        }
+        owed = opening_owed + purchases - payments_in;
+
+        let due = due_date_after(&period_end, due_day);
+        let payment_transaction_id = payments.get(&period_end).copied();
+        let status = if period_end.as_str() >= today {
+            "open"
+        } else if payment_transaction_id.is_some()
+            || owed <= 0
+            || received_after(&period_end) >= owed
+        {
+            "paid"
+        } else if due.as_str() < today {
+            "overdue"
+        } else {
+            "unpaid"
+        };
+
+        let open = status == "open";
+        statements.push(CreditStatement {
+            account_id,
+            period_start,
+            period_end: period_end.clone(),
+            due_date: due,
+            opening_owed: Money::from_minor(opening_owed, scale),
+            purchases: Money::from_minor(purchases, scale),
+            payments: Money::from_minor(payments_in, scale),
+            closing_owed: Money::from_minor(owed, scale),
+            payment_transaction_id,
+            status: status.to_string(),
+        });
+        if open {
+            break;
+        }
+
+        period_start = add_days(&period_end, 1);
+        let (year, month) = next_month(year_month(&period_end));
+        period_end = clamped_date(year, month, billing_day);
+    }
+
+    Ok(statements)
+}
+
+// The day of the month a billing or due date falls on, written either as the day number or as a
+// full YYYY-MM-DD date
+pub(crate) fn statement_day(text: &str, field: &str) -> BackendResult<u32> {
+    let text = text.trim();
+    let day = if is_iso_date(text) {
+        text[8..].parse().ok()
+    } else {
+        text.parse::<u32>().ok()
+    };
+    day.filter(|day| (1..=31).contains(day)).ok_or_else(|| {
+        BackendError::validation(
+            field,
+            format!("'{}' is not a day of the month from 1 to 31", text),
+        )
+    })
+}
+
+// The first `due_day` after the statement closes
+fn due_date_after(period_end: &str, due_day: u32) -> String {
+    let (year, month) = year_month(period_end);
+    let due = clamped_date(year, month, due_day);
+    if due.as_str() > period_end {
+        return due;
+    }
+    let (year, month) = next_month((year, month));
+    clamped_date(year, month, due_day)
+}
+
+// `day` of the month, or the month's last day when it is shorter
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::testing::{money, Fixture};
+    use crate::backend::transaction::create_transaction;
+
+    // A fixture holding one USD card, returned with the card's id and a ledger for its spending
+    fn card(billing_date: &str, due_date: &str) -> (Fixture, i64, i64) {
+        let fixture = Fixture::new();
+        let ledger = fixture.ledger("USD");
+        let card = fixture.credit_card("card", "USD", billing_date, due_date);
+        (fixture, ledger, card)
+    }
+
+    fn transaction(
+        fixture: &Fixture,
+        ledger: i64,
+        card: i64,
+        kind: &str,
+        amount: &str,
+        date: &str,
+    ) {
+        create_transaction(
+            fixture.db(),
+            ledger,
+            card,
+            kind,
+            None,
+            money(amount),
+            "USD",
+            date,
+            vec![],
+            None,
+        )
+        .unwrap();
+    }
+
+    // (period_start, period_end, due_date, closing_owed, status) of each statement
+    fn cycles(
+        fixture: &Fixture,
+        card: i64,
+        today: &str,
+    ) -> Vec<(String, String, String, String, String)> {
+        credit_statements(&fixture.reader(), card, today)
+            .unwrap()
+            .into_iter()
+            .map(|s| {
+                (
+                    s.period_start,
+                    s.period_end,
+                    s.due_date,
+                    s.closing_owed.to_string(),
+                    s.status,
+                )
+            })
+            .collect()
+    }
+
+    fn cycle(
+        start: &str,
+        end: &str,
+        due: &str,
+        owed: &str,
+        status: &str,
+    ) -> (String, String, String, String, String) {
+        (
+            start.to_string(),
+            end.to_string(),
+            due.to_string(),
+            owed.to_string(),
+            status.to_string(),
+        )
+    }
+
+    #[test]
+    fn month_end_billing_closes_on_the_last_day_of_each_month() {
+        let (fixture, ledger, card) = card("31", "10");
+        transaction(&fixture, ledger, card, "expense", "40", "2024-01-20");
+        transaction(&fixture, ledger, card, "expense", "100", "2024-02-20");
+
+        assert_eq!(
+            cycles(&fixture, card, "2024-03-05"),
+            [
+                cycle("2024-01-01", "2024-01-31", "2024-02-10", "40.00", "overdue"),
+                cycle("2024-02-01", "2024-02-29", "2024-03-10", "140.00", "unpaid"),
+                cycle("2024-03-01", "2024-03-31", "2024-04-10", "140.00", "open"),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_closing_day_belongs_to_the_cycle_it_ends() {
+        let (fixture, ledger, card) = card("15", "5");
+        transaction(&fixture, ledger, card, "expense", "10", "2024-01-15");
+        transaction(&fixture, ledger, card, "expense", "20", "2024-01-16");
+
+        assert_eq!(
+            cycles(&fixture, card, "2024-02-20"),
+            [
+                cycle("2023-12-16", "2024-01-15", "2024-02-05", "10.00", "overdue"),
+                cycle("2024-01-16", "2024-02-15", "2024-03-05", "30.00", "unpaid"),
+                cycle("2024-02-16", "2024-03-15", "2024-04-05", "30.00", "open"),
+            ]
+        );
+    }
+
+    #[test]
+    fn payments_after_closing_settle_the_statement() {
+        let (fixture, ledger, card) = card("15", "5");
+        transaction(&fixture, ledger, card, "expense", "10", "2024-01-10");
+        transaction(&fixture, ledger, card, "income", "10", "2024-01-20");
+
+        let statements = cycles(&fixture, card, "2024-02-20");
+        assert_eq!(statements[0].4, "paid");
+        assert_eq!(statements[1].3, "0.00");
+        assert_eq!(statements[1].4, "paid");
+    }
+
+    #[test]
+    fn a_card_without_transactions_has_only_the_open_cycle() {
+        let (fixture, _, card) = card("31", "10");
+        assert_eq!(
+            cycles(&fixture, card, "2024-02-10"),
+            [cycle(
+                "2024-02-01",
+                "2024-02-29",
+                "2024-03-10",
+                "0.00",
+                "open"
+            )]
+        );
+        assert!(credit_statements(&fixture.reader(), card, "2024-13-05").is_err());
+        assert!(credit_statements(&fixture.reader(), card + 1, "2024-02-10").is_err());
+    }
+}
@@ -1,5 +1,7 @@
-use crate::backend::db::get_connection;
-use rusqlite::{Connection, Result, params};
+use crate::backend::db::Database;
+use crate::backend::error::{BackendError, BackendResult, Context};
+use rusqlite::{params, Connection, Result};
+use tauri::State;
 
 #[derive(serde::Serialize)]
 pub struct Tag {
@@ -9,9 +11,7 @@ pub struct Tag {
 }
 
 // Function to create the rag table
-pub fn create_tags_table() -> Result<()> {
-    let conn = get_connection();
-
+pub fn create_tags_table(conn: &Connection) -> Result<()> {
     let create_table_sql = "
     CREATE TABLE IF NOT EXISTS tags (
         id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -36,42 +36,48 @@ pub fn create_tags_table() -> Result<()> {
 
 // Add more ledger-related functions here as needed
 #[tauri::command]
-pub fn create_tag(name: &str, color: Option<&str>) -> Result<i64, String> {
-    let conn = get_connection();
+pub fn create_tag(db: State<'_, Database>, name: &str, color: Option<&str>) -> BackendResult<i64> {
+    let conn = db.writer();
     insert_tag(&conn, name, color)
 }
 
 #[tauri::command]
-pub fn get_tags() -> Result<Vec<Tag>, String> {
-    let conn = get_connection();
+pub fn get_tags(db: State<'_, Database>) -> BackendResult<Vec<Tag>> {
+    let conn = db.reader().context("Failed to open database connection")?;
     get_all_tags(&conn)
 }
 
 #[tauri::command]
-pub fn update_tag(tag_id: i64, name: &str, color: Option<&str>) -> Result<(), String> {
-    let conn = get_connection();
+pub fn update_tag(
+    db: State<'_, Database>,
+    tag_id: i64,
+    name: &str,
+    color: Option<&str>,
+) -> BackendResult<()> {
+    let conn = db.writer();
     update_tag_row(&conn, tag_id, name, color)
 }
 
 #[tauri::command]
-pub fn delete_tag(tag_id: i64) -> Result<(), String> {
-    let conn = get_connection();
+pub fn delete_tag(db: State<'_, Database>, tag_id: i64) -> BackendResult<()> {
+    let conn = db.writer();
     delete_tag_row(&conn, tag_id)
 }
 
-fn insert_tag(conn: &Connection, name: &str, color: Option<&str>) -> Result<i64, String> {
+fn insert_tag(conn: &Connection, name: &str, color: Option<&str>) -> BackendResult<i64> {
     conn.execute(
         "INSERT INTO tags (name, color) VALUES (?1, ?2)",
         params![name, color],
     )
-    .map_err(|err| format!("Failed to insert tag: {}", err))?;
+    .context("Failed to insert tag")?;
 
     Ok(conn.last_insert_rowid())
 }
 
-fn get_all_tags(conn: &Connection) -> Result<Vec<Tag>, String> {
-    let mut stmt = conn.prepare("SELECT id, name, color FROM tags")
-        .map_err(|e| format!("Failed to prepare statement: {}", e))?;
+fn get_all_tags(conn: &Connection) -> BackendResult<Vec<Tag>> {
+    let mut stmt = conn
+        .prepare("SELECT id, name, color FROM tags")
+        .context("Failed to prepare statement")?;
 
     let tag_iter = stmt
         .query_map([], |row| {
@@ -81,31 +87,48 @@ fn get_all_tags(conn: &Connection) -> Result<Vec<Tag>, String> {
                 color: row.get(2)?,
             })
         })
-        .map_err(|err| format!("Failed to get tags: {}", err))?;
+        .context("Failed to get tags")?;
 
     let mut tags = Vec::new();
     for tag in tag_iter {
-        tags.push(tag.map_err(|e| format!("Failed to parse tag row: {}", e))?);
+        tags.push(tag.context("Failed to parse tag row")?);
     }
 
     Ok(tags)
 }
 
 // Update tag
-fn update_tag_row(conn: &Connection, tag_id: i64, name: &str, color: Option<&str>) -> Result<(), String> {
-    conn.execute(
-        "UPDATE tags SET name = ?1, color = ?2 WHERE id = ?3",
-        params![name, color, tag_id],
-    )
-    .map_err(|err| format!("Failed to update tag: {}", err))?;
+fn update_tag_row(
+    conn: &Connection,
+    tag_id: i64,
+    name: &str,
+    color: Option<&str>,
+) -> BackendResult<()> {
+    let updated = conn
+        .execute(
+            "UPDATE tags SET name = ?1, color = ?2 WHERE id = ?3",
+            params![name, color, tag_id],
+        )
+        .context("Failed to update tag")?;
+    if updated == 0 {
+        return Err(tag_not_found(tag_id));
+    }
 
     Ok(())
 }
 
 // Delete tag
-fn delete_tag_row(conn: &Connection, tag_id: i64) -> Result<(), String> {
-    conn.execute("DELETE FROM tags WHERE id = ?1", params![tag_id])
-        .map_err(|err| format!("Failed to delete tag: {}", err))?;
+fn delete_tag_row(conn: &Connection, tag_id: i64) -> BackendResult<()> {
+    let deleted = conn
+        .execute("DELETE FROM tags WHERE id = ?1", params![tag_id])
+        .context("Failed to delete tag")?;
+    if deleted == 0 {
+        return Err(tag_not_found(tag_id));
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+fn tag_not_found(tag_id: i64) -> BackendError {
+    BackendError::not_found(format!("Tag {} not found", tag_id))
+}
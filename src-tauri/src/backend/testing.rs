@@ -0,0 +1,104 @@
+use crate::backend::db::{Database, ReadConnection};
+use crate::backend::money::Money;
+use crate::backend::{account, ledger};
+use rusqlite::types::FromSql;
+use rusqlite::Params;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tauri::test::{mock_app, MockRuntime};
+use tauri::{App, Manager, State};
+
+// A migrated database in its own temporary directory, managed by a mock app so tests can call
+// commands with the same `State` the frontend goes through. The directory goes on drop.
+pub(crate) struct Fixture {
+    app: App<MockRuntime>,
+    dir: PathBuf,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "finance-wifyou-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let app = mock_app();
+        app.manage(Database::open(dir.join("finance.db")).unwrap());
+        Fixture { app, dir }
+    }
+
+    pub fn db(&self) -> State<'_, Database> {
+        self.app.state::<Database>()
+    }
+
+    // Where the database lives, with attachments next to it
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn reader(&self) -> ReadConnection<'_> {
+        self.db().inner().reader().unwrap()
+    }
+
+    // A single value read with `sql`
+    pub fn query<T: FromSql>(&self, sql: &str, params: impl Params) -> T {
+        self.reader()
+            .query_row(sql, params, |row| row.get(0))
+            .unwrap()
+    }
+
+    pub fn ledger(&self, base_currency: &str) -> i64 {
+        ledger::create_ledger(self.db(), "Household", base_currency, None, false).unwrap()
+    }
+
+    // A debit, invest or member account
+    pub fn account(&self, name: &str, account_type: &str, currency: &str, opening: &str) -> i64 {
+        account::create_account(
+            self.db(),
+            name,
+            account_type,
+            money(opening),
+            currency,
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        self.last_account()
+    }
+
+    pub fn credit_card(&self, name: &str, currency: &str, billing_day: &str, due_day: &str) -> i64 {
+        account::create_account(
+            self.db(),
+            name,
+            "credit",
+            money("0"),
+            currency,
+            None,
+            Some(money("1000")),
+            Some(billing_day),
+            Some(due_day),
+        )
+        .unwrap();
+        self.last_account()
+    }
+
+    fn last_account(&self) -> i64 {
+        self.query("SELECT MAX(id) FROM accounts", [])
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub(crate) fn money(text: &str) -> Money {
+    Money::parse(text).unwrap()
+}
@@ -1,22 +1,45 @@
-use crate::backend::db::get_connection;
-use rusqlite::{params, Connection, OptionalExtension, Result};
+use crate::backend::date::{is_iso_date, validate_date};
+use crate::backend::db::Database;
+use crate::backend::error::{BackendError, BackendResult, Context};
+use crate::backend::invest_event::ensure_not_invest_event;
+use crate::backend::money::Money;
+use crate::backend::split::{load_splits, validate_existing_splits, TransactionSplit};
+use crate::backend::{attachment, balance, category, currency};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Result, Row};
+use std::collections::HashMap;
+use tauri::State;
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct Transaction {
     pub id: i64,
     pub ledger_id: i64,
     pub account_id: i64,
-    pub amount: f64,
+    pub transaction_type: String,
+    pub amount: Money,
     pub currency: String,
     pub date: String,
     pub note: Option<String>,
     pub tags: Vec<String>,
+    pub category_id: Option<i64>,
+    pub category_name: Option<String>,
+    // Set on transfers only; `amount` and `currency` are then what left the source account
+    pub to_account_id: Option<i64>,
+    pub to_amount: Option<Money>,
+    pub to_currency: Option<String>,
+    pub fee: Money,
+    // Units of `to_currency` received per unit of `currency` sent
+    pub exchange_rate: Option<f64>,
+    // Empty unless the transaction is split; see `split`
+    #[serde(default)]
+    pub splits: Vec<TransactionSplit>,
+    // The recurring rule that recorded this transaction, if any
+    #[serde(default)]
+    pub recurring_rule_id: Option<i64>,
 }
 
 // Function to create the transactions table
-pub fn create_transactions_table() -> Result<()> {
-    let conn = get_connection();
-
+pub fn create_transactions_table(conn: &Connection) -> Result<()> {
     let create_table_sql = "
     CREATE TABLE IF NOT EXISTS transactions (
         id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -36,174 +59,781 @@ pub fn create_transactions_table() -> Result<()> {
 
 // Add more transaction-related functions here as needed
 #[tauri::command(rename_all = "snake_case")]
+#[allow(clippy::too_many_arguments)]
 pub fn create_transaction(
+    db: State<'_, Database>,
     ledger_id: i64,
     account_id: i64,
-    amount: f64,
+    transaction_type: &str,   // 'expense', 'income'
+    category_id: Option<i64>, // any node of the ledger's category tree
+    amount: Money,
     currency: &str,
     date: &str,
     tags: Vec<String>,
     note: Option<&str>,
-) -> Result<(), String> {
-    let conn = get_connection();
+) -> BackendResult<i64> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
 
-    conn.execute(
-        "INSERT INTO transactions (ledger_id, account_id, amount, currency, date, note) 
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-        params![ledger_id, account_id, amount, currency, date, note],
+    validate_date("date", date)?;
+    let amount = validate_transaction(&tx, account_id, transaction_type, amount, currency)?;
+    category::validate_transaction_category(&tx, ledger_id, transaction_type, category_id)?;
+
+    tx.execute(
+        "INSERT INTO transactions (ledger_id, account_id, type, amount, currency, date, note,
+             category_id)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            ledger_id,
+            account_id,
+            transaction_type,
+            amount.minor(),
+            currency,
+            date,
+            note,
+            category_id
+        ],
     )
-    .map_err(|err| format!("Failed to insert transaction: {}", err))?;
+    .context("Failed to insert transaction")?;
 
-    let transaction_id = conn.last_insert_rowid();
+    let transaction_id = tx.last_insert_rowid();
 
-    // Insert tags and link them to the transaction
+    balance::apply_transaction_effects(&tx, transaction_id, 1)
+        .context("Failed to update account balance")?;
+    link_tags(&tx, transaction_id, &tags)?;
+
+    tx.commit().context("Failed to commit transaction")?;
+    Ok(transaction_id)
+}
+
+// Checks shared by create and update. Returns the amount in the currency's minor units.
+pub(crate) fn validate_transaction(
+    conn: &Connection,
+    account_id: i64,
+    transaction_type: &str,
+    amount: Money,
+    currency: &str,
+) -> BackendResult<Money> {
+    if transaction_type == "transfer" {
+        return Err(BackendError::validation(
+            "transaction_type",
+            "Transfers need a destination account; use create_transfer or update_transfer",
+        ));
+    }
+    if !matches!(transaction_type, "expense" | "income") {
+        return Err(BackendError::validation(
+            "transaction_type",
+            format!("Unsupported transaction type '{}'", transaction_type),
+        ));
+    }
+
+    let account_currency = account_currency(conn, account_id, "account_id")?;
+
+    // Balances are kept in the account's own currency
+    if account_currency != currency {
+        return Err(BackendError::validation(
+            "currency",
+            format!(
+                "Transaction currency {} does not match account currency {}",
+                currency, account_currency
+            ),
+        ));
+    }
+
+    let amount = to_currency_scale(conn, amount, currency, "amount")?;
+    // The type carries the direction, so amounts are never negative
+    if amount.is_negative() {
+        return Err(BackendError::validation(
+            "amount",
+            "Amount must not be negative",
+        ));
+    }
+
+    Ok(amount)
+}
+
+// Currency of an account, reporting a missing account against `field`
+pub(crate) fn account_currency(
+    conn: &Connection,
+    account_id: i64,
+    field: &str,
+) -> BackendResult<String> {
+    conn.query_row(
+        "SELECT currency FROM accounts WHERE id = ?1",
+        params![account_id],
+        |row| row.get(0),
+    )
+    .optional()
+    .context("Failed to look up account")?
+    .ok_or_else(|| {
+        BackendError::not_found(format!("Account {} not found", account_id)).with_field(field)
+    })
+}
+
+pub(crate) fn link_tags(
+    conn: &Connection,
+    transaction_id: i64,
+    tags: &[String],
+) -> BackendResult<()> {
     for tag in tags {
-        let tag_id = insert_or_get_tag(&conn, &tag)?;
+        let tag_id = insert_or_get_tag(conn, tag)?;
         conn.execute(
             "INSERT INTO transaction_tags (transaction_id, tag_id) VALUES (?1, ?2)",
             params![transaction_id, tag_id],
         )
-        .map_err(|err| format!("Failed to link tag to transaction: {}", err))?;
+        .context("Failed to link tag to transaction")?;
     }
-
     Ok(())
 }
 
-fn insert_or_get_tag(conn: &Connection, tag_name: &str) -> Result<i64, String> {
+pub(crate) fn replace_tags(
+    conn: &Connection,
+    transaction_id: i64,
+    tags: &[String],
+) -> BackendResult<()> {
+    conn.execute(
+        "DELETE FROM transaction_tags WHERE transaction_id = ?1",
+        params![transaction_id],
+    )
+    .context("Failed to clear existing tags")?;
+    link_tags(conn, transaction_id, tags)
+}
+
+// Amounts arrive with whatever precision the user typed; store them in the currency's minor units
+pub(crate) fn to_currency_scale(
+    conn: &Connection,
+    amount: Money,
+    currency: &str,
+    field: &str,
+) -> BackendResult<Money> {
+    let scale = currency::minor_unit(conn, currency)
+        .context(&format!("Failed to look up currency {}", currency))
+        .map_err(|err| err.with_field("currency"))?;
+    amount
+        .to_scale(scale)
+        .map_err(|err| BackendError::from(err).with_field(field))
+}
+
+pub(crate) fn insert_or_get_tag(conn: &Connection, tag_name: &str) -> BackendResult<i64> {
     // Check if tag exists
-    let mut stmt = match conn.prepare("SELECT id FROM tags WHERE name = ?1") {
-        Ok(statement) => statement,
-        Err(e) => return Err(format!("Failed to prepare statement: {}", e)),
-    };
+    let mut stmt = conn
+        .prepare("SELECT id FROM tags WHERE name = ?1")
+        .context("Failed to prepare statement")?;
 
     let tag_id: Option<i64> = stmt
         .query_row([tag_name], |row| row.get(0))
         .optional()
-        .map_err(|e| e.to_string())?;
+        .context("Failed to look up tag")?;
 
     match tag_id {
         Some(id) => Ok(id), // tag already exists, return id
         None => {
             // Insert new tag if it doesn't exist
             conn.execute("INSERT INTO tags (name) VALUES (?1)", params![tag_name])
-                .map_err(|err| format!("Failed to update transaction: {}", err))?;
+                .context("Failed to insert tag")?;
             Ok(conn.last_insert_rowid()) // return newly inserted tag id
         }
     }
 }
 
+// Columns and joins read by `transaction_from_row`; `a` is the source account
+pub(crate) const TRANSACTION_SELECT: &str = "
+    SELECT t.id, t.ledger_id, t.account_id, t.amount, t.currency, t.date, t.note,
+        cur.minor_unit, t.type, t.to_account_id, t.to_amount, t.to_currency,
+        to_cur.minor_unit, t.fee, t.exchange_rate, t.category_id, c.name, t.recurring_rule_id";
+pub(crate) const TRANSACTION_FROM: &str = "
+    FROM transactions t
+    JOIN accounts a ON a.id = t.account_id
+    JOIN currencies cur ON cur.code = t.currency
+    LEFT JOIN currencies to_cur ON to_cur.code = t.to_currency
+    LEFT JOIN categories c ON c.id = t.category_id";
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 500;
+// Transactions whose tags are fetched per query, well under SQLite's limit on bound values
+const TAG_BATCH_SIZE: usize = 500;
+
+// Every field is optional; an empty filter matches every transaction
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+pub struct TransactionFilter {
+    pub ledger_id: Option<i64>,
+    // Matches either side of a transfer
+    pub account_id: Option<i64>,
+    // Inclusive YYYY-MM-DD bounds
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub transaction_type: Option<String>,
+    // Inclusive bounds on `amount`, compared in each transaction's own currency
+    pub min_amount: Option<Money>,
+    pub max_amount: Option<Money>,
+    // Also matches the category's descendants, and split transactions with a line in them
+    pub category_id: Option<i64>,
+    // Transactions carrying every one of these tags, on the transaction or any of its lines
+    pub tags: Vec<String>,
+}
+
+// Sums over every transaction in one currency matching a filter
+#[derive(serde::Serialize)]
+pub struct TransactionTotals {
+    pub currency: String,
+    pub count: i64,
+    pub income: Money,
+    pub expense: Money,
+    // Amounts sent by transfers, fees included
+    pub transfers: Money,
+}
+
+#[derive(serde::Serialize)]
+pub struct TransactionPage {
+    pub transactions: Vec<Transaction>,
+    // Across all pages
+    pub total_count: i64,
+    pub totals: Vec<TransactionTotals>,
+    // Pass back as `cursor` for the following page; None on the last one
+    pub next_cursor: Option<String>,
+}
+
 #[tauri::command(rename_all = "snake_case")]
-pub fn read_transactions() -> Result<Vec<Transaction>, String> {
-    let conn = get_connection();
-
-    let mut stmt = match conn
-        .prepare("SELECT id, ledger_id, account_id, amount, currency, date, note FROM transactions")
-    {
-        Ok(statement) => statement,
-        Err(e) => return Err(format!("Failed to prepare statement: {}", e)),
-    };
+pub fn read_transactions(db: State<'_, Database>) -> BackendResult<Vec<Transaction>> {
+    let conn = db.reader().context("Failed to open database connection")?;
 
-    let transaction_iter = match stmt.query_map([], |row| {
-        Ok(Transaction {
-            id: row.get(0)?,
-            ledger_id: row.get(1)?,
-            account_id: row.get(2)?,
-            amount: row.get(3)?,
-            currency: row.get(4)?,
-            date: row.get(5)?,
-            note: row.get(6)?,
-            tags: get_tags_for_transaction(&conn, row.get(0)?).unwrap_or(vec![]),
-        })
-    }) {
-        Ok(iterator) => iterator,
-        Err(e) => return Err(format!("Failed to query accounts: {}", e)),
-    };
+    let mut stmt = conn
+        .prepare(&format!("{} {}", TRANSACTION_SELECT, TRANSACTION_FROM))
+        .context("Failed to prepare statement")?;
+
+    let transaction_iter = stmt
+        .query_map([], transaction_from_row)
+        .context("Failed to query transactions")?;
 
     let mut transactions = Vec::new();
     for transaction in transaction_iter {
-        match transaction {
-            Ok(transaction) => transactions.push(transaction),
-            Err(e) => return Err(format!("Failed to parse account row: {}", e)),
-        }
+        transactions.push(transaction.context("Failed to parse transaction row")?);
     }
+    load_tags(&conn, &mut transactions)?;
+    load_splits(&conn, &mut transactions)?;
 
     Ok(transactions)
 }
 
-fn get_tags_for_transaction(conn: &Connection, transaction_id: i64) -> Result<Vec<String>, String> {
-    let mut stmt = match conn.prepare(
-        "SELECT t.name 
-        FROM tags t
-        JOIN transaction_tags tt ON t.id = tt.tag_id
-        WHERE tt.transaction_id = ?1",
-    ) {
-        Ok(statement) => statement,
-        Err(e) => return Err(format!("Failed to prepare statement: {}", e)),
+// One page of the transactions matching `filter`, ordered by `sort_by` ('date', 'amount', 'type',
+// 'account', 'category', 'currency', 'note' or 'id'; newest date first by default) with the id
+// breaking ties. `cursor` is the `next_cursor` of the previous page, under the same filter and sort.
+#[tauri::command(rename_all = "snake_case")]
+pub fn query_transactions(
+    db: State<'_, Database>,
+    filter: TransactionFilter,
+    sort_by: Option<&str>,
+    descending: Option<bool>,
+    cursor: Option<&str>,
+    limit: Option<u32>,
+) -> BackendResult<TransactionPage> {
+    let sort_by = sort_by.unwrap_or("date");
+    let sort_key = sort_expression(sort_by)?;
+    let descending = descending.unwrap_or(sort_by == "date");
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    let conn = db.reader().context("Failed to open database connection")?;
+    let (conditions, values) = filter_conditions(&filter)?;
+    let totals = transaction_totals(&conn, &conditions, &values)?;
+
+    let mut page_conditions = conditions;
+    let mut page_values = values;
+    let (direction, comparison) = if descending {
+        ("DESC", "<")
+    } else {
+        ("ASC", ">")
+    };
+    if let Some(cursor) = cursor {
+        let (value, id) = decode_cursor(cursor, sort_by)?;
+        page_conditions.push(format!(
+            "({key} {cmp} ? OR ({key} = ? AND t.id {cmp} ?))",
+            key = sort_key,
+            cmp = comparison
+        ));
+        page_values.extend([value.clone(), value, Value::Integer(id)]);
+    }
+    // One extra row tells whether another page follows
+    page_values.push(Value::Integer(limit as i64 + 1));
+
+    let mut stmt = conn
+        .prepare(&format!(
+            "{select}, {key} {from}
+             WHERE {conditions}
+             ORDER BY {key} {direction}, t.id {direction}
+             LIMIT ?",
+            select = TRANSACTION_SELECT,
+            from = TRANSACTION_FROM,
+            key = sort_key,
+            conditions = where_clause(&page_conditions),
+            direction = direction,
+        ))
+        .context("Failed to prepare statement")?;
+
+    let row_iter = stmt
+        .query_map(params_from_iter(page_values), |row| {
+            Ok((transaction_from_row(row)?, row.get::<_, Value>(18)?))
+        })
+        .context("Failed to query transactions")?;
+
+    let mut rows = Vec::new();
+    for row in row_iter {
+        rows.push(row.context("Failed to parse transaction row")?);
+    }
+
+    let next_cursor = if rows.len() > limit as usize {
+        rows.truncate(limit as usize);
+        rows.last()
+            .map(|(transaction, value)| encode_cursor(sort_by, value, transaction.id))
+            .transpose()?
+    } else {
+        None
     };
 
-    let tag_iter = match stmt.query_map([transaction_id], |row| row.get(0)) {
-        Ok(iterator) => iterator,
-        Err(e) => return Err(format!("Failed to query accounts: {}", e)),
+    let mut transactions: Vec<Transaction> = rows.into_iter().map(|(t, _)| t).collect();
+    load_tags(&conn, &mut transactions)?;
+    load_splits(&conn, &mut transactions)?;
+
+    Ok(TransactionPage {
+        transactions,
+        total_count: totals.iter().map(|t| t.count).sum(),
+        totals,
+        next_cursor,
+    })
+}
+
+// Map a row of `TRANSACTION_SELECT`; tags are filled in afterwards by `load_tags`
+pub(crate) fn transaction_from_row(row: &Row) -> Result<Transaction> {
+    let scale: u32 = row.get(7)?;
+    let to_amount = match (row.get::<_, Option<i64>>(10)?, row.get(12)?) {
+        (Some(minor), Some(to_scale)) => Some(Money::from_minor(minor, to_scale)),
+        _ => None,
     };
-    let mut tags = Vec::new();
-    for tag in tag_iter {
-        match tag {
-            Ok(tag) => tags.push(tag),
-            Err(e) => return Err(format!("Failed to parse account row: {}", e)),
+    Ok(Transaction {
+        id: row.get(0)?,
+        ledger_id: row.get(1)?,
+        account_id: row.get(2)?,
+        transaction_type: row.get(8)?,
+        amount: Money::from_minor(row.get(3)?, scale),
+        currency: row.get(4)?,
+        date: row.get(5)?,
+        note: row.get(6)?,
+        tags: Vec::new(),
+        category_id: row.get(15)?,
+        category_name: row.get(16)?,
+        to_account_id: row.get(9)?,
+        to_amount,
+        to_currency: row.get(11)?,
+        fee: Money::from_minor(row.get(13)?, scale),
+        exchange_rate: row.get(14)?,
+        splits: Vec::new(),
+        recurring_rule_id: row.get(17)?,
+    })
+}
+
+// Fill in the tags of every transaction, a batch of transactions per query
+pub(crate) fn load_tags(conn: &Connection, transactions: &mut [Transaction]) -> BackendResult<()> {
+    let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+    for batch in transactions.chunks(TAG_BATCH_SIZE) {
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT tt.transaction_id, t.name
+                 FROM transaction_tags tt
+                 JOIN tags t ON t.id = tt.tag_id
+                 WHERE tt.transaction_id IN ({})",
+                vec!["?"; batch.len()].join(", ")
+            ))
+            .context("Failed to prepare statement")?;
+        let rows = stmt
+            .query_map(params_from_iter(batch.iter().map(|t| t.id)), |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })
+            .context("Failed to query tags")?;
+        for row in rows {
+            let (transaction_id, name) = row.context("Failed to parse tag row")?;
+            tags.entry(transaction_id).or_default().push(name);
         }
     }
 
-    Ok(tags)
+    for transaction in transactions {
+        transaction.tags = tags.remove(&transaction.id).unwrap_or_default();
+    }
+    Ok(())
+}
+
+// Transaction count and sums per currency over everything the conditions match
+fn transaction_totals(
+    conn: &Connection,
+    conditions: &[String],
+    values: &[Value],
+) -> BackendResult<Vec<TransactionTotals>> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT t.currency, cur.minor_unit, COUNT(*),
+                 COALESCE(SUM(CASE WHEN t.type = 'income' THEN t.amount END), 0),
+                 COALESCE(SUM(CASE WHEN t.type = 'expense' THEN t.amount END), 0),
+                 COALESCE(SUM(CASE WHEN t.type = 'transfer' THEN t.amount + t.fee END), 0)
+             {}
+             WHERE {}
+             GROUP BY t.currency
+             ORDER BY t.currency",
+            TRANSACTION_FROM,
+            where_clause(conditions)
+        ))
+        .context("Failed to prepare statement")?;
+
+    let totals_iter = stmt
+        .query_map(params_from_iter(values), |row| {
+            let scale: u32 = row.get(1)?;
+            Ok(TransactionTotals {
+                currency: row.get(0)?,
+                count: row.get(2)?,
+                income: Money::from_minor(row.get(3)?, scale),
+                expense: Money::from_minor(row.get(4)?, scale),
+                transfers: Money::from_minor(row.get(5)?, scale),
+            })
+        })
+        .context("Failed to query transaction totals")?;
+
+    let mut totals = Vec::new();
+    for total in totals_iter {
+        totals.push(total.context("Failed to parse transaction totals row")?);
+    }
+
+    Ok(totals)
+}
+
+// SQL conditions on `TRANSACTION_FROM` for the filter, with the values they bind in order
+fn filter_conditions(filter: &TransactionFilter) -> BackendResult<(Vec<String>, Vec<Value>)> {
+    let mut conditions = Vec::new();
+    let mut values = Vec::new();
+
+    if let Some(ledger_id) = filter.ledger_id {
+        conditions.push("t.ledger_id = ?".to_string());
+        values.push(Value::Integer(ledger_id));
+    }
+    if let Some(account_id) = filter.account_id {
+        conditions.push("(t.account_id = ? OR t.to_account_id = ?)".to_string());
+        values.extend([Value::Integer(account_id), Value::Integer(account_id)]);
+    }
+
+    for (field, date, comparison) in [("from", &filter.from, ">="), ("to", &filter.to, "<=")] {
+        let Some(date) = date else { continue };
+        if !is_iso_date(date) {
+            return Err(BackendError::validation(
+                field,
+                format!("'{}' is not a YYYY-MM-DD date", date),
+            ));
+        }
+        conditions.push(format!("substr(t.date, 1, 10) {} ?", comparison));
+        values.push(Value::Text(date.clone()));
+    }
+
+    if let Some(transaction_type) = &filter.transaction_type {
+        if !matches!(transaction_type.as_str(), "expense" | "income" | "transfer") {
+            return Err(BackendError::validation(
+                "transaction_type",
+                format!("Unsupported transaction type '{}'", transaction_type),
+            ));
+        }
+        conditions.push("t.type = ?".to_string());
+        values.push(Value::Text(transaction_type.clone()));
+    }
+
+    // amount / 10^minor_unit against minor / 10^scale, cross-multiplied to stay in integers.
+    // The substr yields 10^minor_unit, as minor units never exceed 8.
+    for (amount, comparison) in [(filter.min_amount, ">="), (filter.max_amount, "<=")] {
+        let Some(amount) = amount else { continue };
+        conditions.push(format!(
+            "t.amount * ? {} ? * CAST(substr('100000000', 1, cur.minor_unit + 1) AS INTEGER)",
+            comparison
+        ));
+        values.extend([
+            Value::Integer(10i64.pow(amount.scale())),
+            Value::Integer(amount.minor()),
+        ]);
+    }
+
+    if let Some(category_id) = filter.category_id {
+        conditions.push(
+            "t.id IN (
+                 WITH RECURSIVE subtree(id) AS (
+                     SELECT ?
+                     UNION ALL
+                     SELECT c.id FROM categories c JOIN subtree s ON c.parent_id = s.id
+                 )
+                 SELECT l.transaction_id FROM transaction_lines l
+                 WHERE l.category_id IN (SELECT id FROM subtree))"
+                .to_string(),
+        );
+        values.push(Value::Integer(category_id));
+    }
+
+    for tag in &filter.tags {
+        conditions.push(
+            "EXISTS (SELECT 1 FROM tags tg
+                     WHERE tg.name = ? AND (
+                         tg.id IN (SELECT tag_id FROM transaction_tags
+                                   WHERE transaction_id = t.id)
+                         OR tg.id IN (SELECT st.tag_id FROM split_tags st
+                                      JOIN transaction_splits s ON s.id = st.split_id
+                                      WHERE s.transaction_id = t.id)))"
+                .to_string(),
+        );
+        values.push(Value::Text(tag.clone()));
+    }
+
+    Ok((conditions, values))
+}
+
+fn where_clause(conditions: &[String]) -> String {
+    if conditions.is_empty() {
+        "TRUE".to_string()
+    } else {
+        conditions.join(" AND ")
+    }
+}
+
+// Sort keys never evaluate to NULL, so the cursor comparison needs no NULL handling
+fn sort_expression(sort_by: &str) -> BackendResult<&'static str> {
+    Ok(match sort_by {
+        "date" => "t.date",
+        "amount" => "t.amount",
+        "type" => "t.type",
+        "account" => "a.name",
+        "category" => "COALESCE(c.name, '')",
+        "currency" => "t.currency",
+        "note" => "COALESCE(t.note, '')",
+        "id" => "t.id",
+        _ => {
+            return Err(BackendError::validation(
+                "sort_by",
+                format!("Cannot sort transactions by '{}'", sort_by),
+            ))
+        }
+    })
+}
+
+// A cursor is the JSON array [sort_by, sort value, id] of the last row on a page
+fn encode_cursor(sort_by: &str, value: &Value, id: i64) -> BackendResult<String> {
+    let value = match value {
+        Value::Integer(n) => serde_json::Value::from(*n),
+        Value::Real(n) => serde_json::Value::from(*n),
+        Value::Text(text) => serde_json::Value::from(text.as_str()),
+        _ => serde_json::Value::Null,
+    };
+    serde_json::to_string(&(sort_by, value, id))
+        .map_err(|err| BackendError::database(format!("Failed to encode cursor: {}", err)))
+}
+
+fn decode_cursor(cursor: &str, sort_by: &str) -> BackendResult<(Value, i64)> {
+    let invalid = || BackendError::validation("cursor", "The cursor is not from this query");
+    let (cursor_sort, value, id): (String, serde_json::Value, i64) =
+        serde_json::from_str(cursor).map_err(|_| invalid())?;
+    if cursor_sort != sort_by {
+        return Err(invalid());
+    }
+
+    let value = match value {
+        serde_json::Value::String(text) => Value::Text(text),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(n) => Value::Integer(n),
+            None => Value::Real(n.as_f64().ok_or_else(invalid)?),
+        },
+        _ => return Err(invalid()),
+    };
+    Ok((value, id))
 }
 
 #[tauri::command(rename_all = "snake_case")]
+#[allow(clippy::too_many_arguments)]
 pub fn update_transaction(
+    db: State<'_, Database>,
     id: i64,
     ledger_id: i64,
     account_id: i64,
-    amount: f64,
+    transaction_type: &str,   // 'expense', 'income'
+    category_id: Option<i64>, // any node of the ledger's category tree
+    amount: Money,
     currency: &str,
     date: &str,
     tags: Vec<String>,
     note: Option<&str>,
-) -> Result<(), String> {
-    let conn = get_connection();
+) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
 
-    conn.execute(
-        "UPDATE transactions SET ledger_id = ?1, account_id = ?2, amount = ?3, currency = ?4, date = ?5, note = ?6 WHERE id = ?7",
-        params![ledger_id, account_id, amount, currency, date, note, id],
-    ).map_err(|err| format!("Failed to update transaction: {}", err))?;
+    ensure_not_invest_event(&tx, id)?;
+    validate_date("date", date)?;
+    let amount = validate_transaction(&tx, account_id, transaction_type, amount, currency)?;
+    category::validate_transaction_category(&tx, ledger_id, transaction_type, category_id)?;
+    validate_existing_splits(&tx, id, ledger_id, transaction_type, amount)?;
 
-    conn.execute(
-        "DELETE FROM transaction_tags WHERE transaction_id = ?1",
-        params![id],
-    )
-    .map_err(|err| format!("Failed to clear existing tags: {}", err))?;
+    // Take the old effects off before the row changes, then apply the new ones
+    balance::apply_transaction_effects(&tx, id, -1).context("Failed to update account balance")?;
 
-    for tag in tags {
-        let tag_id = insert_or_get_tag(&conn, &tag)?;
-        conn.execute(
-            "INSERT INTO transaction_tags (transaction_id, tag_id) VALUES (?1, ?2)",
-            params![id, tag_id],
-        )
-        .map_err(|err| format!("Failed to link tag to transaction: {}", err))?;
+    // Turning a transfer into a plain transaction drops its destination side
+    let updated = tx.execute(
+        "UPDATE transactions SET ledger_id = ?1, account_id = ?2, type = ?3, amount = ?4, currency = ?5, date = ?6, note = ?7,
+            category_id = ?8,
+            to_account_id = NULL, to_amount = NULL, to_currency = NULL, fee = 0, exchange_rate = NULL
+         WHERE id = ?9",
+        params![ledger_id, account_id, transaction_type, amount.minor(), currency, date, note, category_id, id],
+    ).context("Failed to update transaction")?;
+    if updated == 0 {
+        return Err(BackendError::not_found(format!(
+            "Transaction {} not found",
+            id
+        )));
     }
 
+    balance::apply_transaction_effects(&tx, id, 1).context("Failed to update account balance")?;
+
+    replace_tags(&tx, id, &tags)?;
+
+    tx.commit().context("Failed to commit transaction")?;
     Ok(())
 }
 
 #[tauri::command(rename_all = "snake_case")]
-pub fn delete_transaction(id: i64) -> Result<(), String> {
-    let conn = get_connection();
+pub fn delete_transaction(db: State<'_, Database>, id: i64) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
 
-    conn.execute(
-        "DELETE FROM transaction_tags WHERE transaction_id = ?1",
-        params![id],
-    )
-    .map_err(|err| format!("Failed to delete transaction tags: {}", err))?;
+    ensure_not_invest_event(&tx, id)?;
+    balance::apply_transaction_effects(&tx, id, -1).context("Failed to update account balance")?;
 
-    conn.execute("DELETE FROM transactions WHERE id = ?1", params![id])
-        .map_err(|err| format!("Failed to delete transaction: {}", err))?;
+    // transaction_tags and transaction_attachments rows cascade with the transaction, and a
+    // transfer's destination side lives on the same row, so both sides go together
+    let deleted = tx
+        .execute("DELETE FROM transactions WHERE id = ?1", params![id])
+        .context("Failed to delete transaction")?;
+    if deleted == 0 {
+        return Err(BackendError::not_found(format!(
+            "Transaction {} not found",
+            id
+        )));
+    }
+    let orphans = attachment::take_orphans(&tx)?;
 
+    tx.commit().context("Failed to commit transaction")?;
+    attachment::remove_files(&db, &orphans)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::testing::{money, Fixture};
+
+    // Ids in page order, fetching `limit` at a time
+    fn page_through(
+        fixture: &Fixture,
+        filter: impl Fn() -> TransactionFilter,
+        sort_by: &str,
+        descending: bool,
+        limit: u32,
+    ) -> Vec<i64> {
+        let mut ids = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = query_transactions(
+                fixture.db(),
+                filter(),
+                Some(sort_by),
+                Some(descending),
+                cursor.as_deref(),
+                Some(limit),
+            )
+            .unwrap();
+            assert!(page.transactions.len() <= limit as usize);
+            ids.extend(page.transactions.iter().map(|t| t.id));
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                return ids;
+            }
+        }
+    }
+
+    #[test]
+    fn pages_follow_the_sort_with_ties_broken_by_id() {
+        let fixture = Fixture::new();
+        let ledger = fixture.ledger("USD");
+        let bank = fixture.account("bank", "debit", "USD", "0");
+        // Repeated amounts, dates and notes, some notes missing, so every sort has ties
+        for i in 0..11 {
+            create_transaction(
+                fixture.db(),
+                ledger,
+                bank,
+                if i % 4 == 0 { "income" } else { "expense" },
+                None,
+                money(&format!("{}", i % 3 + 1)),
+                "USD",
+                &format!("2024-01-{:02}", i % 5 + 1),
+                if i % 2 == 0 {
+                    vec!["x".to_string()]
+                } else {
+                    vec![]
+                },
+                (i % 3 != 0).then_some(if i % 2 == 0 { "a" } else { "b" }),
+            )
+            .unwrap();
+        }
+
+        for sort_by in ["date", "amount", "type", "note", "id"] {
+            for descending in [false, true] {
+                let whole = page_through(
+                    &fixture,
+                    TransactionFilter::default,
+                    sort_by,
+                    descending,
+                    100,
+                );
+                assert_eq!(whole.len(), 11);
+                for limit in [1, 3, 4] {
+                    let paged = page_through(
+                        &fixture,
+                        TransactionFilter::default,
+                        sort_by,
+                        descending,
+                        limit,
+                    );
+                    assert_eq!(
+                        paged, whole,
+                        "sorted by {} with {} per page",
+                        sort_by, limit
+                    );
+                }
+            }
+        }
+
+        // Totals cover every page, not just the first
+        let tagged = || TransactionFilter {
+            tags: vec!["x".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(page_through(&fixture, tagged, "date", true, 2).len(), 6);
+        let page = query_transactions(fixture.db(), tagged(), None, None, None, Some(2)).unwrap();
+        assert_eq!(page.total_count, 6);
+        assert_eq!(page.totals[0].count, 6);
+    }
+
+    #[test]
+    fn a_bad_sort_or_cursor_is_rejected() {
+        let fixture = Fixture::new();
+        let query = |sort_by, cursor| {
+            query_transactions(
+                fixture.db(),
+                TransactionFilter::default(),
+                Some(sort_by),
+                None,
+                cursor,
+                None,
+            )
+        };
+        assert!(query("date", None).is_ok());
+        assert!(query("bogus", None).is_err());
+        assert!(query("id", Some("junk")).is_err());
+    }
+}
@@ -0,0 +1,356 @@
+use crate::backend::balance;
+use crate::backend::date::validate_date;
+use crate::backend::db::Database;
+use crate::backend::error::{BackendError, BackendResult, Context};
+use crate::backend::invest_event::ensure_not_invest_event;
+use crate::backend::money::Money;
+use crate::backend::split::validate_existing_splits;
+use crate::backend::transaction::{account_currency, link_tags, replace_tags, to_currency_scale};
+use rusqlite::{params, Connection};
+use tauri::State;
+
+// A transfer is a single `transactions` row of type 'transfer': the source side in `account_id`,
+// `amount` and `currency`, the destination side in `to_account_id`, `to_amount` and
+// `to_currency`. Keeping both sides on one row means they can only be written or deleted together.
+
+// Validated amounts of a transfer, each in its own currency's minor units
+pub(crate) struct TransferAmounts {
+    pub currency: String,
+    pub amount: Money,
+    pub to_currency: String,
+    pub to_amount: Money,
+    pub fee: Money,
+    pub exchange_rate: Option<f64>,
+}
+
+pub(crate) fn validate_transfer(
+    conn: &Connection,
+    from_account_id: i64,
+    to_account_id: i64,
+    amount: Money,
+    to_amount: Option<Money>,
+    fee: Option<Money>,
+) -> BackendResult<TransferAmounts> {
+    if from_account_id == to_account_id {
+        return Err(BackendError::validation(
+            "to_account_id",
+            "A transfer needs two different accounts",
+        ));
+    }
+
+    let currency = account_currency(conn, from_account_id, "from_account_id")?;
+    let to_currency = account_currency(conn, to_account_id, "to_account_id")?;
+
+    let amount = to_currency_scale(conn, amount, &currency, "amount")?;
+    if amount.is_negative() || amount.is_zero() {
+        return Err(BackendError::validation(
+            "amount",
+            "Transfer amount must be positive",
+        ));
+    }
+
+    // The fee is charged by the source account on top of the amount sent
+    let fee = to_currency_scale(conn, fee.unwrap_or_default(), &currency, "fee")?;
+    if fee.is_negative() {
+        return Err(BackendError::validation("fee", "Fee must not be negative"));
+    }
+
+    if currency == to_currency {
+        let to_amount = match to_amount {
+            Some(to_amount) => to_currency_scale(conn, to_amount, &to_currency, "to_amount")?,
+            None => amount,
+        };
+        if to_amount != amount {
+            return Err(BackendError::validation(
+                "to_amount",
+                "A transfer within one currency must arrive in full; record charges as a fee",
+            ));
+        }
+        return Ok(TransferAmounts {
+            currency,
+            amount,
+            to_currency,
+            to_amount,
+            fee,
+            exchange_rate: None,
+        });
+    }
+
+    let to_amount = to_amount.ok_or_else(|| {
+        BackendError::validation(
+            "to_amount",
+            format!(
+                "A transfer from {} to {} needs the amount received",
+                currency, to_currency
+            ),
+        )
+    })?;
+    let to_amount = to_currency_scale(conn, to_amount, &to_currency, "to_amount")?;
+    if to_amount.is_negative() || to_amount.is_zero() {
+        return Err(BackendError::validation(
+            "to_amount",
+            "Amount received must be positive",
+        ));
+    }
+
+    Ok(TransferAmounts {
+        exchange_rate: Some(implied_rate(amount, to_amount)),
+        currency,
+        amount,
+        to_currency,
+        to_amount,
+        fee,
+    })
+}
+
+// Units received per unit sent, e.g. 1.0850 for 100.00 EUR arriving as 108.50 USD
+fn implied_rate(amount: Money, to_amount: Money) -> f64 {
+    let major = |m: Money| m.minor() as f64 / 10f64.powi(m.scale() as i32);
+    major(to_amount) / major(amount)
+}
+
+#[tauri::command(rename_all = "snake_case")]
+#[allow(clippy::too_many_arguments)]
+pub fn create_transfer(
+    db: State<'_, Database>,
+    ledger_id: i64,
+    from_account_id: i64,
+    to_account_id: i64,
+    amount: Money,
+    to_amount: Option<Money>, // required when the accounts use different currencies
+    fee: Option<Money>,
+    date: &str,
+    tags: Vec<String>,
+    note: Option<&str>,
+) -> BackendResult<i64> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    validate_date("date", date)?;
+    let t = validate_transfer(&tx, from_account_id, to_account_id, amount, to_amount, fee)?;
+
+    tx.execute(
+        "INSERT INTO transactions (ledger_id, account_id, type, amount, currency, date, note,
+             to_account_id, to_amount, to_currency, fee, exchange_rate)
+         VALUES (?1, ?2, 'transfer', ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            ledger_id,
+            from_account_id,
+            t.amount.minor(),
+            t.currency,
+            date,
+            note,
+            to_account_id,
+            t.to_amount.minor(),
+            t.to_currency,
+            t.fee.minor(),
+            t.exchange_rate
+        ],
+    )
+    .context("Failed to insert transfer")?;
+
+    let transaction_id = tx.last_insert_rowid();
+
+    balance::apply_transaction_effects(&tx, transaction_id, 1)
+        .context("Failed to update account balances")?;
+    link_tags(&tx, transaction_id, &tags)?;
+
+    tx.commit().context("Failed to commit transfer")?;
+    Ok(transaction_id)
+}
+
+// Also turns an income or expense into a transfer. Delete with `delete_transaction`.
+#[tauri::command(rename_all = "snake_case")]
+#[allow(clippy::too_many_arguments)]
+pub fn update_transfer(
+    db: State<'_, Database>,
+    id: i64,
+    ledger_id: i64,
+    from_account_id: i64,
+    to_account_id: i64,
+    amount: Money,
+    to_amount: Option<Money>,
+    fee: Option<Money>,
+    date: &str,
+    tags: Vec<String>,
+    note: Option<&str>,
+) -> BackendResult<()> {
+    let mut conn = db.writer();
+    let tx = conn.transaction().context("Failed to start transaction")?;
+
+    ensure_not_invest_event(&tx, id)?;
+    validate_date("date", date)?;
+    let t = validate_transfer(&tx, from_account_id, to_account_id, amount, to_amount, fee)?;
+    validate_existing_splits(&tx, id, ledger_id, "transfer", t.amount)?;
+
+    // Take both sides' old effects off before the row changes, then apply the new ones
+    balance::apply_transaction_effects(&tx, id, -1).context("Failed to update account balances")?;
+
+    let updated = tx
+        .execute(
+            "UPDATE transactions SET ledger_id = ?1, account_id = ?2, type = 'transfer',
+                 amount = ?3, currency = ?4, date = ?5, note = ?6, to_account_id = ?7,
+                 to_amount = ?8, to_currency = ?9, fee = ?10, exchange_rate = ?11,
+                 -- an income or expense category no longer fits once this is a transfer
+                 category_id = CASE WHEN type = 'transfer' THEN category_id END
+             WHERE id = ?12",
+            params![
+                ledger_id,
+                from_account_id,
+                t.amount.minor(),
+                t.currency,
+                date,
+                note,
+                to_account_id,
+                t.to_amount.minor(),
+                t.to_currency,
+                t.fee.minor(),
+                t.exchange_rate,
+                id
+            ],
+        )
+        .context("Failed to update transfer")?;
+    if updated == 0 {
+        return Err(BackendError::not_found(format!(
+            "Transaction {} not found",
+            id
+        )));
+    }
+
+    balance::apply_transaction_effects(&tx, id, 1).context("Failed to update account balances")?;
+    replace_tags(&tx, id, &tags)?;
+
+    tx.commit().context("Failed to commit transfer")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::testing::{money, Fixture};
+    use crate::backend::transaction::delete_transaction;
+
+    // Balance in minor units
+    fn balance(fixture: &Fixture, account_id: i64) -> i64 {
+        fixture.query("SELECT balance FROM accounts WHERE id = ?1", [account_id])
+    }
+
+    #[test]
+    fn both_sides_move_together_and_the_fee_stays_with_the_source() {
+        let fixture = Fixture::new();
+        let ledger = fixture.ledger("USD");
+        let bank = fixture.account("bank", "debit", "USD", "100");
+        let savings = fixture.account("savings", "debit", "USD", "0");
+        let yen = fixture.account("yen", "debit", "JPY", "0");
+
+        let id = create_transfer(
+            fixture.db(),
+            ledger,
+            bank,
+            savings,
+            money("30"),
+            None,
+            Some(money("1.5")),
+            "2024-01-01",
+            vec![],
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            (balance(&fixture, bank), balance(&fixture, savings)),
+            (6850, 3000)
+        );
+
+        // Within one currency the whole amount must arrive
+        let err = update_transfer(
+            fixture.db(),
+            id,
+            ledger,
+            bank,
+            savings,
+            money("30"),
+            Some(money("29")),
+            None,
+            "2024-01-01",
+            vec![],
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(err.field(), Some("to_amount"));
+
+        // Across currencies the amount received is required and sets the rate
+        let err = update_transfer(
+            fixture.db(),
+            id,
+            ledger,
+            bank,
+            yen,
+            money("10"),
+            None,
+            None,
+            "2024-01-01",
+            vec![],
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(err.field(), Some("to_amount"));
+        update_transfer(
+            fixture.db(),
+            id,
+            ledger,
+            bank,
+            yen,
+            money("10"),
+            Some(money("1500")),
+            None,
+            "2024-01-01",
+            vec![],
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            (
+                balance(&fixture, bank),
+                balance(&fixture, savings),
+                balance(&fixture, yen)
+            ),
+            (9000, 0, 1500)
+        );
+        let rate: f64 = fixture.query("SELECT exchange_rate FROM transactions WHERE id = ?1", [id]);
+        assert_eq!(rate, 150.0);
+
+        delete_transaction(fixture.db(), id).unwrap();
+        assert_eq!(
+            (balance(&fixture, bank), balance(&fixture, yen)),
+            (10000, 0)
+        );
+    }
+
+    #[test]
+    fn a_transfer_needs_two_accounts_and_a_positive_amount() {
+        let fixture = Fixture::new();
+        let ledger = fixture.ledger("USD");
+        let bank = fixture.account("bank", "debit", "USD", "100");
+        let savings = fixture.account("savings", "debit", "USD", "0");
+        let transfer = |from, amount, fee: Option<&str>| {
+            create_transfer(
+                fixture.db(),
+                ledger,
+                from,
+                savings,
+                money(amount),
+                None,
+                fee.map(money),
+                "2024-01-01",
+                vec![],
+                None,
+            )
+            .unwrap_err()
+        };
+
+        assert_eq!(transfer(savings, "10", None).field(), Some("to_account_id"));
+        assert_eq!(transfer(bank, "0", None).field(), Some("amount"));
+        assert_eq!(transfer(bank, "10", Some("-1")).field(), Some("fee"));
+        assert_eq!(balance(&fixture, bank), 10000);
+    }
+}
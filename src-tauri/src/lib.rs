@@ -1,39 +1,47 @@
 pub mod backend {
     pub mod account;
+    pub mod attachment;
+    pub mod balance;
     pub mod category;
+    pub mod credit;
     pub mod currency;
+    pub mod date;
     pub mod db;
+    pub mod error;
+    pub mod exchange_rate;
+    pub mod format;
+    pub mod invest;
+    pub mod invest_event;
     pub mod ledger;
+    pub mod money;
+    pub mod net_worth;
+    pub mod recurring;
+    pub mod search;
+    pub mod security;
+    pub mod settings;
+    pub mod snapshot;
+    pub mod split;
+    pub mod statement;
     pub mod tag;
+    #[cfg(test)]
+    pub(crate) mod testing;
     pub mod transaction;
+    pub mod transfer;
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    use tauri::Manager;
+
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
-        .setup(|_app| {
-            if let Err(e) = backend::db::init_db() {
-                eprintln!("Error setting up the database: {}", e);
-            }
-            if let Err(e) = backend::currency::create_currencies_table() {
-                eprintln!("Error creating currencies table: {}", e);
-            }
-            if let Err(e) = backend::account::create_accounts_table() {
-                eprintln!("Error creating accounts table: {}", e);
-            }
-            if let Err(e) = backend::ledger::create_ledgers_table() {
-                eprintln!("Error creating ledgers table: {}", e);
-            }
-            if let Err(e) = backend::transaction::create_transactions_table() {
-                eprintln!("Error creating transactions table: {}", e);
-            }
-            if let Err(e) = backend::category::create_categories_table() {
-                eprintln!("Error creating categories table: {}", e);
-            }
-            if let Err(e) = backend::tag::create_tags_table() {
-                eprintln!("Error creating tag table: {}", e);
-            }
+        .plugin(tauri_plugin_store::Builder::default().build())
+        .setup(|app| {
+            let db = backend::db::init_db(app.handle())?;
+            backend::recurring::record_due(&db)?;
+            backend::attachment::collect_garbage(&db)?;
+            app.manage(db);
+            backend::credit::start_reminders(app.handle().clone());
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
@@ -41,6 +49,91 @@ pub fn run() {
             backend::account::read_accounts,
             backend::account::delete_account,
             backend::account::update_account,
+            backend::attachment::add_attachment,
+            backend::attachment::get_attachments,
+            backend::attachment::remove_attachment,
+            backend::balance::recompute_account_balance,
+            backend::category::insert_category,
+            backend::category::get_categories_for_ledger,
+            backend::category::update_category,
+            backend::category::delete_category,
+            backend::category::move_category,
+            backend::category::merge_category,
+            backend::category::reorder_categories,
+            backend::category::get_category_totals,
+            backend::credit::get_credit_utilisation,
+            backend::credit::get_payment_reminders,
+            backend::credit::get_credit_reminders,
+            backend::currency::get_currencies,
+            backend::currency::create_currency,
+            backend::currency::update_currency,
+            backend::currency::delete_currency,
+            backend::db::get_database_info,
+            backend::exchange_rate::add_exchange_rate,
+            backend::exchange_rate::get_exchange_rates,
+            backend::exchange_rate::update_exchange_rate,
+            backend::exchange_rate::delete_exchange_rate,
+            backend::exchange_rate::import_exchange_rates,
+            backend::exchange_rate::convert_amount,
+            backend::format::format_amount,
+            backend::format::parse_amount,
+            backend::format::update_currency_format,
+            backend::invest::add_holding,
+            backend::invest::get_holdings,
+            backend::invest::delete_holding,
+            backend::invest::set_cost_method,
+            backend::invest::add_invest_trade,
+            backend::invest::get_invest_trades,
+            backend::invest::update_invest_trade,
+            backend::invest::delete_invest_trade,
+            backend::invest::get_invest_lots,
+            backend::invest::get_invest_valuation,
+            backend::invest_event::add_invest_event,
+            backend::invest_event::get_invest_events,
+            backend::invest_event::update_invest_event,
+            backend::invest_event::delete_invest_event,
+            backend::invest_event::get_invest_income,
+            backend::ledger::create_ledger,
+            backend::ledger::get_ledgers,
+            backend::ledger::get_ledger,
+            backend::ledger::update_ledger,
+            backend::ledger::delete_ledger,
+            backend::net_worth::get_net_worth,
+            backend::recurring::create_recurring_rule,
+            backend::recurring::get_recurring_rules,
+            backend::recurring::update_recurring_rule,
+            backend::recurring::delete_recurring_rule,
+            backend::recurring::get_recurring_occurrences,
+            backend::recurring::skip_recurring_occurrence,
+            backend::recurring::edit_recurring_occurrence,
+            backend::recurring::end_recurring_rule,
+            backend::recurring::generate_recurring_transactions,
+            backend::search::search_transactions,
+            backend::security::create_security,
+            backend::security::get_securities,
+            backend::security::update_security,
+            backend::security::delete_security,
+            backend::security::add_security_price,
+            backend::security::get_security_prices,
+            backend::security::delete_security_price,
+            backend::security::import_security_prices,
+            backend::snapshot::capture_balance_snapshot,
+            backend::snapshot::get_net_worth_history,
+            backend::split::set_transaction_splits,
+            backend::split::get_transaction_splits,
+            backend::statement::get_credit_statements,
+            backend::statement::mark_statement_paid,
+            backend::tag::create_tag,
+            backend::tag::get_tags,
+            backend::tag::update_tag,
+            backend::tag::delete_tag,
+            backend::transaction::create_transaction,
+            backend::transaction::read_transactions,
+            backend::transaction::query_transactions,
+            backend::transaction::update_transaction,
+            backend::transaction::delete_transaction,
+            backend::transfer::create_transfer,
+            backend::transfer::update_transfer,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");